@@ -0,0 +1,27 @@
+//! Times the benchmark programs in `benches/lox/` and the scanner, parser
+//! and evaluator micro-benchmarks, printing a table.
+//!
+//! Run with `cargo bench --features bench`. `rslox bench`, built with the
+//! same feature, runs the same benchmarks and prints JSON instead, for
+//! comparing versions.
+
+use rslox::bench;
+
+const ITERATIONS: usize = 10;
+
+fn main() {
+    println!(
+        "{:<16} {:>10} {:>10} {:>10}",
+        "benchmark", "min", "median", "mean"
+    );
+    for benchmark in bench::benchmarks() {
+        let timing = bench::time(&benchmark, ITERATIONS);
+        println!(
+            "{:<16} {:>10.2?} {:>10.2?} {:>10.2?}",
+            timing.name,
+            timing.min(),
+            timing.median(),
+            timing.mean()
+        );
+    }
+}
@@ -0,0 +1,29 @@
+//! Running any program either finishes or reports a runtime error.
+//!
+//! Lox programs can loop forever, so timeouts are uninteresting, and
+//! `fuzz/run.sh` skips them.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rslox::interpreter::Interpreter;
+use rslox::parser::Parser;
+use rslox::scanner::Scanner;
+use std::io;
+
+fuzz_target!(|source: &str| {
+    let mut scanner = Scanner::new(source.to_string());
+    let Ok(tokens) = scanner.scan_tokens() else {
+        return;
+    };
+    let Ok(program) = Parser::new(tokens).parse() else {
+        return;
+    };
+
+    // No capabilities, so programs can't read or write files, import
+    // modules or read the environment.
+    let mut interpreter = Interpreter::with_output(Box::new(io::sink()));
+    interpreter.set_input(Box::new(io::empty()));
+    interpreter.seed_random(0);
+    let _ = interpreter.interpret(&program);
+});
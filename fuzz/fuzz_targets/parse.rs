@@ -0,0 +1,27 @@
+//! Parsing any tokens either produces a syntax tree or reports errors, and
+//! the tree prints as source that parses back to the same tree.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rslox::parser::Parser;
+use rslox::printer;
+use rslox::scanner::Scanner;
+
+fuzz_target!(|source: &str| {
+    let mut scanner = Scanner::new(source.to_string());
+    let Ok(tokens) = scanner.scan_tokens() else {
+        return;
+    };
+    let Ok(program) = Parser::new(tokens).parse() else {
+        return;
+    };
+
+    let printed = printer::print(&program);
+    let mut scanner = Scanner::new(printed.clone());
+    let tokens = scanner.scan_tokens().expect("printed program scans");
+    let reparsed = Parser::new(tokens)
+        .parse()
+        .unwrap_or_else(|errors| panic!("printed program doesn't parse: {:?}", errors));
+    assert_eq!(program.to_string(), reparsed.to_string(), "\n{}", printed);
+});
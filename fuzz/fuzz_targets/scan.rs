@@ -0,0 +1,10 @@
+//! Scanning any text either produces tokens or reports an error.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rslox::scanner::Scanner;
+
+fuzz_target!(|source: &str| {
+    let _ = Scanner::new(source.to_string()).scan_tokens();
+});
@@ -0,0 +1,18 @@
+#!/bin/sh
+# Fuzzes one target, starting from the Lox programs under tests/lox/.
+#
+#     fuzz/run.sh scan|parse|interpret [libFuzzer options]
+#
+# New inputs are saved to fuzz/corpus/<target>/. libFuzzer only writes to
+# the first corpus directory it is given, so tests/lox/ is read but never
+# changed.
+set -eu
+
+target=${1:?usage: fuzz/run.sh scan|parse|interpret [libFuzzer options]}
+shift
+cd "$(dirname "$0")/.."
+mkdir -p "fuzz/corpus/$target"
+# Lox programs can loop forever, and a few under tests/lox/ do, so inputs
+# that time out are cut short and skipped, which needs fork mode.
+exec cargo fuzz run "$target" "fuzz/corpus/$target" tests/lox -- \
+    -timeout=5 -fork=1 -ignore_timeouts=1 "$@"
@@ -0,0 +1,231 @@
+//! The syntax tree of a program, held in an arena.
+//!
+//! Nodes refer to their children by `ExprId` and `StmtId` indices into the
+//! arena rather than owning them. Data about nodes that isn't part of the
+//! tree, such as their spans, lives in side tables indexed the same way, so
+//! tools can attach their own without changing `Expr` or `Stmt`.
+
+use crate::expr::Expr;
+use crate::stmt::Stmt;
+use crate::token::Span;
+use std::fmt;
+use std::marker::PhantomData;
+use std::ops::Index;
+use std::rc::Rc;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ExprId(u32);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct StmtId(u32);
+
+/// An index of a node in an `Ast`.
+pub trait NodeId: Copy {
+    fn index(self) -> usize;
+}
+
+impl NodeId for ExprId {
+    fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+impl NodeId for StmtId {
+    fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Ast {
+    exprs: Vec<Expr>,
+    stmts: Vec<Stmt>,
+    expr_spans: Vec<Span>,
+    stmt_spans: Vec<Span>,
+}
+
+impl Ast {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_expr(&mut self, expr: Expr, span: Span) -> ExprId {
+        let id = ExprId(self.exprs.len() as u32);
+        self.exprs.push(expr);
+        self.expr_spans.push(span);
+        id
+    }
+
+    pub fn add_stmt(&mut self, stmt: Stmt, span: Span) -> StmtId {
+        let id = StmtId(self.stmts.len() as u32);
+        self.stmts.push(stmt);
+        self.stmt_spans.push(span);
+        id
+    }
+
+    /// Puts `expr` in the place of the node `id`, which keeps its children.
+    /// The parser uses this to turn an expression it has already parsed into
+    /// an assignment to it.
+    pub(crate) fn replace_expr(&mut self, id: ExprId, expr: Expr, span: Span) {
+        self.exprs[id.index()] = expr;
+        self.expr_spans[id.index()] = span;
+    }
+
+    /// The source text an expression was parsed from.
+    pub fn expr_span(&self, id: ExprId) -> Span {
+        self.expr_spans[id.index()]
+    }
+
+    /// The source text a statement was parsed from. Statements desugared
+    /// from a `for` loop share the loop's span.
+    pub fn stmt_span(&self, id: StmtId) -> Span {
+        self.stmt_spans[id.index()]
+    }
+
+    pub fn expr_count(&self) -> usize {
+        self.exprs.len()
+    }
+
+    pub fn stmt_count(&self) -> usize {
+        self.stmts.len()
+    }
+
+    /// Displays the subtree at `id` in the prefix form of `Expr` and `Stmt`.
+    pub fn tree<Id: NodeId>(&self, id: Id) -> Tree<'_, Id> {
+        Tree { ast: self, id }
+    }
+}
+
+impl Index<ExprId> for Ast {
+    type Output = Expr;
+
+    fn index(&self, id: ExprId) -> &Expr {
+        &self.exprs[id.index()]
+    }
+}
+
+impl Index<StmtId> for Ast {
+    type Output = Stmt;
+
+    fn index(&self, id: StmtId) -> &Stmt {
+        &self.stmts[id.index()]
+    }
+}
+
+/// A node together with the arena it lives in, for printing.
+#[derive(Clone, Copy)]
+pub struct Tree<'a, Id> {
+    pub ast: &'a Ast,
+    pub id: Id,
+}
+
+/// Values attached to some of the nodes of one kind in an `Ast`.
+#[derive(Debug)]
+pub struct SideTable<Id, T> {
+    values: Vec<Option<T>>,
+    id: PhantomData<Id>,
+}
+
+impl<Id: NodeId, T> SideTable<Id, T> {
+    pub fn new() -> Self {
+        Self {
+            values: Vec::new(),
+            id: PhantomData,
+        }
+    }
+
+    pub fn insert(&mut self, id: Id, value: T) {
+        let index = id.index();
+        if index >= self.values.len() {
+            self.values.resize_with(index + 1, || None);
+        }
+        self.values[index] = Some(value);
+    }
+
+    pub fn get(&self, id: Id) -> Option<&T> {
+        self.values.get(id.index())?.as_ref()
+    }
+}
+
+impl<Id: NodeId, T> Default for SideTable<Id, T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A parsed script: its top-level statements and the arena holding them.
+/// The arena is shared with every function declared in the script.
+#[derive(Debug)]
+pub struct Program {
+    pub ast: Rc<Ast>,
+    pub statements: Vec<StmtId>,
+}
+
+/// Prints each top-level statement in prefix form, one per line.
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for &statement in &self.statements {
+            writeln!(f, "{}", self.ast.tree(statement))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parser::Parser;
+    use crate::scanner::Scanner;
+
+    fn parse(source: &str) -> Program {
+        let mut scanner = Scanner::new(source.to_string());
+        let tokens = scanner.scan_tokens().unwrap();
+        Parser::new(tokens).parse().unwrap()
+    }
+
+    fn text(source: &str, span: Span) -> &str {
+        &source[span.start as usize..span.end as usize]
+    }
+
+    #[test]
+    fn test_spans_cover_node_source() {
+        let source = "var x = -(1 + 2) * f(a)[0];\nx.y = [1, 2];";
+        let program = parse(source);
+        let ast = &program.ast;
+
+        let Stmt::Var(_, Some(initializer)) = &ast[program.statements[0]] else {
+            panic!("expected a var declaration");
+        };
+        assert_eq!(
+            text(source, ast.expr_span(*initializer)),
+            "-(1 + 2) * f(a)[0]"
+        );
+        let Expr::Binary(left, _, right) = &ast[*initializer] else {
+            panic!("expected a binary expression");
+        };
+        assert_eq!(text(source, ast.expr_span(*left)), "-(1 + 2)");
+        assert_eq!(text(source, ast.expr_span(*right)), "f(a)[0]");
+
+        let statement = program.statements[1];
+        assert_eq!(text(source, ast.stmt_span(statement)), "x.y = [1, 2];");
+        let Stmt::Expression(set) = &ast[statement] else {
+            panic!("expected an expression statement");
+        };
+        assert!(matches!(ast[*set], Expr::Set(..)));
+        assert_eq!(text(source, ast.expr_span(*set)), "x.y = [1, 2]");
+    }
+
+    #[test]
+    fn test_side_table() {
+        let program = parse("print 1 + 2;");
+        let ast = &program.ast;
+        let mut depths = SideTable::new();
+        let Stmt::Print(sum) = &ast[program.statements[0]] else {
+            panic!("expected a print statement");
+        };
+        depths.insert(*sum, 0);
+        assert_eq!(depths.get(*sum), Some(&0));
+        assert_eq!(depths.get(ExprId(0)), None);
+        assert_eq!(ast.expr_count(), 3);
+    }
+}
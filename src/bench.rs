@@ -0,0 +1,245 @@
+//! Benchmarks of the interpreter, run by `rslox bench` and `cargo bench`.
+//! Both need the `bench` feature, which builds this module.
+//!
+//! The programs are the classic Lox benchmarks from Crafting Interpreters,
+//! scaled down to run in a fraction of a second each, and timed without
+//! scanning and parsing. The micro-benchmarks time the scanner and parser
+//! over all of the programs, and the evaluation of one expression. Results
+//! print as JSON, so that runs of different versions can be compared.
+
+use crate::ast::{Ast, ExprId};
+use crate::interpreter::Interpreter;
+use crate::parser::Parser;
+use crate::scanner::Scanner;
+use std::fmt::Write;
+use std::io;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+/// The benchmark programs in `benches/lox/`, by name.
+pub const PROGRAMS: &[(&str, &str)] = &[
+    (
+        "binary_trees",
+        include_str!("../benches/lox/binary_trees.lox"),
+    ),
+    ("equality", include_str!("../benches/lox/equality.lox")),
+    ("fib", include_str!("../benches/lox/fib.lox")),
+    (
+        "instantiation",
+        include_str!("../benches/lox/instantiation.lox"),
+    ),
+    ("invocation", include_str!("../benches/lox/invocation.lox")),
+    (
+        "method_call",
+        include_str!("../benches/lox/method_call.lox"),
+    ),
+    ("properties", include_str!("../benches/lox/properties.lox")),
+    (
+        "string_equality",
+        include_str!("../benches/lox/string_equality.lox"),
+    ),
+    ("trees", include_str!("../benches/lox/trees.lox")),
+    ("zoo", include_str!("../benches/lox/zoo.lox")),
+];
+
+/// How many times the micro-benchmarks repeat their work per sample, so
+/// that a sample takes long enough to time.
+const SCAN_REPEATS: usize = 20;
+const EVALUATE_REPEATS: usize = 10_000;
+
+const EXPRESSION: &str =
+    "(1 + 2) * 3 - 4 / 5 + (6 - 7) * 8 == 9 or !true and \"a\" + \"b\" == \"ab\"";
+
+pub struct Benchmark {
+    pub name: &'static str,
+    /// Runs the benchmark once, returning the time taken by the part being
+    /// measured.
+    pub run: Box<dyn Fn() -> Duration>,
+}
+
+/// Every benchmark: the programs, then the micro-benchmarks.
+pub fn benchmarks() -> Vec<Benchmark> {
+    let mut benchmarks: Vec<_> = PROGRAMS
+        .iter()
+        .map(|&(name, source)| Benchmark {
+            name,
+            run: Box::new(move || run_program(source)),
+        })
+        .collect();
+    benchmarks.push(Benchmark {
+        name: "scan_tokens",
+        run: Box::new(scan_tokens),
+    });
+    benchmarks.push(Benchmark {
+        name: "parse",
+        run: Box::new(parse),
+    });
+    benchmarks.push(Benchmark {
+        name: "evaluate",
+        run: Box::new(evaluate),
+    });
+    benchmarks
+}
+
+fn run_program(source: &str) -> Duration {
+    let mut scanner = Scanner::new(source.to_string());
+    let tokens = scanner.scan_tokens().expect("benchmark doesn't scan");
+    let program = Parser::new(tokens)
+        .parse()
+        .expect("benchmark doesn't parse");
+    let mut interpreter = Interpreter::with_output(Box::new(io::sink()));
+    let start = Instant::now();
+    interpreter.interpret(&program).expect("benchmark failed");
+    start.elapsed()
+}
+
+/// The source of every program, repeated `SCAN_REPEATS` times.
+fn all_programs() -> String {
+    let sources: Vec<_> = PROGRAMS.iter().map(|(_, source)| *source).collect();
+    sources.join("\n").repeat(SCAN_REPEATS)
+}
+
+fn scan_tokens() -> Duration {
+    let mut scanner = Scanner::new(all_programs());
+    let start = Instant::now();
+    scanner.scan_tokens().expect("benchmark doesn't scan");
+    start.elapsed()
+}
+
+fn parse() -> Duration {
+    let mut scanner = Scanner::new(all_programs());
+    let tokens = scanner.scan_tokens().expect("benchmark doesn't scan");
+    let mut parser = Parser::new(tokens);
+    let start = Instant::now();
+    parser.parse().expect("benchmark doesn't parse");
+    start.elapsed()
+}
+
+/// Parses `EXPRESSION`.
+fn expression() -> (Rc<Ast>, ExprId) {
+    let mut scanner = Scanner::new(EXPRESSION.to_string());
+    let tokens = scanner.scan_tokens().expect("benchmark doesn't scan");
+    let mut parser = Parser::new(tokens);
+    let expr = parser.parse_expression().expect("benchmark doesn't parse");
+    (Rc::new(parser.into_ast()), expr)
+}
+
+fn evaluate() -> Duration {
+    let (ast, expr) = expression();
+    let mut interpreter = Interpreter::with_output(Box::new(io::sink()));
+    let start = Instant::now();
+    for _ in 0..EVALUATE_REPEATS {
+        interpreter
+            .evaluate_expression(&ast, expr)
+            .expect("benchmark failed");
+    }
+    start.elapsed()
+}
+
+/// The samples taken of one benchmark.
+pub struct Timing {
+    pub name: &'static str,
+    pub samples: Vec<Duration>,
+}
+
+impl Timing {
+    pub fn min(&self) -> Duration {
+        self.samples.iter().copied().min().unwrap_or_default()
+    }
+
+    pub fn median(&self) -> Duration {
+        let mut samples = self.samples.clone();
+        samples.sort();
+        samples.get(samples.len() / 2).copied().unwrap_or_default()
+    }
+
+    pub fn mean(&self) -> Duration {
+        match self.samples.len() {
+            0 => Duration::ZERO,
+            n => self.samples.iter().sum::<Duration>() / n as u32,
+        }
+    }
+}
+
+/// Runs `benchmark` once to warm up and then `iterations` times.
+pub fn time(benchmark: &Benchmark, iterations: usize) -> Timing {
+    (benchmark.run)();
+    Timing {
+        name: benchmark.name,
+        samples: (0..iterations).map(|_| (benchmark.run)()).collect(),
+    }
+}
+
+/// Formats `timings` as a JSON object, with times in milliseconds.
+pub fn to_json(timings: &[Timing], iterations: usize) -> String {
+    let ms = |duration: Duration| duration.as_secs_f64() * 1000.0;
+    let mut json = format!(
+        "{{\n  \"version\": \"{}\",\n  \"iterations\": {},\n  \"benchmarks\": [",
+        env!("CARGO_PKG_VERSION"),
+        iterations
+    );
+    for (i, timing) in timings.iter().enumerate() {
+        if i > 0 {
+            json.push(',');
+        }
+        write!(
+            json,
+            "\n    {{\"name\": \"{}\", \"min_ms\": {:.3}, \"median_ms\": {:.3}, \"mean_ms\": {:.3}}}",
+            timing.name,
+            ms(timing.min()),
+            ms(timing.median()),
+            ms(timing.mean())
+        )
+        .unwrap();
+    }
+    json.push_str("\n  ]\n}");
+    json
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::expr::Value;
+
+    #[test]
+    fn test_programs_parse() {
+        for (name, source) in PROGRAMS {
+            let mut scanner = Scanner::new(source.to_string());
+            let tokens = scanner.scan_tokens().unwrap();
+            assert!(
+                Parser::new(tokens).parse().is_ok(),
+                "{} doesn't parse",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn test_to_json() {
+        let timings = [Timing {
+            name: "fib",
+            samples: vec![
+                Duration::from_millis(3),
+                Duration::from_millis(1),
+                Duration::from_millis(2),
+            ],
+        }];
+        assert_eq!(
+            to_json(&timings, 3),
+            format!(
+                "{{\n  \"version\": \"{}\",\n  \"iterations\": 3,\n  \"benchmarks\": [\n    \
+                 {{\"name\": \"fib\", \"min_ms\": 1.000, \"median_ms\": 2.000, \"mean_ms\": 2.000}}\n  \
+                 ]\n}}",
+                env!("CARGO_PKG_VERSION")
+            )
+        );
+    }
+
+    #[test]
+    fn test_expression_evaluates() {
+        let (ast, expr) = expression();
+        let mut interpreter = Interpreter::with_output(Box::new(io::sink()));
+        let value = interpreter.evaluate_expression(&ast, expr).unwrap();
+        assert_eq!(value, Value::False);
+    }
+}
@@ -0,0 +1,97 @@
+use crate::expr::Value;
+use crate::function::LoxFunction;
+use crate::gc::{self, Address, Heap, Trace};
+use crate::interner::{Symbol, SymbolMap};
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+
+pub struct LoxClass {
+    pub name: Symbol,
+    methods: SymbolMap<Rc<LoxFunction>>,
+}
+
+impl LoxClass {
+    pub fn new(name: Symbol, methods: SymbolMap<Rc<LoxFunction>>) -> Self {
+        Self { name, methods }
+    }
+
+    pub fn find_method(&self, name: &Symbol) -> Option<Rc<LoxFunction>> {
+        self.methods.get(name).cloned()
+    }
+}
+
+impl fmt::Debug for LoxClass {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+pub struct LoxInstance {
+    pub class: Rc<LoxClass>,
+    fields: SymbolMap<Value>,
+}
+
+impl LoxInstance {
+    pub fn new(class: Rc<LoxClass>) -> Self {
+        Self {
+            class,
+            fields: SymbolMap::default(),
+        }
+    }
+
+    pub fn field(&self, name: &Symbol) -> Option<Value> {
+        self.fields.get(name).cloned()
+    }
+
+    /// Looks up a field, falling back to a method bound to `instance`.
+    pub fn get(instance: &Rc<RefCell<LoxInstance>>, name: &Symbol, heap: &Heap) -> Option<Value> {
+        let this = instance.borrow();
+        if let Some(value) = this.fields.get(name) {
+            return Some(value.clone());
+        }
+        let method = this.class.find_method(name)?;
+        Some(Value::Function(
+            heap.alloc(method.bind(Rc::clone(instance), heap)),
+        ))
+    }
+
+    pub fn set(&mut self, name: impl Into<Symbol>, value: Value) {
+        self.fields.insert(name.into(), value);
+    }
+}
+
+impl Trace for LoxClass {
+    fn trace(&self, visit: &mut dyn FnMut(Address)) {
+        for method in self.methods.values() {
+            visit(gc::address(method));
+        }
+    }
+
+    fn owned_bytes(&self) -> usize {
+        gc::table_bytes(&self.methods)
+    }
+}
+
+impl Trace for LoxInstance {
+    fn trace(&self, visit: &mut dyn FnMut(Address)) {
+        visit(gc::address(&self.class));
+        for value in self.fields.values() {
+            value.trace(visit);
+        }
+    }
+
+    fn clear(&mut self) {
+        self.fields.clear();
+    }
+
+    fn owned_bytes(&self) -> usize {
+        gc::table_bytes(&self.fields)
+    }
+}
+
+impl fmt::Debug for LoxInstance {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} instance", self.class.name)
+    }
+}
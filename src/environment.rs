@@ -0,0 +1,125 @@
+use crate::expr::Value;
+use crate::gc::{self, Address, Heap, Trace};
+use crate::interner::{Symbol, SymbolMap};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// A scope's variables, keyed by symbol so that a lookup hashes and
+/// compares addresses rather than names.
+#[derive(Debug, Default)]
+pub struct Environment {
+    values: SymbolMap<Value>,
+    enclosing: Option<Rc<RefCell<Environment>>>,
+    /// Whether the scope is on the heap, which it only needs to be once a
+    /// closure captures it.
+    tracked: bool,
+}
+
+impl Environment {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn new_enclosed(enclosing: Rc<RefCell<Environment>>) -> Self {
+        Self {
+            values: SymbolMap::default(),
+            enclosing: Some(enclosing),
+            tracked: false,
+        }
+    }
+
+    /// Puts `environment` and the scopes enclosing it on the heap, for a
+    /// closure to capture, and returns it.
+    ///
+    /// A scope can only be part of a cycle once a closure captures it or a
+    /// scope inside it, so the collector doesn't need to see the rest.
+    pub fn capture(
+        environment: &Rc<RefCell<Environment>>,
+        heap: &Heap,
+    ) -> Rc<RefCell<Environment>> {
+        let mut scope = Some(Rc::clone(environment));
+        while let Some(current) = scope {
+            let enclosing = {
+                let mut current = current.borrow_mut();
+                if current.tracked {
+                    break;
+                }
+                current.tracked = true;
+                current.enclosing.clone()
+            };
+            heap.track(&current);
+            scope = enclosing;
+        }
+        Rc::clone(environment)
+    }
+
+    /// Visits what `environment` and the scopes enclosing it refer to, for
+    /// the collector to mark from. A scope on the heap is visited by its
+    /// address, and the collector traces it and the scopes enclosing it. The
+    /// rest aren't on the heap, so their variables are visited here.
+    pub fn trace_roots(environment: &Rc<RefCell<Environment>>, visit: &mut dyn FnMut(Address)) {
+        let mut scope = Rc::clone(environment);
+        loop {
+            let enclosing = {
+                let current = scope.borrow();
+                if current.tracked {
+                    visit(gc::address(&scope));
+                    return;
+                }
+                for value in current.values.values() {
+                    value.trace(visit);
+                }
+                current.enclosing.clone()
+            };
+            match enclosing {
+                Some(enclosing) => scope = enclosing,
+                None => return,
+            }
+        }
+    }
+
+    pub fn define(&mut self, name: impl Into<Symbol>, value: Value) {
+        self.values.insert(name.into(), value);
+    }
+
+    /// Looks `name` up in this scope and then each enclosing one.
+    pub fn get(&self, name: &Symbol) -> Option<Value> {
+        match self.values.get(name) {
+            Some(value) => Some(value.clone()),
+            None => self.enclosing.as_ref()?.borrow().get(name),
+        }
+    }
+
+    /// Overwrites an existing variable, returning `false` if `name` is not
+    /// defined in any enclosing scope.
+    pub fn assign(&mut self, name: &Symbol, value: Value) -> bool {
+        if let Some(slot) = self.values.get_mut(name) {
+            *slot = value;
+            return true;
+        }
+        match &self.enclosing {
+            Some(enclosing) => enclosing.borrow_mut().assign(name, value),
+            None => false,
+        }
+    }
+}
+
+impl Trace for Environment {
+    fn trace(&self, visit: &mut dyn FnMut(Address)) {
+        for value in self.values.values() {
+            value.trace(visit);
+        }
+        if let Some(enclosing) = &self.enclosing {
+            visit(gc::address(enclosing));
+        }
+    }
+
+    fn clear(&mut self) {
+        self.values.clear();
+        self.enclosing = None;
+    }
+
+    fn owned_bytes(&self) -> usize {
+        gc::table_bytes(&self.values)
+    }
+}
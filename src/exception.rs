@@ -0,0 +1,82 @@
+//! Error objects, the values that `catch` receives.
+//!
+//! An error object is an instance of the built-in `Error` class with a
+//! `message`, the `line` it was raised at and a `stack` listing the active
+//! calls, innermost first. Runtime errors raised by the interpreter become
+//! error objects when they are caught; scripts create their own with
+//! `Error(message)`, whose location is filled in when it is thrown.
+
+use crate::class::{LoxClass, LoxInstance};
+use crate::expr::Value;
+use crate::interner::{Symbol, SymbolMap};
+use crate::interpreter::Interpreter;
+use crate::lox::{Frame, RuntimeError};
+use crate::token::Token;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+pub fn error_class() -> LoxClass {
+    LoxClass::new("Error".into(), SymbolMap::default())
+}
+
+/// `Error(message)`.
+pub fn error(
+    interpreter: &mut Interpreter,
+    _: &Token,
+    args: &[Value],
+) -> Result<Value, RuntimeError> {
+    Ok(new_error(
+        interpreter,
+        args[0].clone(),
+        Value::Nil,
+        Value::Nil,
+    ))
+}
+
+fn new_error(interpreter: &Interpreter, message: Value, line: Value, stack: Value) -> Value {
+    let mut error = LoxInstance::new(interpreter.error_class());
+    error.set("message", message);
+    error.set("line", line);
+    error.set("stack", stack);
+    Value::Instance(interpreter.heap().alloc(RefCell::new(error)))
+}
+
+fn stack(interpreter: &Interpreter, trace: &[Frame]) -> Value {
+    let frames = trace
+        .iter()
+        .map(|frame| Value::from(frame.to_string()))
+        .collect();
+    interpreter.heap().list(frames)
+}
+
+/// The value a `catch` clause binds for `error`: the thrown value itself,
+/// or an error object describing a runtime error.
+pub fn caught_value(interpreter: &Interpreter, error: RuntimeError) -> Value {
+    match error {
+        RuntimeError::Thrown { value, .. } => value,
+        error => new_error(
+            interpreter,
+            Value::from(error.message()),
+            Value::Int(error.line().into()),
+            stack(interpreter, error.trace()),
+        ),
+    }
+}
+
+/// Records where an error object created by `Error(message)` is first
+/// thrown. Other values, and errors being rethrown, are left alone.
+pub fn set_location(interpreter: &Interpreter, value: &Value, keyword: &Token, trace: &[Frame]) {
+    let Value::Instance(instance) = value else {
+        return;
+    };
+    if !Rc::ptr_eq(&instance.borrow().class, &interpreter.error_class()) {
+        return;
+    }
+    if instance.borrow().field(&Symbol::intern("stack")) != Some(Value::Nil) {
+        return;
+    }
+    let stack = stack(interpreter, trace);
+    let mut instance = instance.borrow_mut();
+    instance.set("line", Value::Int(keyword.line.into()));
+    instance.set("stack", stack);
+}
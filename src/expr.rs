@@ -1,83 +1,359 @@
+use crate::ast::{ExprId, Tree};
+use crate::class::{LoxClass, LoxInstance};
+use crate::function::{LoxFunction, NativeFunction};
+use crate::iterator::Range;
+use crate::list::List;
+use crate::map::Map;
+use crate::module::Module;
 use crate::token::Token;
-use crate::lox;
-use std::boxed::Box;
-use std::convert::TryFrom;
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::fmt;
+use std::rc::Rc;
 
+/// An expression node. Children are nodes in the same `Ast`.
 #[derive(Debug)]
 pub enum Expr {
-    Binary(Box<Expr>, Token, Box<Expr>),
-    Unary(Token, Box<Expr>),
-    Grouping(Box<Expr>),
+    Binary(ExprId, Token, ExprId),
+    Unary(Token, ExprId),
+    Grouping(ExprId),
     Literal(Value),
+    Variable(Token),
+    Assign(Token, ExprId),
+    Call(ExprId, Token, Vec<ExprId>),
+    Get(ExprId, Token),
+    /// `object?.name`: `nil` if the object is `nil`, which also skips the
+    /// rest of the property and call chain it heads.
+    OptionalGet(ExprId, Token),
+    Set(ExprId, Token, ExprId),
+    This(Token),
+    /// Short-circuiting binary operators.
+    Logical(ExprId, Token, ExprId),
+    Conditional(ExprId, ExprId, ExprId),
+    /// A list literal, `[a, b, c]`.
+    List(Vec<ExprId>),
+    /// A map literal, `{key: value, ...}`. Each entry keeps its `:` token
+    /// for reporting unhashable keys.
+    Map(Vec<(ExprId, Token, ExprId)>),
+    /// `object[index]`. The token is the opening bracket.
+    Index(ExprId, Token, ExprId),
+    /// `object[index] = value`.
+    IndexSet(ExprId, Token, ExprId, ExprId),
 }
 
-impl Expr {
-    pub fn binary(left: Expr, operator: Token, right: Expr) -> Self {
-        Self::Binary(Box::new(left), operator, Box::new(right))
+/// Prints the tree fully parenthesized in prefix form, e.g. `(+ 1 (* 2 3))`.
+impl fmt::Display for Tree<'_, ExprId> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let tree = |id| self.ast.tree(id);
+        match &self.ast[self.id] {
+            Expr::Binary(left, operator, right) | Expr::Logical(left, operator, right) => {
+                write!(
+                    f,
+                    "({} {} {})",
+                    operator.lexeme(),
+                    tree(*left),
+                    tree(*right)
+                )
+            }
+            Expr::Unary(operator, expr) => write!(f, "({} {})", operator.lexeme(), tree(*expr)),
+            Expr::Grouping(expr) => write!(f, "(group {})", tree(*expr)),
+            Expr::Literal(Value::String(s)) => write!(f, "{:?}", s),
+            Expr::Literal(value) => write!(f, "{}", value),
+            Expr::Variable(name) => write!(f, "{}", name.lexeme()),
+            Expr::Assign(name, value) => write!(f, "(= {} {})", name.lexeme(), tree(*value)),
+            Expr::Call(callee, _, arguments) => {
+                write!(f, "(call {}", tree(*callee))?;
+                for argument in arguments {
+                    write!(f, " {}", tree(*argument))?;
+                }
+                write!(f, ")")
+            }
+            Expr::Get(object, name) => write!(f, "(. {} {})", tree(*object), name.lexeme()),
+            Expr::OptionalGet(object, name) => {
+                write!(f, "(?. {} {})", tree(*object), name.lexeme())
+            }
+            Expr::Set(object, name, value) => {
+                write!(
+                    f,
+                    "(= (. {} {}) {})",
+                    tree(*object),
+                    name.lexeme(),
+                    tree(*value)
+                )
+            }
+            Expr::This(_) => write!(f, "this"),
+            Expr::Conditional(condition, then_branch, else_branch) => {
+                write!(
+                    f,
+                    "(?: {} {} {})",
+                    tree(*condition),
+                    tree(*then_branch),
+                    tree(*else_branch)
+                )
+            }
+            Expr::List(elements) => {
+                write!(f, "(list")?;
+                for element in elements {
+                    write!(f, " {}", tree(*element))?;
+                }
+                write!(f, ")")
+            }
+            Expr::Map(entries) => {
+                write!(f, "(map")?;
+                for (key, _, value) in entries {
+                    write!(f, " ({} {})", tree(*key), tree(*value))?;
+                }
+                write!(f, ")")
+            }
+            Expr::Index(object, _, index) => {
+                write!(f, "(index {} {})", tree(*object), tree(*index))
+            }
+            Expr::IndexSet(object, _, index, value) => {
+                write!(
+                    f,
+                    "(= (index {} {}) {})",
+                    tree(*object),
+                    tree(*index),
+                    tree(*value)
+                )
+            }
+        }
     }
+}
+
+#[derive(Debug, Clone)]
+pub enum Value {
+    /// Strings are immutable, so values share them rather than copying.
+    String(Rc<str>),
+    Number(f64),
+    Int(i64),
+    True,
+    False,
+    Nil,
+    Function(Rc<LoxFunction>),
+    Native(Rc<NativeFunction>),
+    Class(Rc<LoxClass>),
+    Instance(Rc<RefCell<LoxInstance>>),
+    List(List),
+    Map(Map),
+    Range(Range),
+    Module(Rc<Module>),
+}
 
-    pub fn unary(operator: Token, expr: Expr) -> Self {
-        Self::Unary(operator, Box::new(expr))
+impl Value {
+    /// The name used for this value's type in error messages.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::String(_) => "string",
+            Value::Number(_) | Value::Int(_) => "number",
+            Value::True | Value::False => "boolean",
+            Value::Nil => "nil",
+            Value::Function(_) | Value::Native(_) => "function",
+            Value::Class(_) => "class",
+            Value::Instance(_) => "instance",
+            Value::List(_) => "list",
+            Value::Map(_) => "map",
+            Value::Range(_) => "range",
+            Value::Module(_) => "module",
+        }
     }
 
-    pub fn literal_num(num: f64) -> Self {
-        Self::Literal(Value::Number(num))
+    /// Whether the value refers to an object the collector manages.
+    pub fn is_object(&self) -> bool {
+        matches!(
+            self,
+            Value::Function(_)
+                | Value::Native(_)
+                | Value::Class(_)
+                | Value::Instance(_)
+                | Value::List(_)
+                | Value::Map(_)
+        )
     }
 
-    pub fn literal_str(string: String) -> Self {
-        Self::Literal(Value::String(string))
+    pub fn is_number(&self) -> bool {
+        matches!(self, Value::Number(_) | Value::Int(_))
     }
 
-    pub fn grouping(expr: Expr) -> Self {
-        Self::Grouping(Box::new(expr))
+    /// The value as a float if it is a number of either kind.
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Value::Number(n) => Some(*n),
+            Value::Int(n) => Some(*n as f64),
+            _ => None,
+        }
     }
 }
 
-#[derive(Debug, Clone, PartialEq)]
-pub enum Value {
-    String(String),
-    Number(f64),
-    True,
-    False,
-    Nil,
+/// Converts `n` to an integer if it has no fractional part and is within
+/// the `i64` range.
+pub fn exact_integer(n: f64) -> Option<i64> {
+    // 2^63 is exactly representable, unlike i64::MAX.
+    const LIMIT: f64 = 9_223_372_036_854_775_808.0;
+    if n.fract() == 0.0 && (-LIMIT..LIMIT).contains(&n) {
+        Some(n as i64)
+    } else {
+        None
+    }
+}
+
+/// Orders two numbers of either kind, or returns `None` if either is NaN
+/// or isn't a number. An integer and a float compare exactly, like `==`, so
+/// `2 ** 53 + 1 > 2.0 ** 53` even though both convert to the same float.
+pub fn compare_numbers(a: &Value, b: &Value) -> Option<Ordering> {
+    match (a, b) {
+        (Value::Int(a), Value::Int(b)) => Some(a.cmp(b)),
+        (Value::Number(a), Value::Number(b)) => a.partial_cmp(b),
+        (Value::Int(a), Value::Number(b)) => compare_int_float(*a, *b),
+        (Value::Number(a), Value::Int(b)) => compare_int_float(*b, *a).map(Ordering::reverse),
+        _ => None,
+    }
 }
 
-impl TryFrom<Value> for bool {
-    type Error = lox::RuntimeError;
+fn compare_int_float(int: i64, float: f64) -> Option<Ordering> {
+    if float.is_nan() {
+        return None;
+    }
+    // Compare with the integer part of the float, which is exact once it's
+    // in range, and let the fractional part break a tie.
+    match exact_integer(float.floor()) {
+        Some(floor) => Some(int.cmp(&floor).then(if float.fract() == 0.0 {
+            Ordering::Equal
+        } else {
+            Ordering::Less
+        })),
+        None if float > 0.0 => Some(Ordering::Less),
+        None => Some(Ordering::Greater),
+    }
+}
 
-    fn try_from(value: Value) -> Result<Self, Self::Error> {
-        let coerced = match value {
-            Value::True => true,
-            Value::False => false,
-            Value::Nil => false,
-            Value::String(s) => s.is_empty(),
-            Value::Number(n) => n == 0.0,
-        };
+/// Scalars, strings and ranges compare by value, objects by identity. An integer equals a float
+/// only if the float holds exactly that integer, so `3 == 3.0` but
+/// `2 ** 53 + 1 != 2.0 ** 53`.
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Value::String(a), Value::String(b)) => Rc::ptr_eq(a, b) || a == b,
+            (Value::Number(a), Value::Number(b)) => a == b,
+            (Value::Int(a), Value::Int(b)) => a == b,
+            (Value::Int(a), Value::Number(b)) | (Value::Number(b), Value::Int(a)) => {
+                exact_integer(*b) == Some(*a)
+            }
+            (Value::True, Value::True) | (Value::False, Value::False) => true,
+            (Value::Nil, Value::Nil) => true,
+            (Value::Function(a), Value::Function(b)) => Rc::ptr_eq(a, b),
+            (Value::Native(a), Value::Native(b)) => Rc::ptr_eq(a, b),
+            (Value::Class(a), Value::Class(b)) => Rc::ptr_eq(a, b),
+            (Value::Instance(a), Value::Instance(b)) => Rc::ptr_eq(a, b),
+            (Value::List(a), Value::List(b)) => Rc::ptr_eq(a, b),
+            (Value::Map(a), Value::Map(b)) => Rc::ptr_eq(a, b),
+            (Value::Range(a), Value::Range(b)) => a == b,
+            (Value::Module(a), Value::Module(b)) => Rc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+}
 
-        Ok(coerced)
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.write(f, &mut Vec::new())
     }
 }
 
-impl TryFrom<Value> for f64 {
-    type Error = lox::RuntimeError;
+impl Value {
+    /// Writes the value for `print`. `open` holds the collections currently
+    /// being written, so that a list or map containing itself prints as
+    /// `[...]` or `{...}` instead of recursing forever.
+    fn write(&self, f: &mut fmt::Formatter, open: &mut Vec<*const ()>) -> fmt::Result {
+        match self {
+            Value::String(s) => write!(f, "{}", s),
+            // Whole floats print without a fraction, the same as the equal
+            // integer.
+            Value::Number(n) => write!(f, "{}", n),
+            Value::Int(n) => write!(f, "{}", n),
+            Value::True => write!(f, "true"),
+            Value::False => write!(f, "false"),
+            Value::Nil => write!(f, "nil"),
+            Value::Function(function) => write!(f, "<fn {}>", function.name()),
+            Value::Class(class) => write!(f, "{}", class.name),
+            Value::Instance(instance) => write!(f, "{} instance", instance.borrow().class.name),
+            Value::Native(native) => write!(f, "<native fn {}>", native.name),
+            Value::Module(module) => write!(f, "<module {}>", module.name),
+            Value::Range(range) => {
+                write!(f, "range({}, {}, {})", range.start, range.end, range.step)
+            }
+            Value::List(list) => {
+                let ptr = Rc::as_ptr(list) as *const ();
+                if open.contains(&ptr) {
+                    return write!(f, "[...]");
+                }
+                open.push(ptr);
+                write!(f, "[")?;
+                for (i, element) in list.borrow().iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    element.write_element(f, open)?;
+                }
+                open.pop();
+                write!(f, "]")
+            }
+            Value::Map(map) => {
+                let ptr = Rc::as_ptr(map) as *const ();
+                if open.contains(&ptr) {
+                    return write!(f, "{{...}}");
+                }
+                open.push(ptr);
+                write!(f, "{{")?;
+                for (i, (key, value)) in map.borrow().iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    key.to_value().write_element(f, open)?;
+                    write!(f, ": ")?;
+                    value.write_element(f, open)?;
+                }
+                open.pop();
+                write!(f, "}}")
+            }
+        }
+    }
 
-    fn try_from(value: Value) -> Result<Self, Self::Error> {
-        if let Value::Number(n) = value {
-            Ok(n)
-        } else {
-            Err(Self::Error { message: "Not a number".to_string() })
+    /// Writes a value nested in a collection. Strings are quoted there, so
+    /// `["a, b"]` can be told apart from `["a", "b"]`.
+    fn write_element(&self, f: &mut fmt::Formatter, open: &mut Vec<*const ()>) -> fmt::Result {
+        match self {
+            Value::String(s) => write!(f, "{:?}", s),
+            _ => self.write(f, open),
         }
     }
 }
 
-impl TryFrom<Value> for String {
-    type Error = lox::RuntimeError;
+/// Lox truthiness: `false` and `nil` are falsey, everything else is truthy.
+impl From<&Value> for bool {
+    fn from(value: &Value) -> Self {
+        !matches!(value, Value::False | Value::Nil)
+    }
+}
+
+impl From<String> for Value {
+    fn from(string: String) -> Self {
+        Value::String(string.into())
+    }
+}
+
+impl From<&str> for Value {
+    fn from(string: &str) -> Self {
+        Value::String(string.into())
+    }
+}
 
-    fn try_from(value: Value) -> Result<Self, Self::Error> {
-        if let Value::String(s) = value {
-            Ok(s)
+impl From<bool> for Value {
+    fn from(b: bool) -> Self {
+        if b {
+            Value::True
         } else {
-            Err(Self::Error { message: "Not a string".to_string()})
+            Value::False
         }
     }
 }
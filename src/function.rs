@@ -0,0 +1,126 @@
+use crate::ast::Ast;
+use crate::class::LoxInstance;
+use crate::environment::Environment;
+use crate::expr::Value;
+use crate::gc::{self, Address, Heap, Trace};
+use crate::interpreter::Interpreter;
+use crate::lox::RuntimeError;
+use crate::stmt::FunctionDecl;
+use crate::token::Token;
+use std::cell::RefCell;
+use std::fmt;
+use std::ops::RangeInclusive;
+use std::rc::Rc;
+
+/// A user-defined function together with the environment it closes over.
+pub struct LoxFunction {
+    /// The arena holding the declaration's body.
+    pub ast: Rc<Ast>,
+    pub declaration: Rc<FunctionDecl>,
+    pub closure: Rc<RefCell<Environment>>,
+    pub is_initializer: bool,
+}
+
+impl LoxFunction {
+    pub fn new(
+        ast: Rc<Ast>,
+        declaration: Rc<FunctionDecl>,
+        closure: Rc<RefCell<Environment>>,
+        is_initializer: bool,
+    ) -> Self {
+        Self {
+            ast,
+            declaration,
+            closure,
+            is_initializer,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        self.declaration.name.name()
+    }
+
+    pub fn arity(&self) -> usize {
+        self.declaration.params.len()
+    }
+
+    /// Returns a copy of this method whose closure has `this` bound to
+    /// `instance`.
+    pub fn bind(&self, instance: Rc<RefCell<LoxInstance>>, heap: &Heap) -> LoxFunction {
+        let mut environment = Environment::new_enclosed(Rc::clone(&self.closure));
+        environment.define("this", Value::Instance(instance));
+        LoxFunction::new(
+            Rc::clone(&self.ast),
+            Rc::clone(&self.declaration),
+            Environment::capture(&Rc::new(RefCell::new(environment)), heap),
+            self.is_initializer,
+        )
+    }
+}
+
+impl Trace for LoxFunction {
+    fn trace(&self, visit: &mut dyn FnMut(Address)) {
+        visit(gc::address(&self.closure));
+    }
+}
+
+impl fmt::Debug for LoxFunction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "<fn {}>", self.name())
+    }
+}
+
+/// The Rust side of a native function. It receives the token of the call's
+/// closing paren for error reporting, and the call's arguments, preceded by
+/// the receiver for built-in methods.
+pub type NativeFn = fn(&mut Interpreter, &Token, &[Value]) -> Result<Value, RuntimeError>;
+
+/// A function implemented in Rust.
+pub struct NativeFunction {
+    pub name: &'static str,
+    /// The number of arguments accepted, not counting the receiver.
+    pub arity: RangeInclusive<usize>,
+    pub function: NativeFn,
+    /// For built-in methods, the value the method was looked up on.
+    pub receiver: Option<Value>,
+}
+
+impl NativeFunction {
+    pub fn new(name: &'static str, arity: RangeInclusive<usize>, function: NativeFn) -> Self {
+        Self {
+            name,
+            arity,
+            function,
+            receiver: None,
+        }
+    }
+
+    /// Binds a built-in method of `receiver`.
+    pub fn method(
+        name: &'static str,
+        arity: RangeInclusive<usize>,
+        function: NativeFn,
+        receiver: Value,
+    ) -> Self {
+        Self {
+            name,
+            arity,
+            function,
+            receiver: Some(receiver),
+        }
+    }
+}
+
+impl fmt::Debug for NativeFunction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "<native fn {}>", self.name)
+    }
+}
+
+impl Trace for NativeFunction {
+    fn trace(&self, visit: &mut dyn FnMut(Address)) {
+        if let Some(receiver) = &self.receiver {
+            receiver.trace(visit);
+        }
+    }
+}
@@ -0,0 +1,538 @@
+//! A cycle collector for Lox objects.
+//!
+//! Objects are reference counted, which frees most of them as soon as they
+//! become unreachable, but not cycles: an instance whose field refers to
+//! itself, or a closure stored in the environment it captures. The `Heap`
+//! keeps a weak reference to every object the interpreter allocates, and
+//! `collect` finds the cycles that can no longer be reached and breaks them.
+//!
+//! `collect` marks everything reachable from the roots the interpreter
+//! lists: the environments of the code that is running, those of loaded
+//! modules, and the values it is holding on the Rust stack while more Lox
+//! code runs, such as a call's arguments. The rest is garbage, which
+//! `collect` clears so that reference counting frees it. The interpreter
+//! only collects between statements, so natives that allocate without
+//! calling back into Lox don't need to root anything. A value a host holds
+//! outside the interpreter may be cleared if the script can't reach it.
+//!
+//! Scopes are only put on the heap once a closure captures them, along with
+//! the scopes enclosing them. A scope nothing captures can't be part of a
+//! cycle, so most calls and blocks never add an entry.
+
+use crate::class::LoxClass;
+use crate::expr::Value;
+use crate::function::{LoxFunction, NativeFunction};
+use crate::interpreter::Interpreter;
+use crate::lox::RuntimeError;
+use crate::map::{LoxMap, MapKey};
+use crate::token::Token;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::hash::BuildHasher;
+use std::mem;
+use std::rc::{Rc, Weak};
+use std::time::{Duration, Instant};
+
+/// Bytes allocated before the first collection.
+const INITIAL_THRESHOLD: usize = 1024 * 1024;
+
+/// How much the heap may grow past what survived the last collection
+/// before the next one.
+const GROWTH_FACTOR: usize = 2;
+
+/// The address of a heap object, which identifies it.
+pub type Address = usize;
+
+pub fn address<T: ?Sized>(object: &Rc<T>) -> Address {
+    Rc::as_ptr(object) as *const () as Address
+}
+
+/// The references one heap object holds to others.
+pub trait Trace {
+    /// Calls `visit` with the address of each object this one refers to.
+    fn trace(&self, visit: &mut dyn FnMut(Address));
+
+    /// Drops this object's references, to break a cycle it is part of.
+    fn clear(&mut self) {}
+
+    /// The size of the buffers the object owns, such as a list's elements.
+    fn owned_bytes(&self) -> usize {
+        0
+    }
+}
+
+/// The size of a hash map's table.
+pub fn table_bytes<K, V, S: BuildHasher>(map: &HashMap<K, V, S>) -> usize {
+    // Each slot also has a control byte.
+    map.capacity() * (mem::size_of::<(K, V)>() + 1)
+}
+
+/// An object the heap can manage: mutable objects in a `RefCell`, and
+/// functions and classes, which can't change once created and so can only
+/// be part of a cycle that runs through a mutable object.
+pub trait Managed {
+    /// Traces the object, returning `false` if it is borrowed mutably and
+    /// can't be read.
+    fn trace(&self, visit: &mut dyn FnMut(Address)) -> bool;
+
+    /// Clears the object if it isn't borrowed.
+    fn clear(&self);
+
+    /// The size of the object and the buffers it owns, or `None` if it is
+    /// borrowed mutably.
+    fn size(&self) -> Option<usize>;
+}
+
+impl<T: Trace> Managed for RefCell<T> {
+    fn trace(&self, visit: &mut dyn FnMut(Address)) -> bool {
+        match self.try_borrow() {
+            Ok(object) => {
+                object.trace(visit);
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    fn clear(&self) {
+        if let Ok(mut object) = self.try_borrow_mut() {
+            object.clear();
+        }
+    }
+
+    fn size(&self) -> Option<usize> {
+        let object = self.try_borrow().ok()?;
+        Some(mem::size_of::<Self>() + object.owned_bytes())
+    }
+}
+
+macro_rules! immutable {
+    ($($type:ty),*) => {
+        $(
+            impl Managed for $type {
+                fn trace(&self, visit: &mut dyn FnMut(Address)) -> bool {
+                    Trace::trace(self, visit);
+                    true
+                }
+
+                fn clear(&self) {}
+
+                fn size(&self) -> Option<usize> {
+                    Some(mem::size_of::<Self>() + Trace::owned_bytes(self))
+                }
+            }
+        )*
+    };
+}
+
+immutable!(LoxFunction, LoxClass, NativeFunction);
+
+impl Trace for Value {
+    fn trace(&self, visit: &mut dyn FnMut(Address)) {
+        match self {
+            Value::Function(function) => visit(address(function)),
+            Value::Native(native) => visit(address(native)),
+            Value::Class(class) => visit(address(class)),
+            Value::Instance(instance) => visit(address(instance)),
+            Value::List(list) => visit(address(list)),
+            Value::Map(map) => visit(address(map)),
+            // Modules stay loaded, so they aren't on the heap.
+            Value::String(_)
+            | Value::Number(_)
+            | Value::Int(_)
+            | Value::True
+            | Value::False
+            | Value::Nil
+            | Value::Range(_)
+            | Value::Module(_) => {}
+        }
+    }
+}
+
+impl Trace for Vec<Value> {
+    fn trace(&self, visit: &mut dyn FnMut(Address)) {
+        for element in self {
+            element.trace(visit);
+        }
+    }
+
+    fn clear(&mut self) {
+        Vec::clear(self);
+    }
+
+    fn owned_bytes(&self) -> usize {
+        self.capacity() * mem::size_of::<Value>()
+    }
+}
+
+/// What the collector has done so far, as reported by `gc_stats()`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct GcStats {
+    /// The total size of the objects allocated, including the buffers they
+    /// own, such as a list's elements. A buffer that grows after its object
+    /// is allocated is counted again at the next collection.
+    pub bytes_allocated: usize,
+    /// The size of the objects that survived the last collection and the
+    /// buffers they own.
+    pub live_bytes: usize,
+    pub collections: usize,
+    /// The total time spent collecting.
+    pub pause: Duration,
+}
+
+struct Entry {
+    object: Weak<dyn Managed>,
+    /// The object's size when it was last measured.
+    size: usize,
+}
+
+/// The objects allocated by an interpreter.
+pub struct Heap {
+    entries: RefCell<Vec<Entry>>,
+    /// Bytes allocated since the last collection.
+    allocated: Cell<usize>,
+    /// How many bytes may be allocated before the next collection.
+    threshold: Cell<usize>,
+    /// Whether to collect before every statement, to flush out objects
+    /// that are used without being reachable from a root.
+    stress: Cell<bool>,
+    stats: Cell<GcStats>,
+}
+
+impl Default for Heap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Heap {
+    pub fn new() -> Self {
+        Self {
+            entries: RefCell::new(Vec::new()),
+            allocated: Cell::new(0),
+            threshold: Cell::new(INITIAL_THRESHOLD),
+            stress: Cell::new(false),
+            stats: Cell::new(GcStats::default()),
+        }
+    }
+
+    pub fn set_stress(&self, stress: bool) {
+        self.stress.set(stress);
+    }
+
+    pub fn stats(&self) -> GcStats {
+        self.stats.get()
+    }
+
+    /// Moves `object` to the heap.
+    pub fn alloc<T: Managed + 'static>(&self, object: T) -> Rc<T> {
+        let object = Rc::new(object);
+        self.track(&object);
+        object
+    }
+
+    /// Adds an object that was allocated elsewhere to the heap.
+    pub fn track<T: Managed + 'static>(&self, object: &Rc<T>) {
+        let size = object.size().unwrap_or(mem::size_of::<T>());
+        let weak: Weak<dyn Managed> = Rc::downgrade(object) as Weak<dyn Managed>;
+        self.entries.borrow_mut().push(Entry { object: weak, size });
+
+        let mut stats = self.stats.get();
+        stats.bytes_allocated += size;
+        self.stats.set(stats);
+        self.allocated.set(self.allocated.get() + size);
+    }
+
+    /// Whether enough has been allocated since the last collection, or
+    /// stress mode is on, for the interpreter to collect before its next
+    /// statement.
+    pub fn collection_due(&self) -> bool {
+        self.stress.get() || self.allocated.get() >= self.threshold.get()
+    }
+
+    /// The number of objects on the heap that are still alive.
+    pub fn len(&self) -> usize {
+        let entries = self.entries.borrow();
+        entries
+            .iter()
+            .filter(|entry| entry.object.strong_count() > 0)
+            .count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn list(&self, elements: Vec<Value>) -> Value {
+        Value::List(self.alloc(RefCell::new(elements)))
+    }
+
+    pub fn map(&self, map: LoxMap) -> Value {
+        Value::Map(self.alloc(RefCell::new(map)))
+    }
+
+    /// Frees every cycle of objects that can no longer be reached. `roots`
+    /// calls its argument with the address of each object the program
+    /// refers to from outside the heap.
+    pub fn collect(&self, roots: impl FnOnce(&mut dyn FnMut(Address))) {
+        let start = Instant::now();
+        let mut entries = self.entries.borrow_mut();
+        entries.retain(|entry| entry.object.strong_count() > 0);
+        let positions: HashMap<Address, usize> = entries
+            .iter()
+            .enumerate()
+            .map(|(i, entry)| (entry.object.as_ptr() as *const () as Address, i))
+            .collect();
+
+        // The references between heap objects, as a list of the objects
+        // each one refers to.
+        let mut edges = Vec::new();
+        let mut ranges = Vec::with_capacity(entries.len());
+        let mut traced = true;
+        for entry in entries.iter() {
+            let start = edges.len();
+            if let Some(object) = entry.object.upgrade() {
+                traced &= object.trace(&mut |child| {
+                    if let Some(&j) = positions.get(&child) {
+                        edges.push(j);
+                    }
+                });
+            }
+            ranges.push(start..edges.len());
+        }
+
+        let mut marked = vec![false; entries.len()];
+        let mut pending = Vec::new();
+        roots(&mut |root| {
+            if let Some(&i) = positions.get(&root) {
+                if !marked[i] {
+                    marked[i] = true;
+                    pending.push(i);
+                }
+            }
+        });
+        while let Some(i) = pending.pop() {
+            for &j in &edges[ranges[i].clone()] {
+                if !marked[j] {
+                    marked[j] = true;
+                    pending.push(j);
+                }
+            }
+        }
+
+        // What an object borrowed mutably refers to is unknown, so nothing
+        // can be known to be garbage. Nothing is between statements, when
+        // the interpreter collects.
+        if !traced {
+            marked.fill(true);
+        }
+        let garbage: Vec<_> = entries
+            .iter()
+            .zip(&marked)
+            .filter(|(_, &marked)| !marked)
+            .filter_map(|(entry, _)| entry.object.upgrade())
+            .collect();
+        drop(entries);
+        for object in &garbage {
+            object.clear();
+        }
+        drop(garbage);
+
+        let mut entries = self.entries.borrow_mut();
+        entries.retain(|entry| entry.object.strong_count() > 0);
+        let mut stats = self.stats.get();
+        let mut live_bytes = 0;
+        for entry in entries.iter_mut() {
+            let size = entry.object.upgrade().and_then(|object| object.size());
+            if let Some(size) = size {
+                stats.bytes_allocated += size.saturating_sub(entry.size);
+                entry.size = size;
+            }
+            live_bytes += entry.size;
+        }
+        self.allocated.set(0);
+        self.threshold
+            .set((live_bytes * GROWTH_FACTOR).max(INITIAL_THRESHOLD));
+        stats.live_bytes = live_bytes;
+        stats.collections += 1;
+        stats.pause += start.elapsed();
+        self.stats.set(stats);
+    }
+}
+
+pub fn define(interpreter: &mut Interpreter) {
+    interpreter.define_native("gc_stats", 0..=0, gc_stats);
+}
+
+/// `gc_stats()` returns a map of the bytes allocated so far, the bytes that
+/// survived the last collection, the number of collections and the total
+/// time they took in milliseconds.
+fn gc_stats(interpreter: &mut Interpreter, _: &Token, _: &[Value]) -> Result<Value, RuntimeError> {
+    let stats = interpreter.heap().stats();
+    let mut map = LoxMap::new();
+    let mut insert = |key: &str, value: Value| map.insert(MapKey::String(key.into()), value);
+    insert("bytes_allocated", Value::Int(stats.bytes_allocated as i64));
+    insert("live_bytes", Value::Int(stats.live_bytes as i64));
+    insert("collections", Value::Int(stats.collections as i64));
+    insert(
+        "pause_ms",
+        Value::Number(stats.pause.as_secs_f64() * 1000.0),
+    );
+    Ok(interpreter.heap().map(map))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::environment::Environment;
+    use crate::parser::Parser;
+    use crate::scanner::Scanner;
+    use crate::stmt::Stmt;
+    use crate::test_util;
+    use std::io;
+
+    /// Runs `source` and returns the collector's stats after a collection.
+    fn stats_after(source: &str) -> GcStats {
+        let mut scanner = Scanner::new(source.to_string());
+        let tokens = scanner.scan_tokens().expect("scan error");
+        let program = Parser::new(tokens).parse().expect("parse error");
+        let mut interpreter = Interpreter::with_output(Box::new(io::sink()));
+        interpreter.interpret(&program).expect("runtime error");
+        interpreter.collect_garbage();
+        interpreter.heap().stats()
+    }
+
+    fn live_bytes_after(source: &str) -> usize {
+        stats_after(source).live_bytes
+    }
+
+    #[test]
+    fn test_unreachable_cycle_is_freed() {
+        let heap = Heap::new();
+        let list = heap.alloc(RefCell::new(Vec::new()));
+        list.borrow_mut().push(Value::List(Rc::clone(&list)));
+        let weak = Rc::downgrade(&list);
+        drop(list);
+        assert!(weak.upgrade().is_some());
+
+        heap.collect(|_| {});
+        assert!(weak.upgrade().is_none());
+        assert_eq!(heap.stats().collections, 1);
+        assert_eq!(heap.stats().live_bytes, 0);
+    }
+
+    #[test]
+    fn test_reachable_cycle_is_kept() {
+        let heap = Heap::new();
+        let a = heap.alloc(RefCell::new(Vec::new()));
+        let b = heap.alloc(RefCell::new(vec![Value::List(Rc::clone(&a))]));
+        a.borrow_mut().push(Value::List(Rc::clone(&b)));
+        drop(b);
+
+        heap.collect(|visit| visit(address(&a)));
+        let Value::List(b) = a.borrow()[0].clone() else {
+            panic!("expected a list");
+        };
+        assert!(matches!(&b.borrow()[0], Value::List(list) if Rc::ptr_eq(list, &a)));
+    }
+
+    #[test]
+    fn test_heap_is_empty_after_a_closure_cycle_is_dropped() {
+        let heap = Heap::new();
+        let mut scanner = Scanner::new("fun f() { return f; }".to_string());
+        let tokens = scanner.scan_tokens().expect("scan error");
+        let program = Parser::new(tokens).parse().expect("parse error");
+        let Stmt::Function(declaration) = &program.ast[program.statements[0]] else {
+            panic!("expected a function");
+        };
+        let scope = Rc::new(RefCell::new(Environment::new()));
+        let function = heap.alloc(LoxFunction::new(
+            Rc::clone(&program.ast),
+            Rc::clone(declaration),
+            Environment::capture(&scope, &heap),
+            false,
+        ));
+        scope.borrow_mut().define("f", Value::Function(function));
+        drop(scope);
+        assert_eq!(heap.len(), 2);
+
+        heap.collect(|_| {});
+        assert!(heap.is_empty());
+    }
+
+    #[test]
+    fn test_values_in_use_survive_collection() {
+        // Each of these holds a cycle only on the Rust stack while more Lox
+        // code runs, which collects before every statement under stress.
+        let (output, result) = test_util::run_with(
+            "fun cycle() { var list = []; list.push(list); return list; }
+             fun pair(a, b) { return [a, b]; }
+             print pair(cycle(), cycle())[0].len();
+             print [cycle(), cycle()][0].len();
+             print {1: cycle(), 2: cycle()}[1].len();
+             fun element(n) { return cycle(); }
+             print [1, 2].map(element)[0].len();
+             var list = [1, 2];
+             var count = 0;
+             for (element in list) { list = nil; cycle(); count = count + 1; }
+             print count;
+             fun f() { try { return cycle(); } finally { cycle(); } }
+             print f().len();",
+            |interpreter| interpreter.heap().set_stress(true),
+        );
+        assert!(result.is_ok(), "{:?}", result);
+        assert_eq!(output, "1\n1\n1\n1\n2\n1\n");
+    }
+
+    #[test]
+    fn test_interpreter_frees_cycles() {
+        let program = |count: usize| {
+            format!(
+                "class Node {{ init() {{ this.self = this; this.method = this.init; }} }}
+                 fun closure() {{ fun inner() {{}} var map = {{}}; map[1] = map; }}
+                 for (i in range({})) {{
+                     Node();
+                     closure();
+                     var list = [];
+                     list.push(list);
+                     {{ var f; {{ fun g() {{ return f; }} f = g; }} }}
+                 }}",
+                count
+            )
+        };
+        assert_eq!(
+            live_bytes_after(&program(1)),
+            live_bytes_after(&program(100))
+        );
+    }
+
+    #[test]
+    fn test_owned_buffers_are_counted() {
+        let heap = Heap::new();
+        let list = heap.alloc(RefCell::new(Vec::new()));
+        let roots = |visit: &mut dyn FnMut(Address)| visit(address(&list));
+        heap.collect(roots);
+        let empty = heap.stats().live_bytes;
+
+        list.borrow_mut().resize(100, Value::Nil);
+        heap.collect(roots);
+        let buffer = 100 * mem::size_of::<Value>();
+        assert!(heap.stats().live_bytes >= empty + buffer);
+        assert!(heap.stats().bytes_allocated >= empty + buffer);
+    }
+
+    #[test]
+    fn test_scopes_are_only_tracked_once_captured() {
+        let program = |count: usize| {
+            format!(
+                "fun f(a) {{ {{ var b = a; }} }}
+                 for (i in range({})) f(i);",
+                count
+            )
+        };
+        assert_eq!(
+            stats_after(&program(1)).bytes_allocated,
+            stats_after(&program(100)).bytes_allocated
+        );
+    }
+}
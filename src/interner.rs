@@ -0,0 +1,237 @@
+//! Deduplicates identifiers and string literals, so that every name with the
+//! same text shares one allocation.
+//!
+//! Only source text is interned, when it is scanned. Two symbols are
+//! therefore equal exactly when they are the same allocation, so comparing
+//! them, and hashing them to look up a variable, only looks at a pointer.
+//! Strings built at runtime are plain `Rc<str>`s: most are used once, and
+//! interning them would mean hashing every one.
+//!
+//! There is one interner per thread. Values are reference counted and never
+//! leave the thread that made them, so every interpreter and scanner on a
+//! thread shares it, and the symbols they make can be compared with each
+//! other. A string is removed from the interner when the last symbol for it
+//! is dropped, so loading many modules doesn't accumulate their names.
+
+use std::borrow::Borrow;
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::hash::{BuildHasherDefault, Hash, Hasher};
+use std::ops::Deref;
+use std::rc::Rc;
+
+/// A shared, immutable, interned string. Cloning one only bumps a reference
+/// count, and comparing or hashing one only looks at its address.
+#[derive(Clone)]
+pub struct Symbol(Rc<str>);
+
+/// A map keyed by symbols, hashed by address.
+pub type SymbolMap<V> = HashMap<Symbol, V, BuildHasherDefault<SymbolHasher>>;
+
+impl Symbol {
+    /// The symbol for `string`, allocating it the first time it's seen.
+    pub fn intern(string: &str) -> Symbol {
+        INTERNER.with(|interner| interner.borrow_mut().intern(string))
+    }
+
+    /// The address identifying the symbol.
+    pub fn as_ptr(&self) -> *const u8 {
+        Rc::as_ptr(&self.0).cast()
+    }
+}
+
+/// The number of distinct strings currently interned on this thread.
+pub fn len() -> usize {
+    INTERNER.with(|interner| interner.borrow().strings.len())
+}
+
+impl Drop for Symbol {
+    fn drop(&mut self) {
+        // The interner holds one reference, so this is the last symbol for
+        // the string. The interner may already be gone if the thread is
+        // exiting, or borrowed if it is dropping a symbol itself.
+        if Rc::strong_count(&self.0) == 2 {
+            let _ = INTERNER.try_with(|interner| {
+                if let Ok(mut interner) = interner.try_borrow_mut() {
+                    interner.strings.remove(&*self.0);
+                }
+            });
+        }
+    }
+}
+
+impl Deref for Symbol {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl AsRef<str> for Symbol {
+    fn as_ref(&self) -> &str {
+        self
+    }
+}
+
+impl PartialEq for Symbol {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for Symbol {}
+
+impl Hash for Symbol {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (self.as_ptr() as usize).hash(state);
+    }
+}
+
+/// Symbols order by their text, for sorting.
+impl Ord for Symbol {
+    fn cmp(&self, other: &Self) -> Ordering {
+        if self == other {
+            return Ordering::Equal;
+        }
+        (**self).cmp(&**other)
+    }
+}
+
+impl PartialOrd for Symbol {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&**self, f)
+    }
+}
+
+impl From<&str> for Symbol {
+    fn from(string: &str) -> Self {
+        Symbol::intern(string)
+    }
+}
+
+impl From<String> for Symbol {
+    fn from(string: String) -> Self {
+        Symbol::intern(&string)
+    }
+}
+
+/// Hashes a symbol's address. Addresses are already unique, so this only
+/// has to spread the bits that vary between allocations over the whole
+/// hash, rather than resist collisions like the default hasher.
+#[derive(Default)]
+pub struct SymbolHasher(u64);
+
+impl Hasher for SymbolHasher {
+    fn finish(&self) -> u64 {
+        self.0 ^ (self.0 >> 32)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.write_u64(u64::from(byte));
+        }
+    }
+
+    fn write_usize(&mut self, n: usize) {
+        self.write_u64(n as u64);
+    }
+
+    fn write_u64(&mut self, n: u64) {
+        self.0 = (self.0.rotate_left(5) ^ n).wrapping_mul(0x517c_c1b7_2722_0a95);
+    }
+}
+
+/// An interned string, looked up by its text.
+struct Entry(Rc<str>);
+
+impl Borrow<str> for Entry {
+    fn borrow(&self) -> &str {
+        &self.0
+    }
+}
+
+impl PartialEq for Entry {
+    fn eq(&self, other: &Self) -> bool {
+        *self.0 == *other.0
+    }
+}
+
+impl Eq for Entry {}
+
+impl Hash for Entry {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (*self.0).hash(state);
+    }
+}
+
+#[derive(Default)]
+struct Interner {
+    strings: HashSet<Entry>,
+}
+
+impl Interner {
+    fn intern(&mut self, string: &str) -> Symbol {
+        if let Some(entry) = self.strings.get(string) {
+            return Symbol(Rc::clone(&entry.0));
+        }
+        let text: Rc<str> = Rc::from(string);
+        self.strings.insert(Entry(Rc::clone(&text)));
+        Symbol(text)
+    }
+}
+
+thread_local! {
+    static INTERNER: RefCell<Interner> = RefCell::new(Interner::default());
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_intern_shares_allocation() {
+        let a = Symbol::intern("name");
+        let b = Symbol::from(String::from("name"));
+        let c = Symbol::intern("other");
+        assert_eq!(a, b);
+        assert_eq!(a.as_ptr(), b.as_ptr());
+        assert_ne!(a, c);
+        assert_eq!(&*c, "other");
+        assert!(a < c);
+    }
+
+    #[test]
+    fn test_unused_strings_are_released() {
+        let before = len();
+        let a = Symbol::intern("test_unused_strings_are_released");
+        let b = a.clone();
+        assert_eq!(len(), before + 1);
+        drop(a);
+        assert_eq!(len(), before + 1);
+        drop(b);
+        assert_eq!(len(), before);
+    }
+
+    #[test]
+    fn test_runtime_strings_are_not_interned() {
+        let before = len();
+        let value = crate::expr::Value::from("test_runtime_strings_are_not_interned");
+        assert_eq!(len(), before);
+        drop(value);
+    }
+}
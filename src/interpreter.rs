@@ -1,109 +1,2016 @@
-use crate::expr::{Expr, Value};
-use crate::lox;
+use crate::ast::{Ast, ExprId, Program, StmtId};
+use crate::class::{LoxClass, LoxInstance};
+use crate::environment::Environment;
+use crate::exception;
+use crate::expr::{compare_numbers, exact_integer, Expr, Value};
+use crate::function::{LoxFunction, NativeFn, NativeFunction};
+use crate::gc::{self, Address, Heap, Trace};
+use crate::interner::{Symbol, SymbolMap};
+use crate::iterator::{self, LoxIterator};
+use crate::json;
+use crate::list;
+use crate::lox::{Code, Frame, RuntimeError};
+use crate::map::{self, LoxMap};
+use crate::math;
+use crate::module::{self, Module};
+use crate::parser::Parser;
+use crate::random::{self, Rng};
+use crate::scanner::Scanner;
+use crate::stmt::{Imported, Stmt};
+use crate::string;
+use crate::system::{self, Capabilities, Capability};
+use crate::time;
 use crate::token::Token;
 use crate::token_type::TokenType;
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Write};
+use std::mem;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::Instant;
 
-pub struct Interpreter {}
+/// Lox calls nested deeper than this raise `RuntimeError::StackOverflow`
+/// instead of exhausting the host stack.
+const MAX_CALL_DEPTH: usize = 255;
+
+pub struct Interpreter {
+    environment: Rc<RefCell<Environment>>,
+    /// The environments of the blocks, calls and modules that are running,
+    /// other than the current one, innermost last.
+    scopes: Vec<Rc<RefCell<Environment>>>,
+    /// Values held on the Rust stack while more Lox code runs, such as a
+    /// call's arguments, which the collector must keep. See `root`.
+    roots: Vec<Value>,
+    /// The arena of the code being run: the script's, a module's or that of
+    /// the function being called.
+    ast: Rc<Ast>,
+    /// Active calls and imports, outermost first. Each frame records the
+    /// function or module being run and the line it was called or imported
+    /// from.
+    frames: Vec<Frame>,
+    /// Everything the script allocates, except globals and modules.
+    heap: Heap,
+    output: Box<dyn Write>,
+    /// The class of error objects, see `exception`.
+    error_class: Rc<LoxClass>,
+    /// Globals defined by `define_global`, which every module starts with.
+    natives: Vec<(&'static str, Value)>,
+    /// The generator behind the `random` module.
+    rng: Rng,
+    /// What the natives in `system` may touch.
+    capabilities: Capabilities,
+    /// Where `read_line()` reads from.
+    input: Box<dyn BufRead>,
+    /// The arguments `args()` returns.
+    args: Vec<String>,
+    /// When the interpreter was created, which `clock()` counts from.
+    started: Instant,
+    /// Directories searched for imports after the importing file's own.
+    search_path: Vec<PathBuf>,
+    /// Modules that have finished loading, by canonical path.
+    modules: HashMap<PathBuf, Rc<Module>>,
+    /// The files being run, outermost first: each one's canonical path and
+    /// the name it was imported by.
+    loading: Vec<(PathBuf, String)>,
+    /// `this` and `init`, interned once since method calls look them up.
+    this: Symbol,
+    init: Symbol,
+}
+
+/// Why execution of a statement stopped early.
+enum Unwind {
+    Error(RuntimeError),
+    Return(Value),
+    /// `break`, with the label of the loop it targets, if any.
+    Break(Option<Symbol>),
+    /// `continue`, with the label of the loop it targets, if any.
+    Continue(Option<Symbol>),
+}
+
+impl Unwind {
+    /// The value being carried out, which code run on the way out, such as
+    /// a `finally` block, mustn't let the collector clear.
+    fn value(&self) -> Option<&Value> {
+        match self {
+            Unwind::Error(RuntimeError::Thrown { value, .. }) | Unwind::Return(value) => {
+                Some(value)
+            }
+            _ => None,
+        }
+    }
+}
+
+impl From<RuntimeError> for Unwind {
+    fn from(error: RuntimeError) -> Self {
+        Unwind::Error(error)
+    }
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 impl Interpreter {
     pub fn new() -> Self {
-        Interpreter {}
+        Self::with_output(Box::new(io::stdout()))
+    }
+
+    /// Creates an interpreter whose `print` statements write to `output`.
+    pub fn with_output(output: Box<dyn Write>) -> Self {
+        let mut interpreter = Interpreter {
+            environment: Rc::new(RefCell::new(Environment::new())),
+            scopes: Vec::new(),
+            roots: Vec::new(),
+            ast: Rc::new(Ast::new()),
+            frames: Vec::new(),
+            heap: Heap::new(),
+            output,
+            error_class: Rc::new(exception::error_class()),
+            natives: Vec::new(),
+            rng: Rng::from_time(),
+            capabilities: Capabilities::default(),
+            input: Box::new(BufReader::new(io::stdin())),
+            args: Vec::new(),
+            started: Instant::now(),
+            search_path: Vec::new(),
+            modules: HashMap::new(),
+            loading: Vec::new(),
+            this: Symbol::intern("this"),
+            init: Symbol::intern("init"),
+        };
+        interpreter.define_native("range", 1..=3, iterator::range);
+        interpreter.define_native("Error", 1..=1, exception::error);
+        math::define(&mut interpreter);
+        random::define(&mut interpreter);
+        system::define(&mut interpreter);
+        time::define(&mut interpreter);
+        json::define(&mut interpreter);
+        gc::define(&mut interpreter);
+        interpreter
+    }
+
+    /// Defines a global that every module can see, such as a native
+    /// function or native module.
+    pub fn define_global(&mut self, name: &'static str, value: Value) {
+        self.environment.borrow_mut().define(name, value.clone());
+        self.natives.push((name, value));
+    }
+
+    /// Defines a global function implemented in Rust.
+    pub fn define_native(
+        &mut self,
+        name: &'static str,
+        arity: RangeInclusive<usize>,
+        function: NativeFn,
+    ) {
+        let native = Value::Native(Rc::new(NativeFunction::new(name, arity, function)));
+        self.define_global(name, native);
+    }
+
+    /// Defines a global module whose members are implemented in Rust, read
+    /// as `name.member` like those of an imported module.
+    pub fn define_native_module(
+        &mut self,
+        name: &'static str,
+        members: Vec<(&'static str, Value)>,
+    ) {
+        let mut globals = Environment::new();
+        for (member, value) in members {
+            globals.define(member, value);
+        }
+        let module = Module {
+            name: name.to_string(),
+            globals: Rc::new(RefCell::new(globals)),
+        };
+        self.define_global(name, Value::Module(Rc::new(module)));
+    }
+
+    /// Seeds the generator behind the `random` module, so that a run can be
+    /// reproduced.
+    pub fn seed_random(&mut self, seed: u64) {
+        self.rng = Rng::new(seed);
+    }
+
+    pub fn heap(&self) -> &Heap {
+        &self.heap
+    }
+
+    /// Frees every cycle of objects that the script can no longer reach.
+    pub fn collect_garbage(&self) {
+        self.heap.collect(|visit| self.trace_roots(visit));
+    }
+
+    /// Visits the collector's roots: the environments of the code that is
+    /// running and of loaded modules, and the rooted values.
+    fn trace_roots(&self, visit: &mut dyn FnMut(Address)) {
+        Environment::trace_roots(&self.environment, visit);
+        for scope in &self.scopes {
+            Environment::trace_roots(scope, visit);
+        }
+        for module in self.modules.values() {
+            Environment::trace_roots(&module.globals, visit);
+        }
+        for value in &self.roots {
+            value.trace(visit);
+        }
+    }
+
+    /// Keeps `value` alive through collections until the expression or
+    /// statement that rooted it, within `with_roots`, finishes. Natives that
+    /// hold values while calling back into Lox root them too.
+    pub(crate) fn root(&mut self, value: &Value) {
+        if value.is_object() {
+            self.roots.push(value.clone());
+        }
+    }
+
+    /// Runs `f`, releasing whatever it roots once it returns.
+    fn with_roots<T>(&mut self, f: impl FnOnce(&mut Self) -> T) -> T {
+        let len = self.roots.len();
+        let result = f(self);
+        self.roots.truncate(len);
+        result
+    }
+
+    pub(crate) fn rng(&mut self) -> &mut Rng {
+        &mut self.rng
+    }
+
+    /// Grants the script access to files or the environment.
+    pub fn set_capabilities(&mut self, capabilities: Capabilities) {
+        self.capabilities = capabilities;
+    }
+
+    pub(crate) fn capabilities(&self) -> Capabilities {
+        self.capabilities
+    }
+
+    /// Sets where `read_line()` reads from, standard input by default.
+    pub fn set_input(&mut self, input: Box<dyn BufRead>) {
+        self.input = input;
+    }
+
+    pub(crate) fn input(&mut self) -> &mut dyn BufRead {
+        &mut *self.input
+    }
+
+    /// Sets the arguments the script sees through `args()`.
+    pub fn set_args(&mut self, args: Vec<String>) {
+        self.args = args;
+    }
+
+    pub(crate) fn args(&self) -> &[String] {
+        &self.args
+    }
+
+    pub(crate) fn started(&self) -> Instant {
+        self.started
+    }
+
+    /// Sets the file being run, which relative imports are resolved against.
+    /// Without one, they are resolved against the working directory.
+    pub fn set_script(&mut self, path: impl AsRef<Path>) {
+        let path = path.as_ref();
+        let name = path.file_name().map_or_else(
+            || path.display().to_string(),
+            |name| name.to_string_lossy().into_owned(),
+        );
+        let file = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        self.loading = vec![(file, name)];
+    }
+
+    /// Adds a directory to search for imports that aren't found next to the
+    /// importing file.
+    pub fn add_search_path(&mut self, dir: impl Into<PathBuf>) {
+        self.search_path.push(dir.into());
+    }
+
+    pub fn interpret(&mut self, program: &Program) -> Result<(), RuntimeError> {
+        let enclosing = mem::replace(&mut self.ast, Rc::clone(&program.ast));
+        let result =
+            program
+                .statements
+                .iter()
+                .try_for_each(|&statement| match self.execute(statement) {
+                    Ok(()) => Ok(()),
+                    Err(Unwind::Error(error)) => Err(error),
+                    // The parser rejects `return` outside of a function, and
+                    // `break` and `continue` outside of a loop.
+                    Err(Unwind::Return(_) | Unwind::Break(_) | Unwind::Continue(_)) => {
+                        unreachable!()
+                    }
+                });
+        self.ast = enclosing;
+        result
+    }
+
+    fn execute(&mut self, id: StmtId) -> Result<(), Unwind> {
+        if self.heap.collection_due() {
+            self.collect_garbage();
+        }
+        let ast = Rc::clone(&self.ast);
+        match &ast[id] {
+            Stmt::Expression(expr) => {
+                self.evaluate(*expr)?;
+            }
+            Stmt::Print(expr) => {
+                let value = self.evaluate(*expr)?;
+                // A closed stdout is not the script's fault, so don't fail it.
+                let _ = writeln!(self.output, "{}", value);
+            }
+            Stmt::Var(name, initializer) => {
+                let value = match initializer {
+                    Some(expr) => self.evaluate(*expr)?,
+                    None => Value::Nil,
+                };
+                self.environment
+                    .borrow_mut()
+                    .define(name.name().clone(), value);
+            }
+            Stmt::Block(statements) => {
+                let environment = Environment::new_enclosed(Rc::clone(&self.environment));
+                self.execute_block(statements, Rc::new(RefCell::new(environment)))?;
+            }
+            Stmt::Function(declaration) => {
+                let function = self.heap.alloc(LoxFunction::new(
+                    Rc::clone(&self.ast),
+                    Rc::clone(declaration),
+                    Environment::capture(&self.environment, &self.heap),
+                    false,
+                ));
+                self.environment
+                    .borrow_mut()
+                    .define(declaration.name.name().clone(), Value::Function(function));
+            }
+            Stmt::Return(_, value) => {
+                let value = match value {
+                    Some(expr) => self.evaluate(*expr)?,
+                    None => Value::Nil,
+                };
+                return Err(Unwind::Return(value));
+            }
+            Stmt::Class(name, declarations) => {
+                let methods: SymbolMap<Rc<LoxFunction>> = declarations
+                    .iter()
+                    .map(|declaration| {
+                        let method = LoxFunction::new(
+                            Rc::clone(&self.ast),
+                            Rc::clone(declaration),
+                            Environment::capture(&self.environment, &self.heap),
+                            *declaration.name.name() == self.init,
+                        );
+                        (declaration.name.name().clone(), self.heap.alloc(method))
+                    })
+                    .collect();
+                let class = self.heap.alloc(LoxClass::new(name.name().clone(), methods));
+                self.environment
+                    .borrow_mut()
+                    .define(name.name().clone(), Value::Class(class));
+            }
+            Stmt::If(condition, then_branch, else_branch) => {
+                if bool::from(&self.evaluate(*condition)?) {
+                    self.execute(*then_branch)?;
+                } else if let Some(else_branch) = else_branch {
+                    self.execute(*else_branch)?;
+                }
+            }
+            Stmt::While(label, condition, body, increment) => {
+                while bool::from(&self.evaluate(*condition)?) {
+                    let result = self.execute(*body);
+                    if !self.loop_continues(label, result)? {
+                        break;
+                    }
+                    if let Some(increment) = increment {
+                        self.evaluate(*increment)?;
+                    }
+                }
+            }
+            Stmt::ForIn(label, name, iterable, body) => {
+                self.with_roots(|interpreter| {
+                    let iterable = interpreter.evaluate(*iterable)?;
+                    interpreter.root(&iterable);
+                    let mut iterator = LoxIterator::new(interpreter, name, &iterable)?;
+                    while let Some(element) = iterator.next(interpreter, name)? {
+                        let mut environment =
+                            Environment::new_enclosed(Rc::clone(&interpreter.environment));
+                        environment.define(name.name().clone(), element);
+                        let result = interpreter.execute_block(
+                            std::slice::from_ref(body),
+                            Rc::new(RefCell::new(environment)),
+                        );
+                        if !interpreter.loop_continues(label, result)? {
+                            break;
+                        }
+                    }
+                    Ok::<_, Unwind>(())
+                })?;
+            }
+            Stmt::Throw(keyword, value) => {
+                let value = self.evaluate(*value)?;
+                let trace = self.trace(keyword);
+                exception::set_location(self, &value, keyword, &trace);
+                return Err(Unwind::Error(RuntimeError::Thrown {
+                    keyword: keyword.clone(),
+                    value,
+                    trace,
+                }));
+            }
+            Stmt::Try(body, catch, finally) => {
+                let environment = Environment::new_enclosed(Rc::clone(&self.environment));
+                let mut result = self.execute_block(body, Rc::new(RefCell::new(environment)));
+
+                if let Some((name, handler)) = catch {
+                    result = match result {
+                        Err(Unwind::Error(error)) if error.is_catchable() => {
+                            let mut environment =
+                                Environment::new_enclosed(Rc::clone(&self.environment));
+                            environment
+                                .define(name.name().clone(), exception::caught_value(self, error));
+                            self.execute_block(handler, Rc::new(RefCell::new(environment)))
+                        }
+                        result => result,
+                    };
+                }
+
+                // The `finally` block runs however the rest exited. An early
+                // exit from it replaces the pending one.
+                if let Some(finally) = finally {
+                    let environment = Environment::new_enclosed(Rc::clone(&self.environment));
+                    self.with_roots(|interpreter| {
+                        if let Some(value) = result.as_ref().err().and_then(Unwind::value) {
+                            interpreter.root(value);
+                        }
+                        interpreter.execute_block(finally, Rc::new(RefCell::new(environment)))
+                    })?;
+                }
+                result?;
+            }
+            Stmt::Import(keyword, path, imported) => {
+                let module = self.import(keyword, path)?;
+                match imported {
+                    Imported::Module(name) => {
+                        self.environment
+                            .borrow_mut()
+                            .define(name.name().clone(), Value::Module(module));
+                    }
+                    Imported::Names(names) => {
+                        for name in names {
+                            let value =
+                                self.get_property(&Value::Module(Rc::clone(&module)), name)?;
+                            self.environment
+                                .borrow_mut()
+                                .define(name.name().clone(), value);
+                        }
+                    }
+                }
+            }
+            Stmt::Break(_, label) => {
+                return Err(Unwind::Break(label.as_ref().map(|l| l.name().clone())));
+            }
+            Stmt::Continue(_, label) => {
+                return Err(Unwind::Continue(label.as_ref().map(|l| l.name().clone())));
+            }
+        }
+        Ok(())
+    }
+
+    /// Loads the module at `path`, or returns it from the cache if it has
+    /// already been loaded. Importing needs the import capability, and
+    /// errors in the module don't quote its source.
+    fn import(&mut self, keyword: &Token, path: &str) -> Result<Rc<Module>, RuntimeError> {
+        system::require(self, keyword, "import", Capability::Import)?;
+        let base = match self.loading.last() {
+            Some((file, _)) => file.parent().unwrap_or(Path::new(".")).to_path_buf(),
+            None => PathBuf::from("."),
+        };
+        let Some(file) = module::resolve(path, &base, &self.search_path) else {
+            return Err(self.import_failed(keyword, format!("Can't find module {:?}", path)));
+        };
+        if let Some(module) = self.modules.get(&file) {
+            return Ok(Rc::clone(module));
+        }
+        if self.loading.iter().any(|(loading, _)| *loading == file) {
+            let chain = self.loading.iter().map(|(_, name)| name.clone());
+            return Err(RuntimeError::ImportCycle {
+                keyword: keyword.clone(),
+                chain: chain.chain([path.to_string()]).collect(),
+                trace: self.trace(keyword),
+            });
+        }
+
+        let source = std::fs::read_to_string(&file).map_err(|error| {
+            self.import_failed(keyword, format!("Can't read module {:?}: {}", path, error))
+        })?;
+        let mut scanner = Scanner::new(source);
+        let tokens = scanner.scan_tokens().map_err(|error| {
+            self.import_failed(
+                keyword,
+                format!(
+                    "Error in module {:?}: [line {}] Error: {}",
+                    path, error.line, error.message
+                ),
+            )
+        })?;
+        let program = Parser::new(tokens).parse().map_err(|errors| {
+            let errors: Vec<_> = errors
+                .iter()
+                .map(|error| format!("[line {}] Error: {}", error.line(), error.message()))
+                .collect();
+            self.import_failed(
+                keyword,
+                format!("Error in module {:?}: {}", path, errors.join("\n")),
+            )
+        })?;
+
+        let mut globals = Environment::new();
+        for (name, native) in &self.natives {
+            globals.define(*name, native.clone());
+        }
+        let module = Rc::new(Module {
+            name: path.to_string(),
+            globals: Rc::new(RefCell::new(globals)),
+        });
+
+        self.loading.push((file.clone(), path.to_string()));
+        self.frames.push(Frame {
+            code: Code::Module(path.to_string()),
+            line: keyword.line,
+        });
+        let enclosing = mem::replace(&mut self.environment, Rc::clone(&module.globals));
+        self.scopes.push(Rc::clone(&enclosing));
+        let result = self.interpret(&program);
+        self.scopes.pop();
+        self.environment = enclosing;
+        self.frames.pop();
+        self.loading.pop();
+
+        result?;
+        self.modules.insert(file, Rc::clone(&module));
+        Ok(module)
+    }
+
+    fn import_failed(&self, keyword: &Token, message: String) -> RuntimeError {
+        RuntimeError::ImportFailed {
+            keyword: keyword.clone(),
+            message,
+            trace: self.trace(keyword),
+        }
+    }
+
+    /// Decides what a loop labeled `label` does after running its body:
+    /// `Ok(true)` to go on to the next iteration, `Ok(false)` to stop, or an
+    /// error to keep unwinding past the loop.
+    fn loop_continues(
+        &self,
+        label: &Option<Token>,
+        result: Result<(), Unwind>,
+    ) -> Result<bool, Unwind> {
+        let targets_this_loop = |target: &Option<Symbol>| match target {
+            None => true,
+            Some(target) => label.as_ref().is_some_and(|label| label.name() == target),
+        };
+        match result {
+            Ok(()) => Ok(true),
+            Err(Unwind::Break(target)) if targets_this_loop(&target) => Ok(false),
+            Err(Unwind::Continue(target)) if targets_this_loop(&target) => Ok(true),
+            Err(unwind) => Err(unwind),
+        }
+    }
+
+    /// Runs `statements` in `environment`, restoring the current environment
+    /// afterwards however the block exits.
+    fn execute_block(
+        &mut self,
+        statements: &[StmtId],
+        environment: Rc<RefCell<Environment>>,
+    ) -> Result<(), Unwind> {
+        let previous = std::mem::replace(&mut self.environment, environment);
+        self.scopes.push(Rc::clone(&previous));
+        let result = statements.iter().try_for_each(|&stmt| self.execute(stmt));
+        self.scopes.pop();
+        self.environment = previous;
+        result
+    }
+
+    /// Evaluates an expression from `Parser::parse_expression` in the
+    /// global scope.
+    pub fn evaluate_expression(
+        &mut self,
+        ast: &Rc<Ast>,
+        expr: ExprId,
+    ) -> Result<Value, RuntimeError> {
+        let enclosing = mem::replace(&mut self.ast, Rc::clone(ast));
+        let result = self.evaluate(expr);
+        self.ast = enclosing;
+        result
     }
 
-    pub fn evaluate(expr: &Expr) -> Result<Value, lox::RuntimeError> {
-        match expr {
-            Expr::Literal(Value) => Ok(Value.clone()),
-            Expr::Grouping(expr) => Self::evaluate(expr),
+    fn evaluate(&mut self, id: ExprId) -> Result<Value, RuntimeError> {
+        let ast = Rc::clone(&self.ast);
+        match &ast[id] {
+            Expr::Literal(value) => Ok(value.clone()),
+            Expr::Grouping(expr) => self.evaluate(*expr),
             Expr::Unary(token, expr) => {
-                let val = Self::evaluate(expr)?;
+                let val = self.evaluate(*expr)?;
                 match token.type_ {
-                    TokenType::Bang => {
-                        if !bool::try_from(val)? {
-                            Ok(Value::True)
-                        } else {
-                            Ok(Value::False)
+                    TokenType::Bang => Ok(Value::from(!bool::from(&val))),
+                    TokenType::Minus => match val {
+                        Value::Int(n) => n.checked_neg().map(Value::Int).ok_or_else(|| {
+                            RuntimeError::IntegerOverflow {
+                                op: token.clone(),
+                                trace: self.trace(token),
+                            }
+                        }),
+                        _ => Ok(Value::Number(-self.number_operand(token, &val)?)),
+                    },
+                    TokenType::Tilde => match val {
+                        Value::Int(n) => Ok(Value::Int(!n)),
+                        _ => {
+                            let n = self.number_operand(token, &val)?;
+                            Ok(Value::Int(!self.integer_operand(token, n)?))
                         }
+                    },
+                    _ => unreachable!("parser only produces '!', '-' and '~' unary operators"),
+                }
+            }
+            Expr::Binary(left, token, right) => self.evaluate_binary(*left, token, *right),
+            Expr::Variable(name) => self.environment.borrow().get(name.name()).ok_or_else(|| {
+                RuntimeError::UndefinedVariable {
+                    name: name.clone(),
+                    trace: self.trace(name),
+                }
+            }),
+            Expr::Assign(name, value) => {
+                let value = self.evaluate(*value)?;
+                if !self
+                    .environment
+                    .borrow_mut()
+                    .assign(name.name(), value.clone())
+                {
+                    return Err(RuntimeError::UndefinedVariable {
+                        name: name.clone(),
+                        trace: self.trace(name),
+                    });
+                }
+                Ok(value)
+            }
+            Expr::List(elements) => {
+                let elements = self.with_roots(|interpreter| {
+                    elements
+                        .iter()
+                        .map(|&element| interpreter.evaluate_rooted(element))
+                        .collect::<Result<Vec<_>, _>>()
+                })?;
+                Ok(self.heap.list(elements))
+            }
+            Expr::Map(entries) => {
+                let map = self.with_roots(|interpreter| {
+                    let mut map = LoxMap::new();
+                    for (key, colon, value) in entries {
+                        let key = interpreter.evaluate(*key)?;
+                        let key = map::key(interpreter, colon, &key)?;
+                        map.insert(key, interpreter.evaluate_rooted(*value)?);
+                    }
+                    Ok::<_, RuntimeError>(map)
+                })?;
+                Ok(self.heap.map(map))
+            }
+            Expr::Call(..) | Expr::Get(..) | Expr::OptionalGet(..) | Expr::Index(..) => {
+                Ok(self.evaluate_chain(id)?.unwrap_or(Value::Nil))
+            }
+            Expr::Set(object, name, value) => {
+                let object = self.evaluate(*object)?;
+                let Value::Instance(instance) = &object else {
+                    return Err(RuntimeError::UndefinedProperty {
+                        name: name.clone(),
+                        receiver_type: object.type_name(),
+                        trace: self.trace(name),
+                    });
+                };
+                let value = self.with_roots(|interpreter| {
+                    interpreter.root(&object);
+                    interpreter.evaluate(*value)
+                })?;
+                instance
+                    .borrow_mut()
+                    .set(name.name().clone(), value.clone());
+                Ok(value)
+            }
+            Expr::IndexSet(object, bracket, index, value) => {
+                let (object, index, value) = self.with_roots(|interpreter| {
+                    let object = interpreter.evaluate_rooted(*object)?;
+                    let index = interpreter.evaluate_rooted(*index)?;
+                    let value = interpreter.evaluate(*value)?;
+                    Ok::<_, RuntimeError>((object, index, value))
+                })?;
+                match &object {
+                    Value::List(list) => {
+                        let position = self.list_index(bracket, &list.borrow(), &index)?;
+                        list.borrow_mut()[position] = value.clone();
+                    }
+                    Value::Map(map) => {
+                        let key = map::key(self, bracket, &index)?;
+                        map.borrow_mut().insert(key, value.clone());
                     }
-                    TokenType::Minus => Ok(Value::Number(-f64::try_from(val)?)),
-                    _ => Err(lox::RuntimeError {
-                        message: String::from("Invalid token?"),
-                    }),
+                    _ => return Err(self.not_indexable(bracket, &object)),
+                }
+                Ok(value)
+            }
+            Expr::This(keyword) => self.environment.borrow().get(&self.this).ok_or_else(|| {
+                RuntimeError::UndefinedVariable {
+                    name: keyword.clone(),
+                    trace: self.trace(keyword),
+                }
+            }),
+            Expr::Logical(left, token, right) => {
+                let left_val = self.evaluate(*left)?;
+                match token.type_ {
+                    TokenType::QuestionQuestion if left_val != Value::Nil => Ok(left_val),
+                    TokenType::QuestionQuestion => self.evaluate(*right),
+                    TokenType::Or if bool::from(&left_val) => Ok(left_val),
+                    TokenType::And if !bool::from(&left_val) => Ok(left_val),
+                    TokenType::Or | TokenType::And => self.evaluate(*right),
+                    _ => unreachable!("logical operator without an infix rule"),
+                }
+            }
+            Expr::Conditional(condition, then_branch, else_branch) => {
+                if bool::from(&self.evaluate(*condition)?) {
+                    self.evaluate(*then_branch)
+                } else {
+                    self.evaluate(*else_branch)
                 }
             }
-            Expr::Binary(left, token, right) => Self::evaluate_binary(left, token, right),
         }
     }
 
+    /// Evaluates `id` and roots the result, for an expression that holds on
+    /// to it while evaluating its other operands.
+    fn evaluate_rooted(&mut self, id: ExprId) -> Result<Value, RuntimeError> {
+        let value = self.evaluate(id)?;
+        self.root(&value);
+        Ok(value)
+    }
+
+    /// Evaluates a chain of property accesses and calls. Returns `None` once
+    /// a `?.` link finds `nil`, which skips every later link in the chain.
+    fn evaluate_chain(&mut self, id: ExprId) -> Result<Option<Value>, RuntimeError> {
+        let ast = Rc::clone(&self.ast);
+        match &ast[id] {
+            Expr::Call(callee, paren, arguments) => {
+                let Some(callee) = self.evaluate_chain(*callee)? else {
+                    return Ok(None);
+                };
+                self.with_roots(|interpreter| {
+                    interpreter.root(&callee);
+                    let arguments = arguments
+                        .iter()
+                        .map(|&argument| interpreter.evaluate_rooted(argument))
+                        .collect::<Result<Vec<_>, _>>()?;
+                    interpreter.call(callee, paren, arguments).map(Some)
+                })
+            }
+            Expr::Get(object, name) => {
+                let Some(object) = self.evaluate_chain(*object)? else {
+                    return Ok(None);
+                };
+                self.get_property(&object, name).map(Some)
+            }
+            Expr::OptionalGet(object, name) => match self.evaluate_chain(*object)? {
+                None | Some(Value::Nil) => Ok(None),
+                Some(object) => self.get_property(&object, name).map(Some),
+            },
+            Expr::Index(object, bracket, index) => {
+                let Some(object) = self.evaluate_chain(*object)? else {
+                    return Ok(None);
+                };
+                let index = self.with_roots(|interpreter| {
+                    interpreter.root(&object);
+                    interpreter.evaluate(*index)
+                })?;
+                match &object {
+                    Value::List(list) => {
+                        let list = list.borrow();
+                        let position = self.list_index(bracket, &list, &index)?;
+                        Ok(Some(list[position].clone()))
+                    }
+                    Value::Map(map) => {
+                        let key = map::key(self, bracket, &index)?;
+                        match map.borrow().get(&key) {
+                            Some(value) => Ok(Some(value.clone())),
+                            None => Err(RuntimeError::MissingKey {
+                                bracket: bracket.clone(),
+                                key,
+                                trace: self.trace(bracket),
+                            }),
+                        }
+                    }
+                    _ => Err(self.not_indexable(bracket, &object)),
+                }
+            }
+            _ => self.evaluate(id).map(Some),
+        }
+    }
+
+    /// Resolves `index` to a position in `list`. Negative indices count from
+    /// the end.
+    fn list_index(
+        &self,
+        bracket: &Token,
+        list: &[Value],
+        index: &Value,
+    ) -> Result<usize, RuntimeError> {
+        let Value::Int(index) = *index else {
+            return Err(RuntimeError::InvalidIndex {
+                bracket: bracket.clone(),
+                index_type: index.type_name(),
+                trace: self.trace(bracket),
+            });
+        };
+        list::resolve_index(index, list.len()).ok_or_else(|| RuntimeError::IndexOutOfBounds {
+            bracket: bracket.clone(),
+            index,
+            len: list.len(),
+            trace: self.trace(bracket),
+        })
+    }
+
+    fn not_indexable(&self, bracket: &Token, object: &Value) -> RuntimeError {
+        RuntimeError::NotIndexable {
+            bracket: bracket.clone(),
+            receiver_type: object.type_name(),
+            trace: self.trace(bracket),
+        }
+    }
+
+    pub(crate) fn get_property(&self, object: &Value, name: &Token) -> Result<Value, RuntimeError> {
+        let property =
+            match object {
+                Value::Instance(instance) => LoxInstance::get(instance, name.name(), &self.heap),
+                Value::List(list) => list::method(list, name.name())
+                    .map(|method| Value::Native(self.heap.alloc(method))),
+                Value::Map(map) => map::method(map, name.name())
+                    .map(|method| Value::Native(self.heap.alloc(method))),
+                Value::Module(module) => module.globals.borrow().get(name.name()),
+                Value::String(s) => string::method(s, name.name())
+                    .map(|method| Value::Native(self.heap.alloc(method))),
+                _ => None,
+            };
+        property.ok_or_else(|| RuntimeError::UndefinedProperty {
+            name: name.clone(),
+            receiver_type: object.type_name(),
+            trace: self.trace(name),
+        })
+    }
+
     fn evaluate_binary(
-        left: &Expr,
+        &mut self,
+        left: ExprId,
         token: &Token,
-        right: &Expr,
-    ) -> Result<Value, lox::RuntimeError> {
-        let left_val = Self::evaluate(left)?;
-        let right_val = Self::evaluate(right)?;
+        right: ExprId,
+    ) -> Result<Value, RuntimeError> {
+        let left_val = self.evaluate(left)?;
+        let right_val = if left_val.is_object() {
+            self.with_roots(|interpreter| {
+                interpreter.root(&left_val);
+                interpreter.evaluate(right)
+            })?
+        } else {
+            self.evaluate(right)?
+        };
 
         match token.type_ {
-            TokenType::Greater => {
-                if f64::try_from(left_val)? > f64::try_from(right_val)? {
-                    Ok(Value::True)
+            TokenType::Comma => Ok(right_val),
+            TokenType::EqualEqual => Ok(Value::from(left_val == right_val)),
+            TokenType::BangEqual => Ok(Value::from(left_val != right_val)),
+            TokenType::Plus => match (left_val, right_val) {
+                (Value::String(left_str), Value::String(right_str)) => {
+                    Ok(Value::from(format!("{}{}", left_str, right_str)))
+                }
+                (left_val, right_val) if left_val.is_number() && right_val.is_number() => {
+                    self.arithmetic(token, left_val, right_val)
+                }
+                (left_val, right_val) => Err(self.type_mismatch(token, &left_val, &right_val)),
+            },
+            _ => self.arithmetic(token, left_val, right_val),
+        }
+    }
+
+    /// Applies a numeric operator. Two integers stay integers, except that
+    /// `/` always divides as floats (use `~/` for integer division) and a
+    /// negative exponent yields a float. Any float operand promotes the
+    /// other to a float.
+    fn arithmetic(&self, op: &Token, left: Value, right: Value) -> Result<Value, RuntimeError> {
+        if let (Value::Int(left_int), Value::Int(right_int)) = (&left, &right) {
+            return self.int_arithmetic(op, *left_int, *right_int);
+        }
+
+        let (left_num, right_num) = self.number_operands(op, &left, &right)?;
+        // Compared exactly, not as floats, to agree with `==`.
+        let ordering = compare_numbers(&left, &right);
+        match op.type_ {
+            TokenType::Greater => Ok(Value::from(ordering == Some(Ordering::Greater))),
+            TokenType::GreaterEqual => Ok(Value::from(matches!(
+                ordering,
+                Some(Ordering::Greater | Ordering::Equal)
+            ))),
+            TokenType::Less => Ok(Value::from(ordering == Some(Ordering::Less))),
+            TokenType::LessEqual => Ok(Value::from(matches!(
+                ordering,
+                Some(Ordering::Less | Ordering::Equal)
+            ))),
+            TokenType::Plus => Ok(Value::Number(left_num + right_num)),
+            TokenType::Minus => Ok(Value::Number(left_num - right_num)),
+            TokenType::Slash => Ok(Value::Number(left_num / right_num)),
+            TokenType::Star => Ok(Value::Number(left_num * right_num)),
+            TokenType::StarStar => Ok(Value::Number(left_num.powf(right_num))),
+            TokenType::Percent => {
+                if right_num == 0.0 {
+                    return Err(self.division_by_zero(op));
+                }
+                // Floored, so the result takes the sign of the divisor.
+                let rem = left_num % right_num;
+                if rem != 0.0 && (rem < 0.0) != (right_num < 0.0) {
+                    Ok(Value::Number(rem + right_num))
                 } else {
-                    Ok(Value::False)
+                    Ok(Value::Number(rem))
                 }
             }
-            TokenType::GreaterEqual => {
-                if f64::try_from(left_val)? >= f64::try_from(right_val)? {
-                    Ok(Value::True)
-                } else {
-                    Ok(Value::False)
+            TokenType::TildeSlash => {
+                if right_num == 0.0 {
+                    return Err(self.division_by_zero(op));
                 }
+                Ok(Value::Number((left_num / right_num).floor()))
+            }
+            _ => {
+                let left_int = self.integer_operand(op, left_num)?;
+                let right_int = self.integer_operand(op, right_num)?;
+                self.int_arithmetic(op, left_int, right_int)
             }
-            TokenType::Less => {
-                if f64::try_from(left_val)? < f64::try_from(right_val)? {
-                    Ok(Value::True)
+        }
+    }
+
+    /// Integer arithmetic is checked: results that don't fit in an `i64`
+    /// raise `RuntimeError::IntegerOverflow` rather than wrapping. Shifts are
+    /// the exception and discard the bits shifted out.
+    fn int_arithmetic(&self, op: &Token, left: i64, right: i64) -> Result<Value, RuntimeError> {
+        let result = match op.type_ {
+            TokenType::Greater => return Ok(Value::from(left > right)),
+            TokenType::GreaterEqual => return Ok(Value::from(left >= right)),
+            TokenType::Less => return Ok(Value::from(left < right)),
+            TokenType::LessEqual => return Ok(Value::from(left <= right)),
+            TokenType::Slash => return Ok(Value::Number(left as f64 / right as f64)),
+            TokenType::Plus => left.checked_add(right),
+            TokenType::Minus => left.checked_sub(right),
+            TokenType::Star => left.checked_mul(right),
+            TokenType::StarStar => match u32::try_from(right) {
+                Ok(exponent) => left.checked_pow(exponent),
+                Err(_) if right < 0 => {
+                    return Ok(Value::Number((left as f64).powf(right as f64)));
+                }
+                Err(_) => None,
+            },
+            TokenType::Percent => {
+                if right == 0 {
+                    return Err(self.division_by_zero(op));
+                }
+                // i64::MIN % -1 overflows in Rust but is 0 mathematically.
+                let rem = left.wrapping_rem(right);
+                if rem != 0 && (rem < 0) != (right < 0) {
+                    Some(rem + right)
                 } else {
-                    Ok(Value::False)
+                    Some(rem)
                 }
             }
-            TokenType::LessEqual => {
-                if f64::try_from(left_val)? <= f64::try_from(right_val)? {
-                    Ok(Value::True)
-                } else {
-                    Ok(Value::False)
+            TokenType::TildeSlash => {
+                if right == 0 {
+                    return Err(self.division_by_zero(op));
                 }
+                left.checked_div(right).map(|quotient| {
+                    let inexact = left.wrapping_rem(right) != 0;
+                    if inexact && (left < 0) != (right < 0) {
+                        quotient - 1
+                    } else {
+                        quotient
+                    }
+                })
             }
-            TokenType::EqualEqual => {
-                if left_val == right_val {
-                    Ok(Value::True)
-                } else {
-                    Ok(Value::False)
+            TokenType::Ampersand => Some(left & right),
+            TokenType::Pipe => Some(left | right),
+            TokenType::Caret => Some(left ^ right),
+            TokenType::LessLess => Some(left << self.shift_amount(op, right)?),
+            TokenType::GreaterGreater => Some(left >> self.shift_amount(op, right)?),
+            _ => unreachable!("binary operator without an infix rule"),
+        };
+
+        result
+            .map(Value::Int)
+            .ok_or_else(|| RuntimeError::IntegerOverflow {
+                op: op.clone(),
+                trace: self.trace(op),
+            })
+    }
+
+    /// Calls `callee` with already evaluated arguments. Native functions use
+    /// this to call back into Lox.
+    pub(crate) fn call(
+        &mut self,
+        callee: Value,
+        paren: &Token,
+        arguments: Vec<Value>,
+    ) -> Result<Value, RuntimeError> {
+        match callee {
+            Value::Function(function) => self.call_function(&function, paren, arguments),
+            Value::Native(native) => self.call_native(&native, paren, arguments),
+            Value::Class(class) => {
+                let instance = self
+                    .heap
+                    .alloc(RefCell::new(LoxInstance::new(Rc::clone(&class))));
+                match class.find_method(&self.init) {
+                    Some(initializer) => {
+                        let initializer = initializer.bind(Rc::clone(&instance), &self.heap);
+                        self.call_function(&initializer, paren, arguments)?;
+                    }
+                    None => self.check_arity(paren, 0..=0, arguments.len())?,
                 }
+                Ok(Value::Instance(instance))
             }
-            TokenType::BangEqual => {
-                if left_val != right_val {
-                    Ok(Value::True)
-                } else {
-                    Ok(Value::False)
-                }
-            }
-            TokenType::Minus => Ok(Value::Number(
-                f64::try_from(left_val)? - f64::try_from(right_val)?,
-            )),
-            TokenType::Slash => Ok(Value::Number(
-                f64::try_from(left_val)? / f64::try_from(right_val)?,
-            )),
-            TokenType::Star => Ok(Value::Number(
-                f64::try_from(left_val)? * f64::try_from(right_val)?,
-            )),
-            TokenType::Plus => {
-                match (left_val, right_val) {
-                    (Value::Number(left_num), Value::Number(right_num)) => Ok(Value::Number(left_num + right_num)),
-                    (Value::String(left_str), Value::String(right_str)) => Ok(Value::String(left_str + &right_str)),
-                    _ => Err(lox::RuntimeError { message: String::from("Invalid operation") }),
-                }
-            }
-            _ => Err(lox::RuntimeError {
-                message: String::from("Invalid operation"),
+            _ => Err(RuntimeError::NotCallable {
+                paren: paren.clone(),
+                callee_type: callee.type_name(),
+                trace: self.trace(paren),
+            }),
+        }
+    }
+
+    fn call_function(
+        &mut self,
+        function: &LoxFunction,
+        paren: &Token,
+        arguments: Vec<Value>,
+    ) -> Result<Value, RuntimeError> {
+        let arity = function.arity();
+        self.check_arity(paren, arity..=arity, arguments.len())?;
+        if self.frames.len() >= MAX_CALL_DEPTH {
+            return Err(RuntimeError::StackOverflow {
+                paren: paren.clone(),
+                trace: self.trace(paren),
+            });
+        }
+
+        let mut environment = Environment::new_enclosed(Rc::clone(&function.closure));
+        for (param, argument) in function.declaration.params.iter().zip(arguments) {
+            environment.define(param.name().clone(), argument);
+        }
+
+        self.frames.push(Frame {
+            code: Code::Function(function.name().to_string()),
+            line: paren.line,
+        });
+        let enclosing = mem::replace(&mut self.ast, Rc::clone(&function.ast));
+        let result = self.execute_block(
+            &function.declaration.body,
+            Rc::new(RefCell::new(environment)),
+        );
+        self.ast = enclosing;
+        self.frames.pop();
+
+        let value = match result {
+            Ok(()) => Value::Nil,
+            Err(Unwind::Return(value)) => value,
+            Err(Unwind::Error(error)) => return Err(error),
+            // The parser rejects jumps to loops outside the function.
+            Err(Unwind::Break(_) | Unwind::Continue(_)) => unreachable!(),
+        };
+
+        if function.is_initializer {
+            return Ok(function
+                .closure
+                .borrow()
+                .get(&self.this)
+                .unwrap_or(Value::Nil));
+        }
+        Ok(value)
+    }
+
+    fn call_native(
+        &mut self,
+        native: &NativeFunction,
+        paren: &Token,
+        arguments: Vec<Value>,
+    ) -> Result<Value, RuntimeError> {
+        self.check_arity(paren, native.arity.clone(), arguments.len())?;
+        match &native.receiver {
+            Some(receiver) => {
+                let mut receiver_and_arguments = Vec::with_capacity(arguments.len() + 1);
+                receiver_and_arguments.push(receiver.clone());
+                receiver_and_arguments.extend(arguments);
+                (native.function)(self, paren, &receiver_and_arguments)
+            }
+            None => (native.function)(self, paren, &arguments),
+        }
+    }
+
+    fn check_arity(
+        &self,
+        paren: &Token,
+        expected: RangeInclusive<usize>,
+        got: usize,
+    ) -> Result<(), RuntimeError> {
+        if expected.contains(&got) {
+            return Ok(());
+        }
+        Err(RuntimeError::ArityMismatch {
+            paren: paren.clone(),
+            expected,
+            got,
+            trace: self.trace(paren),
+        })
+    }
+
+    fn number_operand(&self, op: &Token, operand: &Value) -> Result<f64, RuntimeError> {
+        operand.as_f64().ok_or_else(|| RuntimeError::TypeMismatch {
+            op: op.clone(),
+            left_type: operand.type_name(),
+            right_type: None,
+            trace: self.trace(op),
+        })
+    }
+
+    /// Converts a bitwise operand to an integer, rejecting fractions and
+    /// numbers outside the `i64` range.
+    fn integer_operand(&self, op: &Token, n: f64) -> Result<i64, RuntimeError> {
+        exact_integer(n).ok_or_else(|| RuntimeError::NonIntegral {
+            op: op.clone(),
+            value: n,
+            trace: self.trace(op),
+        })
+    }
+
+    fn shift_amount(&self, op: &Token, amount: i64) -> Result<u32, RuntimeError> {
+        match u32::try_from(amount) {
+            Ok(amount) if amount < i64::BITS => Ok(amount),
+            _ => Err(RuntimeError::InvalidShift {
+                op: op.clone(),
+                amount,
+                trace: self.trace(op),
             }),
         }
     }
+
+    fn division_by_zero(&self, op: &Token) -> RuntimeError {
+        RuntimeError::DivisionByZero {
+            op: op.clone(),
+            trace: self.trace(op),
+        }
+    }
+
+    fn number_operands(
+        &self,
+        op: &Token,
+        left: &Value,
+        right: &Value,
+    ) -> Result<(f64, f64), RuntimeError> {
+        match (left.as_f64(), right.as_f64()) {
+            (Some(left_num), Some(right_num)) => Ok((left_num, right_num)),
+            _ => Err(self.type_mismatch(op, left, right)),
+        }
+    }
+
+    fn type_mismatch(&self, op: &Token, left: &Value, right: &Value) -> RuntimeError {
+        RuntimeError::TypeMismatch {
+            op: op.clone(),
+            left_type: left.type_name(),
+            right_type: Some(right.type_name()),
+            trace: self.trace(op),
+        }
+    }
+
+    pub(crate) fn error_class(&self) -> Rc<LoxClass> {
+        Rc::clone(&self.error_class)
+    }
+
+    /// Snapshots the call stack for an error raised at `token`, innermost
+    /// frame first.
+    pub(crate) fn trace(&self, token: &Token) -> Vec<Frame> {
+        let mut trace = Vec::with_capacity(self.frames.len() + 1);
+        let mut line = token.line;
+        for frame in self.frames.iter().rev() {
+            trace.push(Frame {
+                code: frame.code.clone(),
+                line,
+            });
+            line = frame.line;
+        }
+        trace.push(Frame {
+            code: Code::Script,
+            line,
+        });
+        trace
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::run;
+
+    fn run_err(source: &str) -> RuntimeError {
+        run(source).1.expect_err("expected a runtime error")
+    }
+
+    #[test]
+    fn test_variables_and_scope() {
+        let (output, result) = run("var a = 1; { var a = 2; print a; a = 3; print a; } print a;
+             var b; print b; b = a = 4; print a + b;");
+        assert!(result.is_ok(), "{:?}", result);
+        assert_eq!(output, "2\n3\n1\nnil\n8\n");
+    }
+
+    #[test]
+    fn test_control_flow() {
+        let (output, result) = run("if (1 < 2) print \"then\"; else print \"else\";
+             if (nil) print \"then\"; else if (0) print \"zero is truthy\";
+             var i = 0; while (i < 3) i = i + 1; print i;
+             for (var j = 0; j < 3; j = j + 1) print j;
+             print nil or \"default\"; print 0 and \"both\"; print false and missing;
+             print true or missing;");
+        assert!(result.is_ok(), "{:?}", result);
+        assert_eq!(
+            output,
+            "then\nzero is truthy\n3\n0\n1\n2\ndefault\nboth\nfalse\ntrue\n"
+        );
+    }
+
+    #[test]
+    fn test_closures() {
+        let (output, result) = run(
+            "fun counter() { var i = 0; fun count() { i = i + 1; return i; } return count; }
+             var c = counter(); c(); print c();
+             var f;
+             for (var k = 0; k < 2; k = k + 1) { fun g() { return k; } f = g; }
+             print f();
+             fun fib(n) { if (n < 2) return n; return fib(n - 1) + fib(n - 2); }
+             print fib(10);",
+        );
+        assert!(result.is_ok(), "{:?}", result);
+        assert_eq!(output, "2\n2\n55\n");
+    }
+
+    #[test]
+    fn test_classes() {
+        let (output, result) = run(
+            "class Point { init(x, y) { this.x = x; this.y = y; } sum() { return this.x + this.y; } }
+             var p = Point(1, 2); print p.sum(); print Point; print p;
+             var sum = p.sum; p.x = 10; print sum();
+             print Point(3, 4).init(5, 6).sum();",
+        );
+        assert!(result.is_ok(), "{:?}", result);
+        assert_eq!(output, "3\nPoint\nPoint instance\n12\n11\n");
+    }
+
+    #[test]
+    fn test_type_mismatch_reports_operator_and_trace() {
+        let error = run_err("fun sub(a, b) {\n  return a - b;\n}\nsub(\"a\", 1);");
+        assert!(matches!(
+            error,
+            RuntimeError::TypeMismatch {
+                left_type: "string",
+                right_type: Some("number"),
+                ..
+            }
+        ));
+        assert_eq!(
+            error.to_string(),
+            "Operands to '-' must be numbers, got string and number at line 2"
+        );
+        assert_eq!(
+            error.trace(),
+            [
+                Frame {
+                    code: Code::Function("sub".to_string()),
+                    line: 2
+                },
+                Frame {
+                    code: Code::Script,
+                    line: 4
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_error_kinds() {
+        assert!(matches!(
+            run_err("print missing;"),
+            RuntimeError::UndefinedVariable { .. }
+        ));
+        assert!(matches!(
+            run_err("\"not a function\"();"),
+            RuntimeError::NotCallable {
+                callee_type: "string",
+                ..
+            }
+        ));
+        assert!(matches!(
+            run_err("fun f(a) {} f(1, 2);"),
+            RuntimeError::ArityMismatch {
+                ref expected,
+                got: 2,
+                ..
+            } if *expected == (1..=1)
+        ));
+        assert!(matches!(
+            run_err("class A {} A().b;"),
+            RuntimeError::UndefinedProperty {
+                receiver_type: "instance",
+                ..
+            }
+        ));
+        assert!(matches!(
+            run_err("-nil;"),
+            RuntimeError::TypeMismatch {
+                left_type: "nil",
+                right_type: None,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn test_arithmetic_and_bitwise_operators() {
+        let (output, result) = run("print -7 % 3; print 7 % -3; print 7 ~/ 2; print -7 ~/ 2;
+             print 2 ** 3 ** 2; print -2 ** 2; print 6 & 3; print 6 | 3; print 6 ^ 3;
+             print ~5; print 1 << 4; print -16 >> 2; print 12 & 4 == 4;");
+        assert!(result.is_ok());
+        assert_eq!(output, "2\n-2\n3\n-4\n512\n-4\n2\n7\n5\n-6\n16\n-4\ntrue\n");
+    }
+
+    #[test]
+    fn test_conditional_comma_and_coalesce() {
+        let (output, result) = run("fun boom() { return nil(); }
+             print true ? 1 : boom(); print nil ? boom() : 2;
+             print 0 ? \"zero is truthy\" : \"\";
+             var a = 1; var b = (a = 2, a + 1); print b;
+             fun two(x, y) { return y; } print two(1, (2, 3));
+             print nil ?? 4; print false ?? boom(); print nil or false or 1 ?? 2;");
+        assert!(result.is_ok(), "{:?}", result);
+        assert_eq!(output, "1\n2\nzero is truthy\n3\n3\n4\nfalse\n1\n");
+    }
+
+    #[test]
+    fn test_optional_chaining() {
+        let (output, result) = run(
+            "class Node { init(next) { this.next = next; } name() { return \"node\"; } }
+             var n = nil;
+             print n?.next; print n?.next.next.name(); print n?.name();
+             print Node(nil)?.name(); print Node(nil).next?.name();",
+        );
+        assert!(result.is_ok(), "{:?}", result);
+        assert_eq!(output, "nil\nnil\nnil\nnode\nnil\n");
+
+        assert!(matches!(
+            run_err("var n = nil; (n?.a).b;"),
+            RuntimeError::UndefinedProperty {
+                receiver_type: "nil",
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn test_integers() {
+        let (output, result) = run(
+            "print 9007199254740993 + 1; print 7 / 2; print 6 / 2; print 7 ~/ 2;
+             print 2 ** 62; print 2 ** -1; print 1 + 0.5; print 3 == 3.0;
+             print 9007199254740993 == 9007199254740992.0; print 1 < 1.5;
+             print -9223372036854775808 == -9223372036854775807 - 1; print 1e18 == 10 ** 18;",
+        );
+        assert!(result.is_ok(), "{:?}", result);
+        assert_eq!(
+            output,
+            "9007199254740994\n3.5\n3\n3\n4611686018427387904\n0.5\n1.5\ntrue\nfalse\ntrue\n\
+             true\ntrue\n"
+        );
+        assert!(matches!(
+            run_err("0.5 | 1;"),
+            RuntimeError::NonIntegral { .. }
+        ));
+        assert_eq!(run("print 3.0 & 1;").0, "1\n");
+    }
+
+    #[test]
+    fn test_mixed_comparisons_are_exact() {
+        let (output, result) = run("var big = 2 ** 53 + 1; var float = 2.0 ** 53;
+             print big > float; print big >= float; print big < float; print big <= float;
+             print float < big; print big == float; print big != float;
+             print 9223372036854775807 < 2.0 ** 63;
+             print -9223372036854775807 - 1 > -1.0 / 0;
+             print 3 < 3.5; print 4 > 3.5; print 3 <= 3.0; print 3 < 0.0 / 0;
+             var list = [big, float, 2 ** 53 - 1];
+             list.sort(); print list;
+             print math.max(float, big); print math.min(big, float);");
+        assert!(result.is_ok(), "{:?}", result);
+        assert_eq!(
+            output,
+            "true\ntrue\nfalse\nfalse\ntrue\nfalse\ntrue\ntrue\ntrue\n\
+             true\ntrue\ntrue\nfalse\n\
+             [9007199254740991, 9007199254740992, 9007199254740993]\n\
+             9007199254740993\n9007199254740992\n"
+        );
+    }
+
+    #[test]
+    fn test_integer_overflow() {
+        for source in [
+            "9223372036854775807 + 1;",
+            "-9223372036854775807 - 2;",
+            "4294967296 * 4294967296;",
+            "2 ** 63;",
+            "-(-9223372036854775807 - 1);",
+            "(-9223372036854775807 - 1) ~/ -1;",
+        ] {
+            assert!(
+                matches!(run_err(source), RuntimeError::IntegerOverflow { .. }),
+                "{}",
+                source
+            );
+        }
+        assert_eq!(
+            run_err("9223372036854775807 + 1;").to_string(),
+            "Result of '+' overflows a 64-bit integer at line 1"
+        );
+    }
+
+    #[test]
+    fn test_arithmetic_errors() {
+        assert_eq!(
+            run_err("1.5 & 1;").to_string(),
+            "Operands to '&' must be integers, got 1.5 at line 1"
+        );
+        assert!(matches!(run_err("~0.5;"), RuntimeError::NonIntegral { .. }));
+        assert!(matches!(
+            run_err("1 % 0;"),
+            RuntimeError::DivisionByZero { .. }
+        ));
+        assert!(matches!(
+            run_err("1 << 64;"),
+            RuntimeError::InvalidShift { amount: 64, .. }
+        ));
+    }
+
+    #[test]
+    fn test_interpreters_in_parallel_threads() {
+        let threads: Vec<_> = (0..8)
+            .map(|i| {
+                std::thread::spawn(move || {
+                    let (output, result) =
+                        run(&format!("var n = {}; while (n > 0) n = n - 1; print n;", i));
+                    (output, result.is_ok())
+                })
+            })
+            .collect();
+        for thread in threads {
+            assert_eq!(thread.join().unwrap(), ("0\n".to_string(), true));
+        }
+    }
+
+    #[test]
+    fn test_unbounded_recursion_overflows() {
+        // Unoptimized builds need more than the default test thread stack to
+        // reach the depth limit.
+        // Errors can hold `Rc`s, so only plain data leaves the thread.
+        let (overflowed, depth) = std::thread::Builder::new()
+            .stack_size(64 * 1024 * 1024)
+            .spawn(|| {
+                let error = run_err("fun f() { f(); } f();");
+                (
+                    matches!(error, RuntimeError::StackOverflow { .. }),
+                    error.trace().len(),
+                )
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+        assert!(overflowed);
+        assert_eq!(depth, MAX_CALL_DEPTH + 1);
+    }
+
+    #[test]
+    fn test_lists() {
+        let (output, result) = run("var xs = [1, \"a\", [nil],];
+             print xs; print xs[0]; print xs[-1]; print xs.len();
+             xs[1] = 2; xs.push(3); print xs; print xs.pop(); print xs;
+             xs.insert(0, 0); xs.insert(-1, 9); print xs.remove(1); print xs;
+             xs.push(xs); print xs;
+             var ys = [1, 2, 3];
+             print ys == ys; print ys == [1, 2, 3];
+             print ys.slice(1); print ys.slice(0, -1); print ys.slice(3);
+             fun double(n) { return n * 2; }
+             fun odd(n) { return n % 2 == 1; }
+             fun add(a, b) { return a + b; }
+             print ys.map(double); print ys.filter(odd);
+             print ys.reduce(add); print ys.reduce(add, 10); print [].reduce(add, 0);
+             print ys.join(\", \");");
+        assert!(result.is_ok(), "{:?}", result);
+        assert_eq!(
+            output,
+            "[1, \"a\", [nil]]\n1\n[nil]\n3\n[1, 2, [nil], 3]\n3\n[1, 2, [nil]]\n\
+             1\n[0, 2, 9, [nil]]\n[0, 2, 9, [nil], [...]]\ntrue\nfalse\n\
+             [2, 3]\n[1, 2]\n[]\n[2, 4, 6]\n[1, 3]\n6\n16\n0\n1, 2, 3\n"
+        );
+    }
+
+    #[test]
+    fn test_list_sort() {
+        let (output, result) = run("var xs = [3, 1.5, -2, 10]; xs.sort(); print xs;
+             var names = [\"b\", \"c\", \"a\"]; names.sort(); print names;
+             fun by_length(a, b) { return a.len() - b.len(); }
+             var lists = [[1, 2], [3], [4, 5], []]; lists.sort(by_length); print lists;
+             fun chaos(a, b) { return 1; }
+             xs.sort(chaos); print xs.len();");
+        assert!(result.is_ok(), "{:?}", result);
+        assert_eq!(
+            output,
+            "[-2, 1.5, 3, 10]\n[\"a\", \"b\", \"c\"]\n[[], [3], [1, 2], [4, 5]]\n4\n"
+        );
+
+        assert!(matches!(
+            run_err("[1, \"a\"].sort();"),
+            RuntimeError::InvalidArgument { .. }
+        ));
+    }
+
+    #[test]
+    fn test_list_errors() {
+        assert!(matches!(
+            run_err("[1, 2, 3][3];"),
+            RuntimeError::IndexOutOfBounds {
+                index: 3,
+                len: 3,
+                ..
+            }
+        ));
+        assert!(matches!(
+            run_err("[1, 2, 3][-4] = 0;"),
+            RuntimeError::IndexOutOfBounds {
+                index: -4,
+                len: 3,
+                ..
+            }
+        ));
+        assert!(matches!(
+            run_err("[1][\"0\"];"),
+            RuntimeError::InvalidIndex {
+                index_type: "string",
+                ..
+            }
+        ));
+        assert!(matches!(
+            run_err("[1][0.0];"),
+            RuntimeError::InvalidIndex {
+                index_type: "number",
+                ..
+            }
+        ));
+        assert!(matches!(
+            run_err("var n = 1; n[0];"),
+            RuntimeError::NotIndexable {
+                receiver_type: "number",
+                ..
+            }
+        ));
+        assert!(matches!(
+            run_err("[].pop();"),
+            RuntimeError::IndexOutOfBounds { .. }
+        ));
+        assert!(matches!(
+            run_err("[1, 2].slice(2, 1);"),
+            RuntimeError::IndexOutOfBounds { index: 1, .. }
+        ));
+        assert!(matches!(
+            run_err("[].reduce(nil);"),
+            RuntimeError::InvalidArgument { .. }
+        ));
+        assert!(matches!(
+            run_err("[].push();"),
+            RuntimeError::ArityMismatch { got: 0, .. }
+        ));
+        assert_eq!(
+            run_err("[].slice();").message(),
+            "Expected 1 to 2 arguments but got 0"
+        );
+        assert!(matches!(
+            run_err("[].nope;"),
+            RuntimeError::UndefinedProperty {
+                receiver_type: "list",
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn test_maps() {
+        let (output, result) = run("var m = {\"b\": 1, \"a\": [2], 3: nil,};
+             print m; print m[\"a\"]; print m[3.0]; print {};
+             m[\"b\"] = 10; m[true] = \"yes\"; print m;
+             print m.keys(); print m.values(); print m.entries();
+             print m.has(\"a\"); print m.has(\"z\"); print m.len();
+             print m.remove(\"b\"); print m.remove(\"b\"); print m;
+             m[0.5] = m; print m;
+             print m == m; print {} == {};");
+        assert!(result.is_ok(), "{:?}", result);
+        assert_eq!(
+            output,
+            "{\"b\": 1, \"a\": [2], 3: nil}\n[2]\nnil\n{}\n\
+             {\"b\": 10, \"a\": [2], 3: nil, true: \"yes\"}\n\
+             [\"b\", \"a\", 3, true]\n[10, [2], nil, \"yes\"]\n\
+             [[\"b\", 10], [\"a\", [2]], [3, nil], [true, \"yes\"]]\n\
+             true\nfalse\n4\n10\nnil\n{\"a\": [2], 3: nil, true: \"yes\"}\n\
+             {\"a\": [2], 3: nil, true: \"yes\", 0.5: {...}}\ntrue\nfalse\n"
+        );
+    }
+
+    #[test]
+    fn test_map_errors() {
+        assert!(matches!(
+            run_err("var m = {[1]: 2};"),
+            RuntimeError::UnhashableKey {
+                key_type: "list",
+                ..
+            }
+        ));
+        assert!(matches!(
+            run_err("var m = {}; m[m] = 1;"),
+            RuntimeError::UnhashableKey {
+                key_type: "map",
+                ..
+            }
+        ));
+        assert!(matches!(
+            run_err("var m = {}; m.has([]);"),
+            RuntimeError::UnhashableKey { .. }
+        ));
+        assert_eq!(
+            run_err("var m = {}; m[0 / 0] = 1;").message(),
+            "Map key must be a string, number, boolean or nil, got NaN"
+        );
+        assert_eq!(run("var m = {0: 1}; print m[-0.0];").0, "1\n");
+        assert_eq!(
+            run_err("var m = {1: 2}; m[\"1\"];").message(),
+            "Key \"1\" not found in map"
+        );
+        assert_eq!(
+            run_err("var m = {}; m[1.5];").message(),
+            "Key 1.5 not found in map"
+        );
+    }
+
+    #[test]
+    fn test_for_in() {
+        let (output, result) = run("for (x in [1, 2]) print x;
+             for (k in {\"b\": 1, \"a\": 2}) print k;
+             for (c in \"hi\") print c;
+             for (n in range(3)) print n;
+             for (n in range(10, 0, -4)) print n;
+             for (n in range(1, 1)) print \"empty\";
+             print range(2, 5);
+             var xs = [1];
+             for (x in xs) if (x < 3) xs.push(x + 1);
+             print xs;
+             var fs = [];
+             for (x in [\"a\", \"b\"]) { fun f() { return x; } fs.push(f); }
+             print fs[0]() + fs[1]();");
+        assert!(result.is_ok(), "{:?}", result);
+        assert_eq!(
+            output,
+            "1\n2\nb\na\nh\ni\n0\n1\n2\n10\n6\n2\nrange(2, 5, 1)\n[1, 2, 3]\nab\n"
+        );
+    }
+
+    #[test]
+    fn test_for_in_user_iterator() {
+        let (output, result) = run("class Countdown {
+               init(n) { this.n = n; }
+               iter() { return CountdownIter(this.n); }
+             }
+             class CountdownIter {
+               init(n) { this.n = n; }
+               next() {
+                 if (this.n == 0) return nil;
+                 this.n = this.n - 1;
+                 return this.n + 1;
+               }
+             }
+             for (n in Countdown(3)) print n;");
+        assert!(result.is_ok(), "{:?}", result);
+        assert_eq!(output, "3\n2\n1\n");
+
+        assert!(matches!(
+            run_err("for (x in 1) {}"),
+            RuntimeError::NotIterable {
+                type_name: "number",
+                ..
+            }
+        ));
+        assert!(matches!(
+            run_err("class A {} for (x in A()) {}"),
+            RuntimeError::UndefinedProperty { .. }
+        ));
+        assert!(matches!(
+            run_err("range(0, 1, 0);"),
+            RuntimeError::InvalidArgument { .. }
+        ));
+        assert!(matches!(
+            run_err("range(0.5);"),
+            RuntimeError::InvalidArgument { .. }
+        ));
+    }
+
+    #[test]
+    fn test_break_and_continue() {
+        let (output, result) = run("for (var i = 0; i < 10; i = i + 1) {
+               if (i == 1) continue;
+               if (i == 4) break;
+               { var inner = i; print inner; }
+             }
+             var n = 0;
+             while (true) { n = n + 1; if (n < 3) continue; break; }
+             print n;
+             outer: for (x in range(3)) {
+               for (y in range(3)) {
+                 if (y == 1) continue outer;
+                 if (x == 2) break outer;
+                 print x * 10 + y;
+               }
+             }
+             fun first_even(xs) { for (x in xs) if (x % 2 == 0) return x; }
+             print first_even([1, 3, 4, 6]);");
+        assert!(result.is_ok(), "{:?}", result);
+        assert_eq!(output, "0\n2\n3\n3\n0\n10\n4\n");
+    }
+
+    #[test]
+    fn test_exceptions() {
+        let (output, result) = run(
+            "try { throw \"oops\"; print \"unreachable\"; } catch (e) { print e; }
+             try { nil - 1; } catch (e) { print e.message; print e.line; }
+             fun inner() { return 1 + nil; }
+             fun outer() { return inner(); }
+             try { outer(); } catch (e) { print e.stack; }
+             try { missing; } catch (e) { print e.message; }
+             try { throw Error(\"custom\"); } catch (e) { print e.message; print e.line; }
+             try { try { throw 1; } finally { print \"finally\"; } } catch (e) { print e; }
+             try { } catch (e) { print \"no error\"; } finally { print \"always\"; }
+             try { throw 1; } catch (e) { print \"caught\"; } finally { print \"after catch\"; }",
+        );
+        assert!(result.is_ok(), "{:?}", result);
+        assert_eq!(
+            output,
+            "oops\n\
+             Operands to '-' must be numbers, got nil and number\n2\n\
+             [\"[line 3] in inner()\", \"[line 4] in outer()\", \"[line 5] in script\"]\n\
+             Undefined variable 'missing'\n\
+             custom\n7\n\
+             finally\n1\n\
+             always\n\
+             caught\nafter catch\n"
+        );
+    }
+
+    #[test]
+    fn test_finally_runs_on_every_exit() {
+        let (output, result) = run(
+            "fun f() { try { return \"try\"; } finally { print \"cleanup\"; } }
+             print f();
+             fun g() { try { return 1; } finally { return 2; } }
+             print g();
+             for (x in [1, 2, 3]) {
+               try { if (x == 2) continue; if (x == 3) break; print x; }
+               finally { print -x; }
+             }
+             fun h() { try { throw 1; } catch (e) { return \"from catch\"; } finally { print \"h\"; } }
+             print h();",
+        );
+        assert!(result.is_ok(), "{:?}", result);
+        assert_eq!(output, "cleanup\ntry\n2\n1\n-1\n-2\n-3\nh\nfrom catch\n");
+    }
+
+    #[test]
+    fn test_uncaught_throw() {
+        let error = run_err("fun f() { throw Error(\"boom\"); }\nf();");
+        assert_eq!(error.to_string(), "boom at line 1");
+        assert_eq!(error.trace().len(), 2);
+        assert_eq!(
+            run_err("throw [1];").to_string(),
+            "Uncaught exception: [1] at line 1"
+        );
+        let error = run_err("try { throw 1; } catch (e) { throw e + 1; }");
+        assert!(matches!(
+            error,
+            RuntimeError::Thrown {
+                value: Value::Int(2),
+                ..
+            }
+        ));
+        let error = run_err("try { throw 1; } finally { nil(); }");
+        assert!(matches!(error, RuntimeError::NotCallable { .. }));
+    }
+
+    #[test]
+    fn test_string_methods() {
+        let (output, result) = run("var s = \"  Hello, World  \";
+             print s.len(); print s.trim().upper(); print s.trim().lower();
+             print \"a,b,,c\".split(\",\"); print \"banana\".replace(\"an\", \"AN\");
+             print \"banana\".contains(\"nan\"); print \"banana\".starts_with(\"ba\");
+             print \"banana\".ends_with(\"na\"); print \"banana\".index_of(\"na\");
+             print \"banana\".index_of(\"x\"); print \"banana\".substring(1, 3);
+             print \"banana\".substring(-3); print \"ab\".repeat(3); print \"ab\".repeat(0);
+             print \"abc\".chars();
+             print \"{} + {} = {}\".format(1, 2.5, \"3.5\"); print \"{{}} {}\".format([1]);
+             print \"x\".len;");
+        assert!(result.is_ok(), "{:?}", result);
+        assert_eq!(
+            output,
+            "16\nHELLO, WORLD\nhello, world\n[\"a\", \"b\", \"\", \"c\"]\nbANANa\n\
+             true\ntrue\ntrue\n2\n-1\nan\nana\nababab\n\n[\"a\", \"b\", \"c\"]\n\
+             1 + 2.5 = 3.5\n{} [1]\n<native fn len>\n"
+        );
+    }
+
+    #[test]
+    fn test_string_method_errors() {
+        assert!(matches!(
+            run_err("\"abc\".substring(2, 1);"),
+            RuntimeError::IndexOutOfBounds {
+                index: 1,
+                len: 3,
+                ..
+            }
+        ));
+        assert!(matches!(
+            run_err("\"abc\".substring(4);"),
+            RuntimeError::IndexOutOfBounds { index: 4, .. }
+        ));
+        assert_eq!(
+            run_err("\"abc\".split(1);").message(),
+            "Argument to split() must be a string, got number"
+        );
+        assert!(matches!(
+            run_err("\"abc\".split(\"\");"),
+            RuntimeError::InvalidArgument { .. }
+        ));
+        assert!(matches!(
+            run_err("\"a\".repeat(-1);"),
+            RuntimeError::InvalidArgument { .. }
+        ));
+        assert_eq!(
+            run_err("\"{} {}\".format(1);").message(),
+            "format() has more placeholders than the 1 arguments given"
+        );
+        assert!(matches!(
+            run_err("\"{}\".format(1, 2);"),
+            RuntimeError::InvalidArgument { .. }
+        ));
+        assert!(matches!(
+            run_err("\"{\".format();"),
+            RuntimeError::InvalidArgument { .. }
+        ));
+        assert!(matches!(
+            run_err("\"abc\".nope();"),
+            RuntimeError::UndefinedProperty {
+                receiver_type: "string",
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn test_math_module() {
+        let (output, result) = run(
+            "print math.sqrt(16); print math.pow(2, 10); print math.pow(2, -1);
+             print math.pow(2, 64) == 2.0 ** 64;
+             print math.floor(-2.5); print math.ceil(2.1); print math.round(2.5);
+             print math.round(-2.5); print math.floor(math.INF); print math.abs(-3);
+             print math.abs(-1.5); print math.min(3, 1.5, 2); print math.max(3, 1.5, 2);
+             print math.min(1, math.NaN); print math.sin(0); print math.cos(0);
+             print math.atan2(1, 1) == math.PI / 4; print math.log(math.E);
+             print math.log(8, 2); print math.exp(0); print math.tan(0);
+             print math.floor(3) + 1;",
+        );
+        assert!(result.is_ok(), "{:?}", result);
+        assert_eq!(
+            output,
+            "4\n1024\n0.5\ntrue\n-3\n3\n3\n-3\ninf\n3\n1.5\n1.5\n3\nNaN\n0\n1\n\
+             true\n1\n3\n1\n0\n4\n"
+        );
+
+        assert_eq!(
+            run_err("math.sqrt(\"4\");").message(),
+            "Argument to sqrt() must be a number, got string"
+        );
+        assert!(matches!(
+            run_err("math.min();"),
+            RuntimeError::ArityMismatch { got: 0, .. }
+        ));
+    }
+
+    #[test]
+    fn test_random_module_is_reproducible() {
+        let source = "random.seed(7);
+             var xs = [];
+             for (i in range(20)) xs.push(random.randint(1, 6));
+             for (x in xs) if (x < 1 or x > 6) print \"out of range\";
+             var f = random.random(); if (f < 0 or f >= 1) print \"out of range\";
+             var list = [1, 2, 3, 4, 5]; random.shuffle(list);
+             list.sort(); print xs; print list; print random.choice([\"a\"]);
+             print random.randint(3, 3);";
+        let (first, result) = run(source);
+        assert!(result.is_ok(), "{:?}", result);
+        let (second, _) = run(source);
+        assert_eq!(first, second);
+        assert!(first.ends_with("[1, 2, 3, 4, 5]\na\n3\n"), "{}", first);
+
+        assert!(matches!(
+            run_err("random.choice([]);"),
+            RuntimeError::InvalidArgument { .. }
+        ));
+        assert_eq!(
+            run_err("random.randint(2, 1);").message(),
+            "randint() bounds are reversed: 2 > 1"
+        );
+        assert_eq!(
+            run_err("random.shuffle(\"abc\");").message(),
+            "Argument to shuffle() must be a list, got string"
+        );
+    }
+
+    #[test]
+    fn test_time_natives() {
+        let (output, result) = run(
+            "var start = clock(); sleep(5); print clock() - start >= 0.005;
+             print now() > 1700000000;
+             print format_time(0); print format_time(1700000000.25, \"%a %d %b %Y %H:%M:%S.%L %%\");
+             print format_time(-1); print parse_time(\"2023-11-14T22:13:20Z\");
+             print parse_time(\"2023-11-15T00:13:20.5+02:00\");
+             print format_time(parse_time(\"2024-02-29\"));",
+        );
+        assert!(result.is_ok(), "{:?}", result);
+        assert_eq!(
+            output,
+            "true\ntrue\n1970-01-01T00:00:00Z\nTue 14 Nov 2023 22:13:20.250 %\n\
+             1969-12-31T23:59:59Z\n1700000000\n1700000000.5\n2024-02-29T00:00:00Z\n"
+        );
+
+        assert_eq!(
+            run_err("parse_time(\"2023-02-30\");").message(),
+            "Invalid ISO-8601 time \"2023-02-30\""
+        );
+        assert_eq!(
+            run_err("format_time(0, \"%Q\");").message(),
+            "Unknown format specifier '%Q' in format_time()"
+        );
+        assert!(matches!(
+            run_err("format_time(math.INF);"),
+            RuntimeError::InvalidArgument { .. }
+        ));
+        assert!(matches!(
+            run_err("sleep(-1);"),
+            RuntimeError::InvalidArgument { .. }
+        ));
+    }
+
+    #[test]
+    fn test_json_module() {
+        let (output, result) = run(
+            "var data = {\"name\": \"lox\", \"tags\": [1, 2.5, nil, true], \"nested\": {}};
+             var text = json.stringify(data); print text;
+             var copy = json.parse(text); print copy; print copy[\"tags\"][1] + 1;
+             print json.stringify([1, [2]], 1);",
+        );
+        assert!(result.is_ok(), "{:?}", result);
+        assert_eq!(
+            output,
+            "{\"name\":\"lox\",\"tags\":[1,2.5,null,true],\"nested\":{}}\n\
+             {\"name\": \"lox\", \"tags\": [1, 2.5, nil, true], \"nested\": {}}\n3.5\n\
+             [\n 1,\n [\n  2\n ]\n]\n"
+        );
+
+        assert_eq!(
+            run_err("json.parse(\"[1,\n 2,,]\");").message(),
+            "Invalid JSON at line 2, column 4: Expected a JSON value"
+        );
+        assert_eq!(
+            run_err("fun f() {} json.stringify([f]);").message(),
+            "Can't serialize function to JSON"
+        );
+        assert!(matches!(
+            run_err("var m = {}; m[\"self\"] = m; json.stringify(m);"),
+            RuntimeError::NotSerializable { .. }
+        ));
+    }
 }
@@ -0,0 +1,174 @@
+//! The iteration protocol behind `for (x in iterable)`.
+//!
+//! Lists yield their elements, maps their keys in insertion order, strings
+//! their characters and ranges their numbers. An instance is iterable if it
+//! has an `iter()` method, which returns an iterator object whose `next()`
+//! method returns each element in turn and `nil` once it is exhausted.
+
+use crate::expr::{exact_integer, Value};
+use crate::interpreter::Interpreter;
+use crate::list::List;
+use crate::lox::RuntimeError;
+use crate::token::Token;
+use std::vec;
+
+/// The numbers from `start` up to but not including `end`, counting by
+/// `step`, which is never zero. A negative step counts down.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Range {
+    pub start: i64,
+    pub end: i64,
+    pub step: i64,
+}
+
+/// `range(end)`, `range(start, end)` or `range(start, end, step)`.
+pub fn range(
+    interpreter: &mut Interpreter,
+    paren: &Token,
+    args: &[Value],
+) -> Result<Value, RuntimeError> {
+    let mut bounds = Vec::with_capacity(args.len());
+    for arg in args {
+        let bound = match arg {
+            Value::Int(n) => Some(*n),
+            Value::Number(n) => exact_integer(*n),
+            _ => None,
+        };
+        let Some(bound) = bound else {
+            return Err(RuntimeError::InvalidArgument {
+                paren: paren.clone(),
+                message: format!(
+                    "Arguments to range() must be integers, got {}",
+                    arg.type_name()
+                ),
+                trace: interpreter.trace(paren),
+            });
+        };
+        bounds.push(bound);
+    }
+
+    let range = match bounds[..] {
+        [end] => Range {
+            start: 0,
+            end,
+            step: 1,
+        },
+        [start, end] => Range {
+            start,
+            end,
+            step: 1,
+        },
+        [start, end, step] => Range { start, end, step },
+        _ => unreachable!("range() has an arity of 1 to 3"),
+    };
+    if range.step == 0 {
+        return Err(RuntimeError::InvalidArgument {
+            paren: paren.clone(),
+            message: "Step passed to range() must not be zero".to_string(),
+            trace: interpreter.trace(paren),
+        });
+    }
+    Ok(Value::Range(range))
+}
+
+pub enum LoxIterator {
+    /// Lists are read live, so elements pushed during the loop are visited.
+    List {
+        list: List,
+        next: usize,
+    },
+    /// A snapshot of a map's keys or a string's characters.
+    Values(vec::IntoIter<Value>),
+    Range {
+        next: Option<i64>,
+        range: Range,
+    },
+    /// The object returned by an instance's `iter()` method.
+    Object(Value),
+}
+
+impl LoxIterator {
+    /// Starts iterating over `iterable`. Errors are reported at `token`.
+    pub fn new(
+        interpreter: &mut Interpreter,
+        token: &Token,
+        iterable: &Value,
+    ) -> Result<Self, RuntimeError> {
+        match iterable {
+            Value::List(list) => Ok(LoxIterator::List {
+                list: list.clone(),
+                next: 0,
+            }),
+            Value::Map(map) => {
+                let keys: Vec<_> = map.borrow().iter().map(|(key, _)| key.to_value()).collect();
+                Ok(LoxIterator::Values(keys.into_iter()))
+            }
+            Value::String(s) => {
+                let chars: Vec<_> = s.chars().map(|c| Value::from(c.to_string())).collect();
+                Ok(LoxIterator::Values(chars.into_iter()))
+            }
+            Value::Range(range) => Ok(LoxIterator::Range {
+                next: Some(range.start),
+                range: *range,
+            }),
+            Value::Instance(_) => {
+                let iter = interpreter.get_property(iterable, &Self::method(token, "iter"))?;
+                let iterator = interpreter.call(iter, token, Vec::new())?;
+                interpreter.root(&iterator);
+                Ok(LoxIterator::Object(iterator))
+            }
+            _ => Err(RuntimeError::NotIterable {
+                token: token.clone(),
+                type_name: iterable.type_name(),
+                trace: interpreter.trace(token),
+            }),
+        }
+    }
+
+    /// Returns the next element, or `None` once the iteration is over.
+    pub fn next(
+        &mut self,
+        interpreter: &mut Interpreter,
+        token: &Token,
+    ) -> Result<Option<Value>, RuntimeError> {
+        match self {
+            LoxIterator::List { list, next } => {
+                let element = list.borrow().get(*next).cloned();
+                *next += 1;
+                Ok(element)
+            }
+            LoxIterator::Values(values) => Ok(values.next()),
+            LoxIterator::Range { next, range } => {
+                let Some(current) = *next else {
+                    return Ok(None);
+                };
+                let in_range = if range.step > 0 {
+                    current < range.end
+                } else {
+                    current > range.end
+                };
+                if !in_range {
+                    return Ok(None);
+                }
+                *next = current.checked_add(range.step);
+                Ok(Some(Value::Int(current)))
+            }
+            LoxIterator::Object(iterator) => {
+                let next = interpreter.get_property(iterator, &Self::method(token, "next"))?;
+                match interpreter.call(next, token, Vec::new())? {
+                    Value::Nil => Ok(None),
+                    element => Ok(Some(element)),
+                }
+            }
+        }
+    }
+
+    /// A token naming the protocol method `name`, for looking it up and for
+    /// reporting a missing method.
+    fn method(token: &Token, name: &str) -> Token {
+        Token {
+            span: token.span,
+            ..Token::identifier(name, token.line)
+        }
+    }
+}
@@ -0,0 +1,561 @@
+//! The `json` native module.
+//!
+//! `json.parse(text)` turns objects into maps, arrays into lists, numbers
+//! into integers when they are written without a fraction or exponent and
+//! fit in 64 bits, and `null` into nil. `json.stringify(value, indent?)`
+//! goes the other way; map keys that aren't strings are written as their
+//! printed form, and values with no JSON form are rejected.
+
+use crate::expr::Value;
+use crate::function::{NativeFn, NativeFunction};
+use crate::gc::Heap;
+use crate::interpreter::Interpreter;
+use crate::lox::RuntimeError;
+use crate::map::{LoxMap, MapKey};
+use crate::token::Token;
+use std::fmt::Write;
+use std::ops::RangeInclusive;
+use std::rc::Rc;
+
+/// Documents nested deeper than this are rejected rather than risking the
+/// host stack.
+const MAX_DEPTH: usize = 512;
+
+pub fn define(interpreter: &mut Interpreter) {
+    let functions: [(&'static str, RangeInclusive<usize>, NativeFn); 2] =
+        [("parse", 1..=1, parse), ("stringify", 1..=2, stringify)];
+    let members = functions
+        .into_iter()
+        .map(|(name, arity, function)| {
+            let native = NativeFunction::new(name, arity, function);
+            (name, Value::Native(Rc::new(native)))
+        })
+        .collect();
+    interpreter.define_native_module("json", members);
+}
+
+/// `json.parse(text)`.
+fn parse(
+    interpreter: &mut Interpreter,
+    paren: &Token,
+    args: &[Value],
+) -> Result<Value, RuntimeError> {
+    let Value::String(text) = &args[0] else {
+        return Err(RuntimeError::InvalidArgument {
+            paren: paren.clone(),
+            message: format!(
+                "Argument to parse() must be a string, got {}",
+                args[0].type_name()
+            ),
+            trace: interpreter.trace(paren),
+        });
+    };
+    let mut parser = JsonParser {
+        chars: text.chars().collect(),
+        current: 0,
+        heap: interpreter.heap(),
+    };
+    parser.document().map_err(|(position, message)| {
+        let (line, column) = parser.location(position);
+        RuntimeError::InvalidJson {
+            paren: paren.clone(),
+            line,
+            column,
+            message,
+            trace: interpreter.trace(paren),
+        }
+    })
+}
+
+/// A syntax error: the character index it was found at and a description.
+type ParseError = (usize, String);
+
+struct JsonParser<'a> {
+    chars: Vec<char>,
+    current: usize,
+    /// Where the arrays and objects parsed are allocated.
+    heap: &'a Heap,
+}
+
+impl JsonParser<'_> {
+    fn document(&mut self) -> Result<Value, ParseError> {
+        let value = self.value(0)?;
+        self.skip_whitespace();
+        if self.current < self.chars.len() {
+            return Err(self.error("Unexpected text after JSON value"));
+        }
+        Ok(value)
+    }
+
+    fn value(&mut self, depth: usize) -> Result<Value, ParseError> {
+        if depth > MAX_DEPTH {
+            return Err(self.error("JSON is nested too deeply"));
+        }
+        self.skip_whitespace();
+        match self.peek() {
+            Some('{') => self.object(depth),
+            Some('[') => self.array(depth),
+            Some('"') => Ok(Value::from(self.string()?)),
+            Some('-' | '0'..='9') => self.number(),
+            Some('t') => self.literal("true", Value::True),
+            Some('f') => self.literal("false", Value::False),
+            Some('n') => self.literal("null", Value::Nil),
+            Some(_) => Err(self.error("Expected a JSON value")),
+            None => Err(self.error("Unexpected end of JSON")),
+        }
+    }
+
+    fn object(&mut self, depth: usize) -> Result<Value, ParseError> {
+        self.current += 1;
+        let mut map = LoxMap::new();
+        self.skip_whitespace();
+        if self.eat('}') {
+            return Ok(self.heap.map(map));
+        }
+        loop {
+            self.skip_whitespace();
+            if self.peek() != Some('"') {
+                return Err(self.error("Expected a string key"));
+            }
+            let key = self.string()?;
+            self.skip_whitespace();
+            if !self.eat(':') {
+                return Err(self.error("Expected ':' after object key"));
+            }
+            let value = self.value(depth + 1)?;
+            map.insert(MapKey::String(key.into()), value);
+            self.skip_whitespace();
+            if self.eat('}') {
+                return Ok(self.heap.map(map));
+            }
+            if !self.eat(',') {
+                return Err(self.error("Expected ',' or '}' after object member"));
+            }
+        }
+    }
+
+    fn array(&mut self, depth: usize) -> Result<Value, ParseError> {
+        self.current += 1;
+        let mut elements = Vec::new();
+        self.skip_whitespace();
+        if self.eat(']') {
+            return Ok(self.heap.list(elements));
+        }
+        loop {
+            elements.push(self.value(depth + 1)?);
+            self.skip_whitespace();
+            if self.eat(']') {
+                return Ok(self.heap.list(elements));
+            }
+            if !self.eat(',') {
+                return Err(self.error("Expected ',' or ']' after array element"));
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<String, ParseError> {
+        self.current += 1;
+        let mut string = String::new();
+        loop {
+            match self.advance() {
+                None => return Err(self.error("Unterminated string")),
+                Some('"') => return Ok(string),
+                Some('\\') => {
+                    let escaped = match self.advance() {
+                        Some('"') => '"',
+                        Some('\\') => '\\',
+                        Some('/') => '/',
+                        Some('b') => '\u{8}',
+                        Some('f') => '\u{c}',
+                        Some('n') => '\n',
+                        Some('r') => '\r',
+                        Some('t') => '\t',
+                        Some('u') => self.unicode_escape()?,
+                        _ => {
+                            self.current -= 1;
+                            return Err(self.error("Invalid escape sequence"));
+                        }
+                    };
+                    string.push(escaped);
+                }
+                Some(c) if c < ' ' => {
+                    self.current -= 1;
+                    return Err(self.error("Control character in string"));
+                }
+                Some(c) => string.push(c),
+            }
+        }
+    }
+
+    /// The character after `\u`, joining a surrogate pair if there is one.
+    fn unicode_escape(&mut self) -> Result<char, ParseError> {
+        let high = self.hex4()?;
+        if !(0xd800..0xdc00).contains(&high) {
+            return char::from_u32(high).ok_or_else(|| self.error("Invalid Unicode escape"));
+        }
+        if !(self.eat('\\') && self.eat('u')) {
+            return Err(self.error("Expected a low surrogate after a high surrogate"));
+        }
+        let low = self.hex4()?;
+        if !(0xdc00..0xe000).contains(&low) {
+            return Err(self.error("Invalid low surrogate"));
+        }
+        let code = 0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00);
+        char::from_u32(code).ok_or_else(|| self.error("Invalid Unicode escape"))
+    }
+
+    fn hex4(&mut self) -> Result<u32, ParseError> {
+        let mut code = 0;
+        for _ in 0..4 {
+            let Some(digit) = self.peek().and_then(|c| c.to_digit(16)) else {
+                return Err(self.error("Expected four hex digits"));
+            };
+            self.current += 1;
+            code = code * 16 + digit;
+        }
+        Ok(code)
+    }
+
+    fn number(&mut self) -> Result<Value, ParseError> {
+        let start = self.current;
+        self.eat('-');
+        match self.peek() {
+            Some('0') => self.current += 1,
+            Some('1'..='9') => self.digits(),
+            _ => return Err(self.error("Expected a digit")),
+        }
+        let mut integral = true;
+        if self.eat('.') {
+            integral = false;
+            if !self.peek().is_some_and(|c| c.is_ascii_digit()) {
+                return Err(self.error("Expected a digit after '.'"));
+            }
+            self.digits();
+        }
+        if self.eat('e') || self.eat('E') {
+            integral = false;
+            if !self.eat('+') {
+                self.eat('-');
+            }
+            if !self.peek().is_some_and(|c| c.is_ascii_digit()) {
+                return Err(self.error("Expected a digit in exponent"));
+            }
+            self.digits();
+        }
+
+        let text: String = self.chars[start..self.current].iter().collect();
+        if integral {
+            if let Ok(int) = text.parse() {
+                return Ok(Value::Int(int));
+            }
+        }
+        text.parse()
+            .map(Value::Number)
+            .map_err(|_| (start, "Invalid number".to_string()))
+    }
+
+    fn digits(&mut self) {
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.current += 1;
+        }
+    }
+
+    fn literal(&mut self, word: &str, value: Value) -> Result<Value, ParseError> {
+        for expected in word.chars() {
+            if !self.eat(expected) {
+                return Err(self.error("Expected a JSON value"));
+            }
+        }
+        Ok(value)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while matches!(self.peek(), Some(' ' | '\t' | '\n' | '\r')) {
+            self.current += 1;
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.current).copied()
+    }
+
+    fn advance(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.current += 1;
+        Some(c)
+    }
+
+    fn eat(&mut self, expected: char) -> bool {
+        if self.peek() == Some(expected) {
+            self.current += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn error(&self, message: &str) -> ParseError {
+        (self.current, message.to_string())
+    }
+
+    /// The 1-based line and column of the character at `position`.
+    fn location(&self, position: usize) -> (usize, usize) {
+        let before = &self.chars[..position.min(self.chars.len())];
+        let line = before.iter().filter(|&&c| c == '\n').count() + 1;
+        let column = before.iter().rev().take_while(|&&c| c != '\n').count() + 1;
+        (line, column)
+    }
+}
+
+/// `json.stringify(value, indent?)`. With an indent, each element goes on
+/// its own line, indented by that many spaces per level.
+fn stringify(
+    interpreter: &mut Interpreter,
+    paren: &Token,
+    args: &[Value],
+) -> Result<Value, RuntimeError> {
+    let indent = match args.get(1) {
+        None | Some(Value::Nil) => None,
+        Some(Value::Int(indent)) if (0..=10).contains(indent) => Some(*indent as usize),
+        Some(indent) => {
+            return Err(RuntimeError::InvalidArgument {
+                paren: paren.clone(),
+                message: format!(
+                    "Indent passed to stringify() must be an integer from 0 to 10, got {}",
+                    indent
+                ),
+                trace: interpreter.trace(paren),
+            })
+        }
+    };
+    let mut writer = JsonWriter {
+        indent,
+        output: String::new(),
+        open: Vec::new(),
+    };
+    match writer.value(&args[0]) {
+        Ok(()) => Ok(Value::from(writer.output)),
+        Err(reason) => Err(RuntimeError::NotSerializable {
+            paren: paren.clone(),
+            reason,
+            trace: interpreter.trace(paren),
+        }),
+    }
+}
+
+struct JsonWriter {
+    indent: Option<usize>,
+    output: String,
+    /// The lists and maps being written, to detect cycles.
+    open: Vec<*const ()>,
+}
+
+impl JsonWriter {
+    fn value(&mut self, value: &Value) -> Result<(), String> {
+        match value {
+            Value::Nil => self.output.push_str("null"),
+            Value::True => self.output.push_str("true"),
+            Value::False => self.output.push_str("false"),
+            Value::Int(n) => write!(self.output, "{}", n).unwrap(),
+            Value::Number(n) if n.is_finite() => write!(self.output, "{}", n).unwrap(),
+            Value::Number(n) => return Err(format!("Can't serialize {} to JSON", n)),
+            Value::String(s) => self.string(s),
+            Value::List(list) => {
+                self.enter(Rc::as_ptr(list) as *const (), "list")?;
+                let elements = list.borrow();
+                self.sequence('[', ']', elements.len(), |writer, i| {
+                    writer.value(&elements[i])
+                })?;
+                self.open.pop();
+            }
+            Value::Map(map) => {
+                self.enter(Rc::as_ptr(map) as *const (), "map")?;
+                let map = map.borrow();
+                let entries: Vec<_> = map.iter().collect();
+                self.sequence('{', '}', entries.len(), |writer, i| {
+                    let (key, value) = entries[i];
+                    match key {
+                        MapKey::String(key) => writer.string(key),
+                        MapKey::Nil => writer.string("null"),
+                        key => writer.string(&key.to_value().to_string()),
+                    }
+                    writer.output.push(':');
+                    if writer.indent.is_some() {
+                        writer.output.push(' ');
+                    }
+                    writer.value(value)
+                })?;
+                self.open.pop();
+            }
+            _ => return Err(format!("Can't serialize {} to JSON", value.type_name())),
+        }
+        Ok(())
+    }
+
+    fn enter(&mut self, ptr: *const (), type_name: &str) -> Result<(), String> {
+        if self.open.contains(&ptr) {
+            return Err(format!(
+                "Can't serialize a {} that contains itself",
+                type_name
+            ));
+        }
+        if self.open.len() >= MAX_DEPTH {
+            return Err("Value is nested too deeply to serialize".to_string());
+        }
+        self.open.push(ptr);
+        Ok(())
+    }
+
+    /// Writes `len` items between `open` and `close`, separated by commas
+    /// and, when indenting, newlines.
+    fn sequence(
+        &mut self,
+        open: char,
+        close: char,
+        len: usize,
+        mut item: impl FnMut(&mut Self, usize) -> Result<(), String>,
+    ) -> Result<(), String> {
+        self.output.push(open);
+        for i in 0..len {
+            if i > 0 {
+                self.output.push(',');
+            }
+            self.newline(self.open.len());
+            item(self, i)?;
+        }
+        if len > 0 {
+            self.newline(self.open.len() - 1);
+        }
+        self.output.push(close);
+        Ok(())
+    }
+
+    fn newline(&mut self, level: usize) {
+        if let Some(indent) = self.indent {
+            self.output.push('\n');
+            self.output
+                .extend(std::iter::repeat(' ').take(indent * level));
+        }
+    }
+
+    fn string(&mut self, s: &str) {
+        self.output.push('"');
+        for c in s.chars() {
+            match c {
+                '"' => self.output.push_str("\\\""),
+                '\\' => self.output.push_str("\\\\"),
+                '\n' => self.output.push_str("\\n"),
+                '\r' => self.output.push_str("\\r"),
+                '\t' => self.output.push_str("\\t"),
+                '\u{8}' => self.output.push_str("\\b"),
+                '\u{c}' => self.output.push_str("\\f"),
+                c if c < ' ' => write!(self.output, "\\u{:04x}", c as u32).unwrap(),
+                c => self.output.push(c),
+            }
+        }
+        self.output.push('"');
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::cell::RefCell;
+
+    fn parse(text: &str) -> Result<Value, (usize, usize, String)> {
+        let heap = Heap::new();
+        let mut parser = JsonParser {
+            chars: text.chars().collect(),
+            current: 0,
+            heap: &heap,
+        };
+        parser.document().map_err(|(position, message)| {
+            let (line, column) = parser.location(position);
+            (line, column, message)
+        })
+    }
+
+    fn stringify(value: &Value, indent: Option<usize>) -> Result<String, String> {
+        let mut writer = JsonWriter {
+            indent,
+            output: String::new(),
+            open: Vec::new(),
+        };
+        writer.value(value).map(|()| writer.output)
+    }
+
+    #[test]
+    fn test_parse() {
+        let value = parse(
+            r#" {"name": "rslox", "tags": ["a", "b\n\"c\"", "\u00e9\ud83d\ude00"],
+                "version": 1, "ratio": -0.5e1, "big": 12345678901234567890,
+                "ok": true, "no": false, "none": null, "empty": {}, "list": []} "#,
+        )
+        .unwrap();
+        assert_eq!(
+            value.to_string(),
+            "{\"name\": \"rslox\", \"tags\": [\"a\", \"b\\n\\\"c\\\"\", \"é😀\"], \
+             \"version\": 1, \"ratio\": -5, \"big\": 12345678901234567000, \"ok\": true, \
+             \"no\": false, \"none\": nil, \"empty\": {}, \"list\": []}"
+        );
+        assert!(matches!(parse("1").unwrap(), Value::Int(1)));
+        assert!(matches!(parse("1.0").unwrap(), Value::Number(_)));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let error = |text| parse(text).unwrap_err();
+        assert_eq!(
+            error("{\n  \"a\": 1,\n  \"b\" 2\n}"),
+            (3, 7, "Expected ':' after object key".to_string())
+        );
+        assert_eq!(error(""), (1, 1, "Unexpected end of JSON".to_string()));
+        assert_eq!(
+            error("[1, 2"),
+            (1, 6, "Expected ',' or ']' after array element".to_string())
+        );
+        assert_eq!(error("[1,]").2, "Expected a JSON value");
+        assert_eq!(error("01").2, "Unexpected text after JSON value");
+        assert_eq!(error("1.").2, "Expected a digit after '.'");
+        assert_eq!(error("\"\\x\"").2, "Invalid escape sequence");
+        assert_eq!(error("\"abc").2, "Unterminated string");
+        assert_eq!(
+            error("\"\\ud800\"").2,
+            "Expected a low surrogate after a high surrogate"
+        );
+        assert_eq!(error("nul").2, "Expected a JSON value");
+        assert_eq!(error(&"[".repeat(600)).2, "JSON is nested too deeply");
+    }
+
+    #[test]
+    fn test_stringify() {
+        let value = parse(r#"{"a": [1, 2.5, "x\ty", null], "b": {}, "c": []}"#).unwrap();
+        assert_eq!(
+            stringify(&value, None).unwrap(),
+            r#"{"a":[1,2.5,"x\ty",null],"b":{},"c":[]}"#
+        );
+        assert_eq!(
+            stringify(&value, Some(2)).unwrap(),
+            "{\n  \"a\": [\n    1,\n    2.5,\n    \"x\\ty\",\n    null\n  ],\n  \"b\": {},\n  \"c\": []\n}"
+        );
+
+        let mut map = LoxMap::new();
+        map.insert(MapKey::Int(1), Value::True);
+        map.insert(MapKey::Nil, Value::False);
+        let map = Value::Map(Rc::new(RefCell::new(map)));
+        assert_eq!(stringify(&map, None).unwrap(), r#"{"1":true,"null":false}"#);
+
+        let list = Rc::new(RefCell::new(Vec::new()));
+        list.borrow_mut().push(Value::List(Rc::clone(&list)));
+        assert_eq!(
+            stringify(&Value::List(Rc::clone(&list)), None).unwrap_err(),
+            "Can't serialize a list that contains itself"
+        );
+        list.borrow_mut().clear();
+
+        assert_eq!(
+            stringify(&Value::Number(f64::NAN), None).unwrap_err(),
+            "Can't serialize NaN to JSON"
+        );
+    }
+}
@@ -0,0 +1,31 @@
+pub mod ast;
+#[cfg(feature = "bench")]
+pub mod bench;
+pub mod class;
+pub mod environment;
+pub mod error;
+pub mod exception;
+pub mod expr;
+pub mod function;
+pub mod gc;
+pub mod interner;
+pub mod interpreter;
+pub mod iterator;
+pub mod json;
+pub mod list;
+pub mod lox;
+pub mod map;
+pub mod math;
+pub mod module;
+pub mod parser;
+pub mod printer;
+pub mod random;
+pub mod scanner;
+pub mod stmt;
+pub mod string;
+pub mod system;
+#[cfg(test)]
+mod test_util;
+pub mod time;
+pub mod token;
+pub mod token_type;
@@ -0,0 +1,342 @@
+//! Built-in methods of `Value::List`.
+
+use crate::expr::{compare_numbers, Value};
+use crate::function::{NativeFn, NativeFunction};
+use crate::interpreter::Interpreter;
+use crate::lox::RuntimeError;
+use crate::token::Token;
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::ops::RangeInclusive;
+use std::rc::Rc;
+
+pub type List = Rc<RefCell<Vec<Value>>>;
+
+/// Looks up the built-in method `name`, bound to `list`.
+pub fn method(list: &List, name: &str) -> Option<NativeFunction> {
+    let (name, arity, function): (&'static str, RangeInclusive<usize>, NativeFn) = match name {
+        "push" => ("push", 1..=1, push),
+        "pop" => ("pop", 0..=0, pop),
+        "len" => ("len", 0..=0, len),
+        "insert" => ("insert", 2..=2, insert),
+        "remove" => ("remove", 1..=1, remove),
+        "slice" => ("slice", 1..=2, slice),
+        "map" => ("map", 1..=1, map),
+        "filter" => ("filter", 1..=1, filter),
+        "reduce" => ("reduce", 1..=2, reduce),
+        "sort" => ("sort", 0..=1, sort),
+        "join" => ("join", 1..=1, join),
+        _ => return None,
+    };
+    Some(NativeFunction::method(
+        name,
+        arity,
+        function,
+        Value::List(Rc::clone(list)),
+    ))
+}
+
+/// Resolves an element index, counting from the end if it is negative.
+pub fn resolve_index(index: i64, len: usize) -> Option<usize> {
+    resolve_position(index, len).filter(|&i| i < len)
+}
+
+/// Like `resolve_index`, but also accepts the position just past the last
+/// element, for inserting and slicing.
+pub(crate) fn resolve_position(index: i64, len: usize) -> Option<usize> {
+    let resolved = if index < 0 {
+        index.checked_add(i64::try_from(len).ok()?)?
+    } else {
+        index
+    };
+    usize::try_from(resolved).ok().filter(|&i| i <= len)
+}
+
+fn this(args: &[Value]) -> &List {
+    match &args[0] {
+        Value::List(list) => list,
+        _ => unreachable!("list method bound to a non-list"),
+    }
+}
+
+pub(crate) fn integer_arg(
+    interpreter: &Interpreter,
+    paren: &Token,
+    method: &str,
+    value: &Value,
+) -> Result<i64, RuntimeError> {
+    match value {
+        Value::Int(n) => Ok(*n),
+        _ => Err(RuntimeError::InvalidArgument {
+            paren: paren.clone(),
+            message: format!(
+                "Index passed to {}() must be an integer, got {}",
+                method,
+                value.type_name()
+            ),
+            trace: interpreter.trace(paren),
+        }),
+    }
+}
+
+pub(crate) fn out_of_bounds(
+    interpreter: &Interpreter,
+    paren: &Token,
+    index: i64,
+    len: usize,
+) -> RuntimeError {
+    RuntimeError::IndexOutOfBounds {
+        bracket: paren.clone(),
+        index,
+        len,
+        trace: interpreter.trace(paren),
+    }
+}
+
+fn push(_: &mut Interpreter, _: &Token, args: &[Value]) -> Result<Value, RuntimeError> {
+    this(args).borrow_mut().push(args[1].clone());
+    Ok(Value::Nil)
+}
+
+fn pop(
+    interpreter: &mut Interpreter,
+    paren: &Token,
+    args: &[Value],
+) -> Result<Value, RuntimeError> {
+    this(args)
+        .borrow_mut()
+        .pop()
+        .ok_or_else(|| out_of_bounds(interpreter, paren, -1, 0))
+}
+
+fn len(_: &mut Interpreter, _: &Token, args: &[Value]) -> Result<Value, RuntimeError> {
+    Ok(Value::Int(this(args).borrow().len() as i64))
+}
+
+fn insert(
+    interpreter: &mut Interpreter,
+    paren: &Token,
+    args: &[Value],
+) -> Result<Value, RuntimeError> {
+    let mut list = this(args).borrow_mut();
+    let index = integer_arg(interpreter, paren, "insert", &args[1])?;
+    let Some(position) = resolve_position(index, list.len()) else {
+        return Err(out_of_bounds(interpreter, paren, index, list.len()));
+    };
+    list.insert(position, args[2].clone());
+    Ok(Value::Nil)
+}
+
+fn remove(
+    interpreter: &mut Interpreter,
+    paren: &Token,
+    args: &[Value],
+) -> Result<Value, RuntimeError> {
+    let mut list = this(args).borrow_mut();
+    let index = integer_arg(interpreter, paren, "remove", &args[1])?;
+    let Some(position) = resolve_index(index, list.len()) else {
+        return Err(out_of_bounds(interpreter, paren, index, list.len()));
+    };
+    Ok(list.remove(position))
+}
+
+/// `slice(start, end?)` copies the elements from `start` up to but not
+/// including `end`, which defaults to the end of the list.
+fn slice(
+    interpreter: &mut Interpreter,
+    paren: &Token,
+    args: &[Value],
+) -> Result<Value, RuntimeError> {
+    let list = this(args).borrow();
+    let start = integer_arg(interpreter, paren, "slice", &args[1])?;
+    let end = match args.get(2) {
+        Some(end) => integer_arg(interpreter, paren, "slice", end)?,
+        None => list.len() as i64,
+    };
+
+    let Some(from) = resolve_position(start, list.len()) else {
+        return Err(out_of_bounds(interpreter, paren, start, list.len()));
+    };
+    let Some(to) = resolve_position(end, list.len()).filter(|&to| to >= from) else {
+        return Err(out_of_bounds(interpreter, paren, end, list.len()));
+    };
+    Ok(interpreter.heap().list(list[from..to].to_vec()))
+}
+
+// The higher-order methods work on a snapshot of the list, so callbacks are
+// free to modify the list while it is being traversed.
+
+/// Copies the list's elements, rooting them so that they survive
+/// collections while callbacks run, even if one removes them from the list.
+fn snapshot(interpreter: &mut Interpreter, args: &[Value]) -> Vec<Value> {
+    let items = this(args).borrow().clone();
+    for item in &items {
+        interpreter.root(item);
+    }
+    items
+}
+
+fn map(
+    interpreter: &mut Interpreter,
+    paren: &Token,
+    args: &[Value],
+) -> Result<Value, RuntimeError> {
+    let items = snapshot(interpreter, args);
+    let mapped = items
+        .into_iter()
+        .map(|item| {
+            let value = interpreter.call(args[1].clone(), paren, vec![item])?;
+            interpreter.root(&value);
+            Ok(value)
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(interpreter.heap().list(mapped))
+}
+
+fn filter(
+    interpreter: &mut Interpreter,
+    paren: &Token,
+    args: &[Value],
+) -> Result<Value, RuntimeError> {
+    let items = snapshot(interpreter, args);
+    let mut kept = Vec::new();
+    for item in items {
+        let keep = interpreter.call(args[1].clone(), paren, vec![item.clone()])?;
+        if bool::from(&keep) {
+            kept.push(item);
+        }
+    }
+    Ok(interpreter.heap().list(kept))
+}
+
+/// `reduce(fn, initial?)` folds the list with `fn(accumulator, element)`,
+/// starting from the first element if there is no initial value.
+fn reduce(
+    interpreter: &mut Interpreter,
+    paren: &Token,
+    args: &[Value],
+) -> Result<Value, RuntimeError> {
+    let mut items = snapshot(interpreter, args).into_iter();
+    let mut accumulator = match args.get(2) {
+        Some(initial) => initial.clone(),
+        None => items.next().ok_or_else(|| RuntimeError::InvalidArgument {
+            paren: paren.clone(),
+            message: "Can't reduce() an empty list without an initial value".to_string(),
+            trace: interpreter.trace(paren),
+        })?,
+    };
+    for item in items {
+        accumulator = interpreter.call(args[1].clone(), paren, vec![accumulator, item])?;
+    }
+    Ok(accumulator)
+}
+
+/// `sort(compare?)` sorts the list in place. Without a comparator, numbers
+/// sort numerically and strings lexicographically. A comparator is called
+/// as `compare(a, b)` and returns a negative number, zero or a positive
+/// number. The sort is stable.
+fn sort(
+    interpreter: &mut Interpreter,
+    paren: &Token,
+    args: &[Value],
+) -> Result<Value, RuntimeError> {
+    let items = snapshot(interpreter, args);
+    let compare = args.get(1).cloned();
+
+    let sorted = merge_sort(items, &mut |a, b| match &compare {
+        Some(compare) => {
+            let order = interpreter.call(compare.clone(), paren, vec![a.clone(), b.clone()])?;
+            match order.as_f64() {
+                Some(order) => Ok(order > 0.0),
+                None => Err(RuntimeError::InvalidArgument {
+                    paren: paren.clone(),
+                    message: format!(
+                        "Comparator passed to sort() must return a number, got {}",
+                        order.type_name()
+                    ),
+                    trace: interpreter.trace(paren),
+                }),
+            }
+        }
+        None => match natural_order(a, b) {
+            Some(order) => Ok(order == Ordering::Greater),
+            None => Err(RuntimeError::InvalidArgument {
+                paren: paren.clone(),
+                message: format!(
+                    "Can't sort() {} and {} without a comparator",
+                    a.type_name(),
+                    b.type_name()
+                ),
+                trace: interpreter.trace(paren),
+            }),
+        },
+    })?;
+
+    *this(args).borrow_mut() = sorted;
+    Ok(Value::Nil)
+}
+
+fn natural_order(a: &Value, b: &Value) -> Option<Ordering> {
+    match (a, b) {
+        (Value::Int(a), Value::Int(b)) => Some(a.cmp(b)),
+        (Value::String(a), Value::String(b)) => Some(a.cmp(b)),
+        (Value::Number(a), Value::Number(b)) => Some(a.total_cmp(b)),
+        // Mixed integers and floats compare exactly. Only NaN, which has no
+        // exact order, falls back to its place in the float total order.
+        _ => compare_numbers(a, b).or_else(|| Some(a.as_f64()?.total_cmp(&b.as_f64()?))),
+    }
+}
+
+/// A stable merge sort over a fallible comparison. `greater(a, b)` reports
+/// whether `a` must come after `b`. Unlike `slice::sort_by`, an
+/// inconsistent user comparator can't make this panic.
+fn merge_sort<F>(mut items: Vec<Value>, greater: &mut F) -> Result<Vec<Value>, RuntimeError>
+where
+    F: FnMut(&Value, &Value) -> Result<bool, RuntimeError>,
+{
+    if items.len() <= 1 {
+        return Ok(items);
+    }
+
+    let right = items.split_off(items.len() / 2);
+    let left = merge_sort(items, greater)?;
+    let right = merge_sort(right, greater)?;
+
+    let mut merged = Vec::with_capacity(left.len() + right.len());
+    let mut left = left.into_iter().peekable();
+    let mut right = right.into_iter().peekable();
+    while let (Some(l), Some(r)) = (left.peek(), right.peek()) {
+        if greater(l, r)? {
+            merged.extend(right.next());
+        } else {
+            merged.extend(left.next());
+        }
+    }
+    merged.extend(left);
+    merged.extend(right);
+    Ok(merged)
+}
+
+fn join(
+    interpreter: &mut Interpreter,
+    paren: &Token,
+    args: &[Value],
+) -> Result<Value, RuntimeError> {
+    let Value::String(separator) = &args[1] else {
+        return Err(RuntimeError::InvalidArgument {
+            paren: paren.clone(),
+            message: format!(
+                "Separator passed to join() must be a string, got {}",
+                args[1].type_name()
+            ),
+            trace: interpreter.trace(paren),
+        });
+    };
+    let joined = this(args)
+        .borrow()
+        .iter()
+        .map(|item| item.to_string())
+        .collect::<Vec<_>>()
+        .join(separator);
+    Ok(Value::from(joined))
+}
@@ -1,4 +1,409 @@
-#[derive(Debug)]
-pub struct RuntimeError {
-    pub message: String,
+use crate::expr::Value;
+use crate::interner::Symbol;
+use crate::map::MapKey;
+use crate::system::Capability;
+use crate::token::Token;
+use crate::token_type::TokenType;
+use std::fmt;
+use std::ops::RangeInclusive;
+
+/// One entry of a call-stack trace: the code that was executing and the
+/// line it had reached.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    pub code: Code,
+    pub line: u32,
+}
+
+/// What a frame of a trace was executing.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Code {
+    /// The top level of the main script.
+    Script,
+    /// The top level of a module, named by the path it was imported by.
+    Module(String),
+    Function(String),
+}
+
+impl fmt::Display for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.code {
+            Code::Script => write!(f, "[line {}] in script", self.line),
+            Code::Module(path) => write!(f, "[line {}] in module {}", self.line, path),
+            Code::Function(name) => write!(f, "[line {}] in {}()", self.line, name),
+        }
+    }
+}
+
+/// An error raised while executing a program. Every kind carries the token
+/// the error is reported at and the call stack at that point, innermost
+/// frame first.
+#[derive(Debug, Clone)]
+pub enum RuntimeError {
+    /// An operator was applied to operands of the wrong type. Unary
+    /// operators have no `right_type`.
+    TypeMismatch {
+        op: Token,
+        left_type: &'static str,
+        right_type: Option<&'static str>,
+        trace: Vec<Frame>,
+    },
+    UndefinedVariable {
+        name: Token,
+        trace: Vec<Frame>,
+    },
+    NotCallable {
+        paren: Token,
+        callee_type: &'static str,
+        trace: Vec<Frame>,
+    },
+    ArityMismatch {
+        paren: Token,
+        expected: RangeInclusive<usize>,
+        got: usize,
+        trace: Vec<Frame>,
+    },
+    UndefinedProperty {
+        name: Token,
+        receiver_type: &'static str,
+        trace: Vec<Frame>,
+    },
+    StackOverflow {
+        paren: Token,
+        trace: Vec<Frame>,
+    },
+    /// The right operand of `%` or `~/` was zero.
+    DivisionByZero {
+        op: Token,
+        trace: Vec<Frame>,
+    },
+    /// A bitwise operator was given a number that isn't a whole number in
+    /// the 64-bit integer range.
+    NonIntegral {
+        op: Token,
+        value: f64,
+        trace: Vec<Frame>,
+    },
+    /// Integer arithmetic whose result doesn't fit in an `i64`.
+    IntegerOverflow {
+        op: Token,
+        trace: Vec<Frame>,
+    },
+    /// A list index past either end of the list.
+    IndexOutOfBounds {
+        bracket: Token,
+        index: i64,
+        len: usize,
+        trace: Vec<Frame>,
+    },
+    /// A list was indexed with something other than an integer.
+    InvalidIndex {
+        bracket: Token,
+        index_type: &'static str,
+        trace: Vec<Frame>,
+    },
+    NotIndexable {
+        bracket: Token,
+        receiver_type: &'static str,
+        trace: Vec<Frame>,
+    },
+    /// A map key of a type that can't be hashed, or NaN.
+    UnhashableKey {
+        token: Token,
+        key_type: &'static str,
+        trace: Vec<Frame>,
+    },
+    /// A map was indexed with a key it doesn't contain.
+    MissingKey {
+        bracket: Token,
+        key: MapKey,
+        trace: Vec<Frame>,
+    },
+    /// A `for-in` loop over a value that can't be iterated.
+    NotIterable {
+        token: Token,
+        type_name: &'static str,
+        trace: Vec<Frame>,
+    },
+    /// A value raised by `throw` that no `catch` handled.
+    Thrown {
+        keyword: Token,
+        value: Value,
+        trace: Vec<Frame>,
+    },
+    /// A module that can't be found, read or compiled.
+    ImportFailed {
+        keyword: Token,
+        message: String,
+        trace: Vec<Frame>,
+    },
+    /// An import of a module that is still loading. `chain` lists the
+    /// modules from the main script to the repeated one.
+    ImportCycle {
+        keyword: Token,
+        chain: Vec<String>,
+        trace: Vec<Frame>,
+    },
+    /// A native function was called with an argument it can't use.
+    InvalidArgument {
+        paren: Token,
+        message: String,
+        trace: Vec<Frame>,
+    },
+    /// A native function or an import that needs a capability the
+    /// interpreter wasn't granted. `operation` names what was attempted.
+    PermissionDenied {
+        token: Token,
+        operation: &'static str,
+        capability: Capability,
+        trace: Vec<Frame>,
+    },
+    /// A file or directory operation that failed.
+    Io {
+        paren: Token,
+        message: String,
+        trace: Vec<Frame>,
+    },
+    /// A call to `exit(code)`. It unwinds like an error, running `finally`
+    /// blocks, but `catch` can't handle it.
+    Exit {
+        paren: Token,
+        code: i32,
+        trace: Vec<Frame>,
+    },
+    /// Text passed to `json.parse` that isn't valid JSON. `line` and
+    /// `column` locate the problem within the text.
+    InvalidJson {
+        paren: Token,
+        line: usize,
+        column: usize,
+        message: String,
+        trace: Vec<Frame>,
+    },
+    /// A value passed to `json.stringify` that has no JSON form.
+    NotSerializable {
+        paren: Token,
+        reason: String,
+        trace: Vec<Frame>,
+    },
+    /// A shift amount outside of `0..64`.
+    InvalidShift {
+        op: Token,
+        amount: i64,
+        trace: Vec<Frame>,
+    },
+}
+
+impl RuntimeError {
+    pub fn token(&self) -> &Token {
+        match self {
+            RuntimeError::TypeMismatch { op: token, .. }
+            | RuntimeError::UndefinedVariable { name: token, .. }
+            | RuntimeError::NotCallable { paren: token, .. }
+            | RuntimeError::ArityMismatch { paren: token, .. }
+            | RuntimeError::UndefinedProperty { name: token, .. }
+            | RuntimeError::StackOverflow { paren: token, .. }
+            | RuntimeError::DivisionByZero { op: token, .. }
+            | RuntimeError::NonIntegral { op: token, .. }
+            | RuntimeError::IntegerOverflow { op: token, .. }
+            | RuntimeError::InvalidShift { op: token, .. }
+            | RuntimeError::IndexOutOfBounds { bracket: token, .. }
+            | RuntimeError::InvalidIndex { bracket: token, .. }
+            | RuntimeError::NotIndexable { bracket: token, .. }
+            | RuntimeError::UnhashableKey { token, .. }
+            | RuntimeError::MissingKey { bracket: token, .. }
+            | RuntimeError::NotIterable { token, .. }
+            | RuntimeError::Thrown { keyword: token, .. }
+            | RuntimeError::ImportFailed { keyword: token, .. }
+            | RuntimeError::ImportCycle { keyword: token, .. }
+            | RuntimeError::InvalidArgument { paren: token, .. }
+            | RuntimeError::PermissionDenied { token, .. }
+            | RuntimeError::Io { paren: token, .. }
+            | RuntimeError::Exit { paren: token, .. }
+            | RuntimeError::InvalidJson { paren: token, .. }
+            | RuntimeError::NotSerializable { paren: token, .. } => token,
+        }
+    }
+
+    pub fn trace(&self) -> &[Frame] {
+        match self {
+            RuntimeError::TypeMismatch { trace, .. }
+            | RuntimeError::UndefinedVariable { trace, .. }
+            | RuntimeError::NotCallable { trace, .. }
+            | RuntimeError::ArityMismatch { trace, .. }
+            | RuntimeError::UndefinedProperty { trace, .. }
+            | RuntimeError::StackOverflow { trace, .. }
+            | RuntimeError::DivisionByZero { trace, .. }
+            | RuntimeError::NonIntegral { trace, .. }
+            | RuntimeError::IntegerOverflow { trace, .. }
+            | RuntimeError::InvalidShift { trace, .. }
+            | RuntimeError::IndexOutOfBounds { trace, .. }
+            | RuntimeError::InvalidIndex { trace, .. }
+            | RuntimeError::NotIndexable { trace, .. }
+            | RuntimeError::UnhashableKey { trace, .. }
+            | RuntimeError::MissingKey { trace, .. }
+            | RuntimeError::NotIterable { trace, .. }
+            | RuntimeError::Thrown { trace, .. }
+            | RuntimeError::ImportFailed { trace, .. }
+            | RuntimeError::ImportCycle { trace, .. }
+            | RuntimeError::InvalidArgument { trace, .. }
+            | RuntimeError::PermissionDenied { trace, .. }
+            | RuntimeError::Io { trace, .. }
+            | RuntimeError::Exit { trace, .. }
+            | RuntimeError::InvalidJson { trace, .. }
+            | RuntimeError::NotSerializable { trace, .. } => trace,
+        }
+    }
+
+    /// Whether a `catch` clause can handle the error.
+    pub fn is_catchable(&self) -> bool {
+        !matches!(self, RuntimeError::Exit { .. })
+    }
+
+    pub fn line(&self) -> u32 {
+        self.token().line
+    }
+
+    /// The error description without its location.
+    pub fn message(&self) -> String {
+        match self {
+            RuntimeError::TypeMismatch {
+                op,
+                left_type,
+                right_type: Some(right_type),
+                ..
+            } => match op.type_ {
+                TokenType::Plus => format!(
+                    "Operands to '+' must be two numbers or two strings, got {} and {}",
+                    left_type, right_type
+                ),
+                _ => format!(
+                    "Operands to '{}' must be numbers, got {} and {}",
+                    op.lexeme(),
+                    left_type,
+                    right_type
+                ),
+            },
+            RuntimeError::TypeMismatch {
+                op,
+                left_type,
+                right_type: None,
+                ..
+            } => format!(
+                "Operand to '{}' must be a number, got {}",
+                op.lexeme(),
+                left_type
+            ),
+            RuntimeError::UndefinedVariable { name, .. } => {
+                format!("Undefined variable '{}'", name.lexeme())
+            }
+            RuntimeError::NotCallable { callee_type, .. } => {
+                format!("Can only call functions and classes, got {}", callee_type)
+            }
+            RuntimeError::ArityMismatch { expected, got, .. } => {
+                if expected.start() == expected.end() {
+                    format!("Expected {} arguments but got {}", expected.start(), got)
+                } else {
+                    format!(
+                        "Expected {} to {} arguments but got {}",
+                        expected.start(),
+                        expected.end(),
+                        got
+                    )
+                }
+            }
+            RuntimeError::UndefinedProperty {
+                name,
+                receiver_type,
+                ..
+            } => format!(
+                "Undefined property '{}' on {}",
+                name.lexeme(),
+                receiver_type
+            ),
+            RuntimeError::StackOverflow { .. } => "Stack overflow".to_string(),
+            RuntimeError::DivisionByZero { op, .. } => {
+                format!("Right operand of '{}' must not be zero", op.lexeme())
+            }
+            RuntimeError::NonIntegral { op, value, .. } => {
+                format!(
+                    "Operands to '{}' must be integers, got {}",
+                    op.lexeme(),
+                    value
+                )
+            }
+            RuntimeError::IntegerOverflow { op, .. } => {
+                format!("Result of '{}' overflows a 64-bit integer", op.lexeme())
+            }
+            RuntimeError::InvalidShift { amount, .. } => {
+                format!("Shift amount must be between 0 and 63, got {}", amount)
+            }
+            RuntimeError::IndexOutOfBounds { index, len, .. } => {
+                format!("Index {} out of bounds for length {}", index, len)
+            }
+            RuntimeError::InvalidIndex { index_type, .. } => {
+                format!("List index must be an integer, got {}", index_type)
+            }
+            RuntimeError::NotIndexable { receiver_type, .. } => {
+                format!("Can't index into {}", receiver_type)
+            }
+            RuntimeError::UnhashableKey { key_type, .. } => format!(
+                "Map key must be a string, number, boolean or nil, got {}",
+                key_type
+            ),
+            RuntimeError::MissingKey {
+                key: MapKey::String(key),
+                ..
+            } => format!("Key {:?} not found in map", key),
+            RuntimeError::MissingKey { key, .. } => {
+                format!("Key {} not found in map", key.to_value())
+            }
+            RuntimeError::NotIterable { type_name, .. } => {
+                format!("Can't iterate over {}", type_name)
+            }
+            RuntimeError::InvalidArgument { message, .. }
+            | RuntimeError::ImportFailed { message, .. }
+            | RuntimeError::Io { message, .. }
+            | RuntimeError::NotSerializable {
+                reason: message, ..
+            } => message.clone(),
+            RuntimeError::InvalidJson {
+                line,
+                column,
+                message,
+                ..
+            } => format!(
+                "Invalid JSON at line {}, column {}: {}",
+                line, column, message
+            ),
+            RuntimeError::PermissionDenied {
+                operation,
+                capability,
+                ..
+            } => format!(
+                "{} requires {} access (--allow-{})",
+                operation, capability, capability
+            ),
+            RuntimeError::Exit { code, .. } => format!("Exited with code {}", code),
+            RuntimeError::ImportCycle { chain, .. } => {
+                format!("Import cycle: {}", chain.join(" -> "))
+            }
+            // Error objects are reported by their message.
+            RuntimeError::Thrown { value, .. } => match value {
+                Value::Instance(instance) => {
+                    match instance.borrow().field(&Symbol::intern("message")) {
+                        Some(message) => message.to_string(),
+                        None => format!("Uncaught exception: {}", value),
+                    }
+                }
+                _ => format!("Uncaught exception: {}", value),
+            },
+        }
+    }
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at line {}", self.message(), self.line())
+    }
 }
@@ -2,61 +2,163 @@ use std::env;
 use std::fs::File;
 use std::io::prelude::*;
 use std::process;
-
-mod error;
-mod expr;
-mod interpreter;
-mod lox;
-mod parser;
-mod scanner;
-mod token;
-mod token_type;
-
-use expr::{Expr, Value};
-use parser::Parser;
-use scanner::Scanner;
-use token::Token;
-
-use crate::interpreter::Interpreter;
+use std::thread;
+
+#[cfg(feature = "bench")]
+use rslox::bench;
+use rslox::interpreter::Interpreter;
+use rslox::lox::RuntimeError;
+use rslox::parser::Parser;
+use rslox::scanner::Scanner;
+use rslox::system::Capabilities;
+
+/// Exit code for a script with a syntax error (sysexits EX_DATAERR).
+const EXIT_COMPILE_ERROR: i32 = 65;
+/// Exit code for a script that failed at runtime (sysexits EX_SOFTWARE).
+const EXIT_RUNTIME_ERROR: i32 = 70;
+/// Exit code for a script that couldn't be read (sysexits EX_IOERR).
+const EXIT_IO_ERROR: i32 = 74;
+
+/// Lox code runs on a thread with this much stack, so that it reaches the
+/// interpreter's call depth limit before the host stack runs out, even in
+/// unoptimized builds.
+const STACK_SIZE: usize = 64 * 1024 * 1024;
+
+#[cfg(not(feature = "bench"))]
+const USAGE: &str = "Usage: rslox [--path dir]... [--seed n] [--allow-read] [--allow-write] \
+                     [--allow-env] [--allow-import] [--gc-stress] [script [args...]]";
+#[cfg(feature = "bench")]
+const USAGE: &str = "Usage: rslox [--path dir]... [--seed n] [--allow-read] [--allow-write] \
+                     [--allow-env] [--allow-import] [--gc-stress] [script [args...]]
+       rslox bench [--iterations n] [benchmark...]";
+
+/// How many times `rslox bench` runs each benchmark by default.
+#[cfg(feature = "bench")]
+const BENCH_ITERATIONS: usize = 5;
 
 fn main() -> std::io::Result<()> {
-    let args: Vec<String> = env::args().collect();
-    dbg!(&args);
+    let main = thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(run_main)?;
+    match main.join() {
+        Ok(result) => result,
+        // The panic has already been reported.
+        Err(_) => process::exit(101),
+    }
+}
 
-    if args.len() > 2 {
-        println!("Usage: rslox [script]");
-        process::exit(64);
+fn run_main() -> std::io::Result<()> {
+    let mut script = None;
+    let mut search_path = Vec::new();
+    let mut seed = None;
+    let mut capabilities = Capabilities::default();
+    let mut gc_stress = false;
+    let mut args = env::args().skip(1).peekable();
+    #[cfg(feature = "bench")]
+    if args.peek().is_some_and(|arg| arg == "bench") {
+        args.next();
+        run_bench(args);
+        return Ok(());
+    }
+    // Everything after the script belongs to the script.
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--path" => match args.next() {
+                Some(dir) => search_path.push(dir),
+                None => usage(),
+            },
+            "--seed" => match args.next().and_then(|n| n.parse::<u64>().ok()) {
+                Some(n) => seed = Some(n),
+                None => usage(),
+            },
+            "--allow-read" => capabilities.read = true,
+            "--allow-write" => capabilities.write = true,
+            "--allow-env" => capabilities.env = true,
+            "--allow-import" => capabilities.import = true,
+            "--gc-stress" => gc_stress = true,
+            _ if arg.starts_with("--") => usage(),
+            _ => {
+                script = Some(arg);
+                break;
+            }
+        }
     }
 
-    init();
+    let mut interpreter = Interpreter::new();
+    for dir in search_path {
+        interpreter.add_search_path(dir);
+    }
+    if let Some(seed) = seed {
+        interpreter.seed_random(seed);
+    }
+    interpreter.set_capabilities(capabilities);
+    interpreter.heap().set_stress(gc_stress);
+    interpreter.set_args(args.collect());
 
-    if args.len() == 2 {
-        let file_path = &args[1];
-        run_file(file_path);
-    } else {
-        run_prompt()?;
+    match script {
+        Some(file_path) => run_file(&mut interpreter, &file_path),
+        None => run_prompt(&mut interpreter)?,
     }
 
     Ok(())
 }
 
-fn init() {
-    scanner::init();
+/// Runs the benchmarks named in `args`, or all of them, and prints their
+/// timings as JSON.
+#[cfg(feature = "bench")]
+fn run_bench(mut args: impl Iterator<Item = String>) {
+    let mut iterations = BENCH_ITERATIONS;
+    let mut names = Vec::new();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--iterations" => match args.next().and_then(|n| n.parse::<usize>().ok()) {
+                Some(n) if n > 0 => iterations = n,
+                _ => usage(),
+            },
+            _ if arg.starts_with("--") => usage(),
+            _ => names.push(arg),
+        }
+    }
+
+    let benchmarks = bench::benchmarks();
+    for name in &names {
+        if !benchmarks.iter().any(|benchmark| benchmark.name == name) {
+            eprintln!("Unknown benchmark {:?}", name);
+            process::exit(64);
+        }
+    }
+    let timings: Vec<_> = benchmarks
+        .iter()
+        .filter(|benchmark| names.is_empty() || names.iter().any(|name| name == benchmark.name))
+        .map(|benchmark| bench::time(benchmark, iterations))
+        .collect();
+    println!("{}", bench::to_json(&timings, iterations));
+}
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    process::exit(64);
 }
 
-fn run_file(file_path: &String) {
+fn run_file(interpreter: &mut Interpreter, file_path: &str) {
     let Ok(mut file) = File::open(file_path) else {
-        println!("File not found {}", file_path);
+        eprintln!("File not found {}", file_path);
         process::exit(64);
     };
     let mut source = String::new();
-    file.read_to_string(&mut source).unwrap();
-    run(source);
+    if let Err(error) = file.read_to_string(&mut source) {
+        eprintln!("Could not read {}: {}", file_path, error);
+        process::exit(EXIT_IO_ERROR);
+    }
+    interpreter.set_script(file_path);
+    if let Err(code) = run(interpreter, source) {
+        process::exit(code);
+    }
 }
 
-fn run_prompt() -> std::io::Result<()> {
+fn run_prompt(interpreter: &mut Interpreter) -> std::io::Result<()> {
     let stdin = std::io::stdin();
-    let mut stdout = std::io::stdout().lock();
+    let mut stdout = std::io::stdout();
     loop {
         print!("> ");
         stdout.flush()?;
@@ -67,7 +169,8 @@ fn run_prompt() -> std::io::Result<()> {
             break;
         }
 
-        run(line);
+        // Errors have already been reported; the session carries on.
+        let _ = run(interpreter, line);
     }
 
     println!();
@@ -75,13 +178,46 @@ fn run_prompt() -> std::io::Result<()> {
     Ok(())
 }
 
-fn run(source: String) {
+/// Runs `source`, reporting any errors to stderr. On failure, returns the
+/// process exit code for the kind of error.
+fn run(interpreter: &mut Interpreter, source: String) -> Result<(), i32> {
     let mut scanner = Scanner::new(source);
-    let tokens = scanner.scan_tokens().unwrap();
-    dbg!("{}", tokens);
+    let tokens = match scanner.scan_tokens() {
+        Ok(tokens) => tokens,
+        Err(error) => {
+            eprintln!("[line {}] Error: {}", error.line, error.message);
+            return Err(EXIT_COMPILE_ERROR);
+        }
+    };
+
     let mut parser = Parser::new(tokens);
-    let expr = parser.parse().unwrap();
-    dbg!("{}", &expr);
+    let program = match parser.parse() {
+        Ok(program) => program,
+        Err(errors) => {
+            for error in errors {
+                eprintln!("{}", error);
+            }
+            return Err(EXIT_COMPILE_ERROR);
+        }
+    };
 
-    dbg!("{}", Interpreter::evaluate(&expr).unwrap());
+    interpreter
+        .interpret(&program)
+        .map_err(|error| match error {
+            RuntimeError::Exit { code, .. } => process::exit(code),
+            error => {
+                report_runtime_error(&error);
+                EXIT_RUNTIME_ERROR
+            }
+        })
+}
+
+/// Prints the error's message and then its stack trace, whose first frame
+/// gives the line, in the layout the Crafting Interpreters test suite
+/// expects.
+fn report_runtime_error(error: &RuntimeError) {
+    eprintln!("{}", error.message());
+    for frame in error.trace() {
+        eprintln!("{}", frame);
+    }
 }
@@ -0,0 +1,228 @@
+//! `Value::Map` and its built-in methods.
+
+use crate::expr::{exact_integer, Value};
+use crate::function::{NativeFn, NativeFunction};
+use crate::gc::{self, Address, Trace};
+use crate::interpreter::Interpreter;
+use crate::lox::RuntimeError;
+use crate::token::Token;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::ops::RangeInclusive;
+use std::rc::Rc;
+
+/// A value usable as a map key. Numbers that hold a whole number are stored
+/// as integers, so `m[1]` and `m[1.0]` are the same entry, matching `1 == 1.0`.
+/// That includes `-0.0`, which is the same key as `0`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum MapKey {
+    String(Rc<str>),
+    Int(i64),
+    /// The bit pattern of a float with a fractional part, or of an
+    /// infinity.
+    Number(u64),
+    True,
+    False,
+    Nil,
+}
+
+impl MapKey {
+    /// Converts `value` to a key, or returns `None` if its type isn't
+    /// hashable. NaN isn't either: it isn't equal to itself, so an entry
+    /// stored under it could never be found again.
+    pub fn new(value: &Value) -> Option<MapKey> {
+        match value {
+            Value::String(s) => Some(MapKey::String(s.clone())),
+            Value::Int(n) => Some(MapKey::Int(*n)),
+            Value::Number(n) if n.is_nan() => None,
+            Value::Number(n) => Some(match exact_integer(*n) {
+                Some(n) => MapKey::Int(n),
+                None => MapKey::Number(n.to_bits()),
+            }),
+            Value::True => Some(MapKey::True),
+            Value::False => Some(MapKey::False),
+            Value::Nil => Some(MapKey::Nil),
+            _ => None,
+        }
+    }
+
+    pub fn to_value(&self) -> Value {
+        match self {
+            MapKey::String(s) => Value::String(s.clone()),
+            MapKey::Int(n) => Value::Int(*n),
+            MapKey::Number(bits) => Value::Number(f64::from_bits(*bits)),
+            MapKey::True => Value::True,
+            MapKey::False => Value::False,
+            MapKey::Nil => Value::Nil,
+        }
+    }
+}
+
+/// A hash map that iterates in insertion order.
+#[derive(Debug, Default)]
+pub struct LoxMap {
+    entries: Vec<(MapKey, Value)>,
+    /// The position of each key in `entries`.
+    positions: HashMap<MapKey, usize>,
+}
+
+impl LoxMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get(&self, key: &MapKey) -> Option<&Value> {
+        self.positions.get(key).map(|&i| &self.entries[i].1)
+    }
+
+    pub fn contains_key(&self, key: &MapKey) -> bool {
+        self.positions.contains_key(key)
+    }
+
+    /// Sets the value for `key`. A key that is already present keeps its
+    /// place in the iteration order.
+    pub fn insert(&mut self, key: MapKey, value: Value) {
+        match self.positions.get(&key) {
+            Some(&i) => self.entries[i].1 = value,
+            None => {
+                self.positions.insert(key.clone(), self.entries.len());
+                self.entries.push((key, value));
+            }
+        }
+    }
+
+    pub fn remove(&mut self, key: &MapKey) -> Option<Value> {
+        let i = self.positions.remove(key)?;
+        let (_, value) = self.entries.remove(i);
+        for (key, _) in &self.entries[i..] {
+            *self.positions.get_mut(key).unwrap() -= 1;
+        }
+        Some(value)
+    }
+
+    /// The entries in insertion order.
+    pub fn iter(&self) -> impl Iterator<Item = (&MapKey, &Value)> {
+        self.entries.iter().map(|(key, value)| (key, value))
+    }
+}
+
+impl Trace for LoxMap {
+    fn trace(&self, visit: &mut dyn FnMut(Address)) {
+        for (_, value) in &self.entries {
+            value.trace(visit);
+        }
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+        self.positions.clear();
+    }
+
+    fn owned_bytes(&self) -> usize {
+        self.entries.capacity() * std::mem::size_of::<(MapKey, Value)>()
+            + gc::table_bytes(&self.positions)
+    }
+}
+
+pub type Map = Rc<RefCell<LoxMap>>;
+
+/// Converts `value` to a key, raising `RuntimeError::UnhashableKey` at
+/// `token` if it can't be one.
+pub fn key(
+    interpreter: &Interpreter,
+    token: &Token,
+    value: &Value,
+) -> Result<MapKey, RuntimeError> {
+    MapKey::new(value).ok_or_else(|| RuntimeError::UnhashableKey {
+        token: token.clone(),
+        key_type: match value {
+            Value::Number(_) => "NaN",
+            _ => value.type_name(),
+        },
+        trace: interpreter.trace(token),
+    })
+}
+
+/// Looks up the built-in method `name`, bound to `map`.
+pub fn method(map: &Map, name: &str) -> Option<NativeFunction> {
+    let (name, arity, function): (&'static str, RangeInclusive<usize>, NativeFn) = match name {
+        "len" => ("len", 0..=0, len),
+        "keys" => ("keys", 0..=0, keys),
+        "values" => ("values", 0..=0, values),
+        "entries" => ("entries", 0..=0, entries),
+        "has" => ("has", 1..=1, has),
+        "remove" => ("remove", 1..=1, remove),
+        _ => return None,
+    };
+    Some(NativeFunction::method(
+        name,
+        arity,
+        function,
+        Value::Map(Rc::clone(map)),
+    ))
+}
+
+fn this(args: &[Value]) -> &Map {
+    match &args[0] {
+        Value::Map(map) => map,
+        _ => unreachable!("map method bound to a non-map"),
+    }
+}
+
+fn len(_: &mut Interpreter, _: &Token, args: &[Value]) -> Result<Value, RuntimeError> {
+    Ok(Value::Int(this(args).borrow().len() as i64))
+}
+
+fn keys(interpreter: &mut Interpreter, _: &Token, args: &[Value]) -> Result<Value, RuntimeError> {
+    let map = this(args).borrow();
+    let keys = map.iter().map(|(key, _)| key.to_value()).collect();
+    Ok(interpreter.heap().list(keys))
+}
+
+fn values(interpreter: &mut Interpreter, _: &Token, args: &[Value]) -> Result<Value, RuntimeError> {
+    let map = this(args).borrow();
+    let values = map.iter().map(|(_, value)| value.clone()).collect();
+    Ok(interpreter.heap().list(values))
+}
+
+/// `entries()` returns a list of `[key, value]` pairs.
+fn entries(
+    interpreter: &mut Interpreter,
+    _: &Token,
+    args: &[Value],
+) -> Result<Value, RuntimeError> {
+    let heap = interpreter.heap();
+    let map = this(args).borrow();
+    let entries = map
+        .iter()
+        .map(|(key, value)| heap.list(vec![key.to_value(), value.clone()]))
+        .collect();
+    Ok(heap.list(entries))
+}
+
+fn has(
+    interpreter: &mut Interpreter,
+    paren: &Token,
+    args: &[Value],
+) -> Result<Value, RuntimeError> {
+    let key = key(interpreter, paren, &args[1])?;
+    Ok(Value::from(this(args).borrow().contains_key(&key)))
+}
+
+/// `remove(key)` returns the removed value, or `nil` if the key was absent.
+fn remove(
+    interpreter: &mut Interpreter,
+    paren: &Token,
+    args: &[Value],
+) -> Result<Value, RuntimeError> {
+    let key = key(interpreter, paren, &args[1])?;
+    Ok(this(args).borrow_mut().remove(&key).unwrap_or(Value::Nil))
+}
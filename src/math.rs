@@ -0,0 +1,256 @@
+//! The `math` native module.
+//!
+//! Functions accept integers and floats alike and return floats, except
+//! that `floor`, `ceil` and `round` return integers when the result fits,
+//! and `abs`, `min`, `max` and `pow` keep integer arguments integral.
+
+use crate::expr::{compare_numbers, exact_integer, Value};
+use crate::function::{NativeFn, NativeFunction};
+use crate::interpreter::Interpreter;
+use crate::lox::RuntimeError;
+use crate::token::Token;
+use std::f64::consts;
+use std::ops::RangeInclusive;
+use std::rc::Rc;
+
+pub fn define(interpreter: &mut Interpreter) {
+    let functions: [(&'static str, RangeInclusive<usize>, NativeFn); 14] = [
+        ("sqrt", 1..=1, sqrt),
+        ("pow", 2..=2, pow),
+        ("floor", 1..=1, floor),
+        ("ceil", 1..=1, ceil),
+        ("round", 1..=1, round),
+        ("abs", 1..=1, abs),
+        ("min", 1..=255, min),
+        ("max", 1..=255, max),
+        ("sin", 1..=1, sin),
+        ("cos", 1..=1, cos),
+        ("tan", 1..=1, tan),
+        ("atan2", 2..=2, atan2),
+        ("log", 1..=2, log),
+        ("exp", 1..=1, exp),
+    ];
+    let mut members: Vec<_> = functions
+        .into_iter()
+        .map(|(name, arity, function)| {
+            let native = NativeFunction::new(name, arity, function);
+            (name, Value::Native(Rc::new(native)))
+        })
+        .collect();
+    members.extend([
+        ("PI", Value::Number(consts::PI)),
+        ("E", Value::Number(consts::E)),
+        ("INF", Value::Number(f64::INFINITY)),
+        ("NaN", Value::Number(f64::NAN)),
+    ]);
+    interpreter.define_native_module("math", members);
+}
+
+/// The argument `value` as a float, or an error naming `function`.
+pub(crate) fn number_arg(
+    interpreter: &Interpreter,
+    paren: &Token,
+    function: &str,
+    value: &Value,
+) -> Result<f64, RuntimeError> {
+    value.as_f64().ok_or_else(|| RuntimeError::InvalidArgument {
+        paren: paren.clone(),
+        message: format!(
+            "Argument to {}() must be a number, got {}",
+            function,
+            value.type_name()
+        ),
+        trace: interpreter.trace(paren),
+    })
+}
+
+/// `n` as an integer if it is whole and in range, and as a float otherwise.
+fn integral(n: f64) -> Value {
+    match exact_integer(n) {
+        Some(n) => Value::Int(n),
+        None => Value::Number(n),
+    }
+}
+
+fn unary(
+    interpreter: &Interpreter,
+    paren: &Token,
+    function: &str,
+    args: &[Value],
+    op: fn(f64) -> f64,
+) -> Result<Value, RuntimeError> {
+    let n = number_arg(interpreter, paren, function, &args[0])?;
+    Ok(Value::Number(op(n)))
+}
+
+fn sqrt(
+    interpreter: &mut Interpreter,
+    paren: &Token,
+    args: &[Value],
+) -> Result<Value, RuntimeError> {
+    unary(interpreter, paren, "sqrt", args, f64::sqrt)
+}
+
+fn sin(
+    interpreter: &mut Interpreter,
+    paren: &Token,
+    args: &[Value],
+) -> Result<Value, RuntimeError> {
+    unary(interpreter, paren, "sin", args, f64::sin)
+}
+
+fn cos(
+    interpreter: &mut Interpreter,
+    paren: &Token,
+    args: &[Value],
+) -> Result<Value, RuntimeError> {
+    unary(interpreter, paren, "cos", args, f64::cos)
+}
+
+fn tan(
+    interpreter: &mut Interpreter,
+    paren: &Token,
+    args: &[Value],
+) -> Result<Value, RuntimeError> {
+    unary(interpreter, paren, "tan", args, f64::tan)
+}
+
+fn exp(
+    interpreter: &mut Interpreter,
+    paren: &Token,
+    args: &[Value],
+) -> Result<Value, RuntimeError> {
+    unary(interpreter, paren, "exp", args, f64::exp)
+}
+
+fn floor(
+    interpreter: &mut Interpreter,
+    paren: &Token,
+    args: &[Value],
+) -> Result<Value, RuntimeError> {
+    let n = number_arg(interpreter, paren, "floor", &args[0])?;
+    Ok(integral(n.floor()))
+}
+
+fn ceil(
+    interpreter: &mut Interpreter,
+    paren: &Token,
+    args: &[Value],
+) -> Result<Value, RuntimeError> {
+    let n = number_arg(interpreter, paren, "ceil", &args[0])?;
+    Ok(integral(n.ceil()))
+}
+
+/// Rounds half away from zero.
+fn round(
+    interpreter: &mut Interpreter,
+    paren: &Token,
+    args: &[Value],
+) -> Result<Value, RuntimeError> {
+    let n = number_arg(interpreter, paren, "round", &args[0])?;
+    Ok(integral(n.round()))
+}
+
+fn abs(
+    interpreter: &mut Interpreter,
+    paren: &Token,
+    args: &[Value],
+) -> Result<Value, RuntimeError> {
+    if let Value::Int(n) = args[0] {
+        if let Some(abs) = n.checked_abs() {
+            return Ok(Value::Int(abs));
+        }
+    }
+    let n = number_arg(interpreter, paren, "abs", &args[0])?;
+    Ok(Value::Number(n.abs()))
+}
+
+/// `pow(base, exponent)`. Unlike `**`, an integer result that overflows
+/// becomes a float rather than an error.
+fn pow(
+    interpreter: &mut Interpreter,
+    paren: &Token,
+    args: &[Value],
+) -> Result<Value, RuntimeError> {
+    if let (Value::Int(base), Value::Int(exponent)) = (&args[0], &args[1]) {
+        if let Some(n) = u32::try_from(*exponent)
+            .ok()
+            .and_then(|exponent| base.checked_pow(exponent))
+        {
+            return Ok(Value::Int(n));
+        }
+    }
+    let base = number_arg(interpreter, paren, "pow", &args[0])?;
+    let exponent = number_arg(interpreter, paren, "pow", &args[1])?;
+    Ok(Value::Number(base.powf(exponent)))
+}
+
+/// `atan2(y, x)`.
+fn atan2(
+    interpreter: &mut Interpreter,
+    paren: &Token,
+    args: &[Value],
+) -> Result<Value, RuntimeError> {
+    let y = number_arg(interpreter, paren, "atan2", &args[0])?;
+    let x = number_arg(interpreter, paren, "atan2", &args[1])?;
+    Ok(Value::Number(y.atan2(x)))
+}
+
+/// `log(x)` is the natural logarithm; `log(x, base)` uses `base`.
+fn log(
+    interpreter: &mut Interpreter,
+    paren: &Token,
+    args: &[Value],
+) -> Result<Value, RuntimeError> {
+    let n = number_arg(interpreter, paren, "log", &args[0])?;
+    match args.get(1) {
+        Some(base) => {
+            let base = number_arg(interpreter, paren, "log", base)?;
+            Ok(Value::Number(n.log(base)))
+        }
+        None => Ok(Value::Number(n.ln())),
+    }
+}
+
+/// Returns the argument that compares first by `ordering`, unchanged, or
+/// NaN if any argument is NaN.
+fn extreme(
+    interpreter: &Interpreter,
+    paren: &Token,
+    function: &str,
+    args: &[Value],
+    ordering: std::cmp::Ordering,
+) -> Result<Value, RuntimeError> {
+    let mut nan = false;
+    for arg in args {
+        nan |= number_arg(interpreter, paren, function, arg)?.is_nan();
+    }
+    if nan {
+        return Ok(Value::Number(f64::NAN));
+    }
+    // Compared exactly, so that integers beyond 2^53 aren't confused with
+    // the floats they round to.
+    let mut best = &args[0];
+    for arg in &args[1..] {
+        if compare_numbers(arg, best) == Some(ordering) {
+            best = arg;
+        }
+    }
+    Ok(best.clone())
+}
+
+fn min(
+    interpreter: &mut Interpreter,
+    paren: &Token,
+    args: &[Value],
+) -> Result<Value, RuntimeError> {
+    extreme(interpreter, paren, "min", args, std::cmp::Ordering::Less)
+}
+
+fn max(
+    interpreter: &mut Interpreter,
+    paren: &Token,
+    args: &[Value],
+) -> Result<Value, RuntimeError> {
+    extreme(interpreter, paren, "max", args, std::cmp::Ordering::Greater)
+}
@@ -0,0 +1,230 @@
+//! Modules: Lox files loaded by `import`.
+//!
+//! `import "path.lox" as m;` binds the module itself, whose top-level
+//! variables are then read as `m.name`. `import { a, b } from "path.lox";`
+//! copies the named variables instead. Paths are resolved against the
+//! directory of the importing file first and then against each directory
+//! of the search path. Every module runs once, in its own global
+//! environment, and later imports of the same file share the result.
+//! Importing needs its own capability (`--allow-import`), separate from
+//! reading files, since it only ever runs modules rather than exposing their
+//! text.
+
+use crate::environment::Environment;
+use std::cell::RefCell;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+pub struct Module {
+    /// The path the module was first imported by.
+    pub name: String,
+    pub globals: Rc<RefCell<Environment>>,
+}
+
+impl fmt::Debug for Module {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "<module {}>", self.name)
+    }
+}
+
+/// Finds the file `path` refers to when imported from a file in `base`,
+/// returning its canonical path.
+pub fn resolve(path: &str, base: &Path, search_path: &[PathBuf]) -> Option<PathBuf> {
+    std::iter::once(base)
+        .chain(search_path.iter().map(PathBuf::as_path))
+        .map(|dir| dir.join(path))
+        .find(|candidate| candidate.is_file())
+        .and_then(|file| file.canonicalize().ok())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::lox::RuntimeError;
+    use crate::system::Capabilities;
+    use crate::test_util;
+    use std::fs;
+
+    /// Writes `files` to a fresh directory named after the test and runs
+    /// the first one as the main script.
+    fn run(test: &str, files: &[(&str, &str)]) -> (String, Result<(), RuntimeError>) {
+        run_with(test, files, Capabilities::all())
+    }
+
+    fn run_with(
+        test: &str,
+        files: &[(&str, &str)],
+        capabilities: Capabilities,
+    ) -> (String, Result<(), RuntimeError>) {
+        let dir = std::env::temp_dir().join(format!("rslox-{}-{}", test, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        for (name, source) in files {
+            let path = dir.join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, source).unwrap();
+        }
+
+        let (printed, result) = test_util::run_with(files[0].1, |interpreter| {
+            interpreter.set_capabilities(capabilities);
+            interpreter.set_script(dir.join(files[0].0));
+            interpreter.add_search_path(dir.join("lib"));
+        });
+
+        fs::remove_dir_all(&dir).unwrap();
+        (printed, result)
+    }
+
+    #[test]
+    fn test_imports() {
+        let (output, result) = run(
+            "imports",
+            &[
+                (
+                    "main.lox",
+                    "import \"util/math.lox\" as m;
+                     import { square, name } from \"util/math.lox\";
+                     import \"strings.lox\" as s;
+                     print m.square(3); print square(4); print name; print m;
+                     print s.greeting;",
+                ),
+                (
+                    "util/math.lox",
+                    "print \"loading math\";
+                     import { base } from \"base.lox\";
+                     fun square(n) { return n * n; }
+                     var name = base;",
+                ),
+                ("util/base.lox", "var base = \"math\";"),
+                ("lib/strings.lox", "var greeting = \"hi\";"),
+            ],
+        );
+        assert!(result.is_ok(), "{:?}", result);
+        assert_eq!(
+            output,
+            "loading math\n9\n16\nmath\n<module util/math.lox>\nhi\n"
+        );
+    }
+
+    #[test]
+    fn test_import_errors() {
+        let (_, result) = run(
+            "cycle",
+            &[
+                ("main.lox", "import \"a.lox\" as a;"),
+                ("a.lox", "import \"b.lox\" as b;"),
+                ("b.lox", "import \"main.lox\" as main;"),
+            ],
+        );
+        assert_eq!(
+            result.unwrap_err().message(),
+            "Import cycle: main.lox -> a.lox -> b.lox -> main.lox"
+        );
+
+        let (_, result) = run("missing", &[("main.lox", "import \"nope.lox\" as n;")]);
+        assert_eq!(
+            result.unwrap_err().message(),
+            "Can't find module \"nope.lox\""
+        );
+
+        let (_, result) = run(
+            "syntax",
+            &[
+                ("main.lox", "import \"bad.lox\" as b;"),
+                ("bad.lox", "var;"),
+            ],
+        );
+        assert_eq!(
+            result.unwrap_err().message(),
+            "Error in module \"bad.lox\": [line 1] Error: Expect variable name."
+        );
+
+        let (_, result) = run(
+            "undefined",
+            &[
+                ("main.lox", "import { a, b } from \"m.lox\";"),
+                ("m.lox", "var a = 1;"),
+            ],
+        );
+        assert!(matches!(
+            result.unwrap_err(),
+            RuntimeError::UndefinedProperty {
+                receiver_type: "module",
+                ..
+            }
+        ));
+
+        let (_, result) = run(
+            "trace",
+            &[
+                ("main.lox", "print 1;\nimport \"m.lox\" as m;"),
+                ("m.lox", "var a = 1;\nvar b = a + nil;"),
+            ],
+        );
+        let trace: Vec<_> = result
+            .unwrap_err()
+            .trace()
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(trace, ["[line 2] in module m.lox", "[line 2] in script"]);
+    }
+
+    #[test]
+    fn test_imports_need_import_access() {
+        let (_, result) = run_with(
+            "denied",
+            &[
+                ("main.lox", "import \"secret.txt\" as s;"),
+                ("secret.txt", "here be secrets"),
+            ],
+            Capabilities {
+                read: true,
+                ..Capabilities::default()
+            },
+        );
+        assert_eq!(
+            result.unwrap_err().message(),
+            "import requires import access (--allow-import)"
+        );
+
+        let (output, result) = run_with(
+            "granted",
+            &[
+                ("main.lox", "import { a } from \"m.lox\"; print a;"),
+                ("m.lox", "var a = 1;"),
+            ],
+            Capabilities {
+                import: true,
+                ..Capabilities::default()
+            },
+        );
+        assert!(result.is_ok(), "{:?}", result);
+        assert_eq!(output, "1\n");
+
+        // Modules that don't parse are reported without quoting them.
+        let (_, result) = run(
+            "quoted",
+            &[
+                ("main.lox", "import \"secret.txt\" as s;"),
+                ("secret.txt", "here be secrets"),
+            ],
+        );
+        let message = result.unwrap_err().message();
+        assert!(!message.contains("here"), "{}", message);
+        assert!(!message.contains("secrets"), "{}", message);
+    }
+
+    #[test]
+    fn test_search_path_is_used_after_the_importing_directory() {
+        let (output, result) = run(
+            "search",
+            &[
+                ("main.lox", "import { which } from \"m.lox\"; print which;"),
+                ("m.lox", "var which = \"local\";"),
+                ("lib/m.lox", "var which = \"library\";"),
+            ],
+        );
+        assert!(result.is_ok(), "{:?}", result);
+        assert_eq!(output, "local\n");
+    }
+}
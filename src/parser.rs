@@ -1,158 +1,1342 @@
+use crate::ast::{Ast, ExprId, Program, StmtId};
 use crate::expr::{Expr, Value};
-use crate::token::Token;
+use crate::interner::Symbol;
+use crate::stmt::{FunctionDecl, Imported, Stmt};
+use crate::token::{Span, Token};
 use crate::token_type::TokenType;
 use std::fmt;
+use std::mem;
+use std::rc::Rc;
+
+/// Calls and declarations are limited to this many arguments/parameters.
+const MAX_ARGUMENTS: usize = 255;
+
+/// Statements and expressions nested deeper than this are a syntax error,
+/// so that hostile input can't overflow the stack of the parser or of the
+/// code that walks the tree it builds.
+const MAX_NESTING: usize = 256;
 
 pub struct Parser<'a> {
     tokens: &'a Vec<Token>,
     current: usize,
+    functions: Vec<FunctionKind>,
+    /// The loops enclosing the statement being parsed, innermost last, with
+    /// their labels. Loops outside the current function aren't included, as
+    /// `break` and `continue` can't reach them.
+    loops: Vec<Option<Symbol>>,
+    class_depth: usize,
+    /// How deeply the syntax being parsed is nested. Operators that chain
+    /// to the left count once per operand, as each one deepens the tree.
+    depth: usize,
+    ast: Ast,
+    errors: Vec<ParseError>,
 }
 
 #[derive(Debug)]
 pub struct ParseError {
     message: String,
+    line: u32,
+    lexeme: Option<String>,
 }
 
-pub type ParseResult = Result<Expr, ParseError>;
+pub type ParseResult = Result<ExprId, ParseError>;
+type StmtResult = Result<Stmt, ParseError>;
+
+impl ParseError {
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    pub fn line(&self) -> u32 {
+        self.line
+    }
+
+    fn at(token: &Token, message: &str) -> Self {
+        let lexeme = match token.type_ {
+            TokenType::Eof => None,
+            _ => Some(token.lexeme().into_owned()),
+        };
+        Self {
+            message: message.to_string(),
+            line: token.line,
+            lexeme,
+        }
+    }
+}
 
 impl fmt::Display for ParseError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "Parse Error...")
+        match &self.lexeme {
+            Some(lexeme) => write!(
+                f,
+                "[line {}] Error at '{}': {}",
+                self.line, lexeme, self.message
+            ),
+            None => write!(f, "[line {}] Error at end: {}", self.line, self.message),
+        }
+    }
+}
+
+/// Binding power of an operator, from loosest to tightest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Precedence {
+    Coalesce,
+    Or,
+    And,
+    Equality,
+    Comparison,
+    BitOr,
+    BitXor,
+    BitAnd,
+    Shift,
+    Term,
+    Factor,
+    Unary,
+    Exponent,
+}
+
+impl Precedence {
+    fn next(self) -> Self {
+        match self {
+            Precedence::Coalesce => Precedence::Or,
+            Precedence::Or => Precedence::And,
+            Precedence::And => Precedence::Equality,
+            Precedence::Equality => Precedence::Comparison,
+            Precedence::Comparison => Precedence::BitOr,
+            Precedence::BitOr => Precedence::BitXor,
+            Precedence::BitXor => Precedence::BitAnd,
+            Precedence::BitAnd => Precedence::Shift,
+            Precedence::Shift => Precedence::Term,
+            Precedence::Term => Precedence::Factor,
+            Precedence::Factor => Precedence::Unary,
+            Precedence::Unary | Precedence::Exponent => Precedence::Exponent,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Associativity {
+    Left,
+    Right,
+}
+
+/// Which expression node an infix operator builds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InfixKind {
+    /// Evaluates both operands.
+    Binary,
+    /// Short-circuits, evaluating the right operand only if it is needed.
+    Logical,
+}
+
+/// Precedence, associativity and node kind of an infix operator.
+#[derive(Debug, Clone, Copy)]
+pub struct InfixRule {
+    pub precedence: Precedence,
+    pub associativity: Associativity,
+    pub kind: InfixKind,
+}
+
+/// The operator table. Every binary operator gets exactly one entry here;
+/// tokens without an entry end the expression.
+///
+/// Bitwise operators bind tighter than comparisons, as in Python, so that
+/// `flags & MASK == 0` tests the masked bits. `**` binds tighter than a
+/// prefix operator on its left, so `-2 ** 2` is `-(2 ** 2)`.
+pub fn infix_rule(type_: &TokenType) -> Option<InfixRule> {
+    use Associativity::*;
+    use InfixKind::*;
+
+    let (precedence, associativity, kind) = match type_ {
+        TokenType::QuestionQuestion => (Precedence::Coalesce, Right, Logical),
+        TokenType::Or => (Precedence::Or, Left, Logical),
+        TokenType::And => (Precedence::And, Left, Logical),
+        TokenType::BangEqual | TokenType::EqualEqual => (Precedence::Equality, Left, Binary),
+        TokenType::Greater | TokenType::GreaterEqual | TokenType::Less | TokenType::LessEqual => {
+            (Precedence::Comparison, Left, Binary)
+        }
+        TokenType::Pipe => (Precedence::BitOr, Left, Binary),
+        TokenType::Caret => (Precedence::BitXor, Left, Binary),
+        TokenType::Ampersand => (Precedence::BitAnd, Left, Binary),
+        TokenType::LessLess | TokenType::GreaterGreater => (Precedence::Shift, Left, Binary),
+        TokenType::Minus | TokenType::Plus => (Precedence::Term, Left, Binary),
+        TokenType::Slash | TokenType::Star | TokenType::Percent | TokenType::TildeSlash => {
+            (Precedence::Factor, Left, Binary)
+        }
+        TokenType::StarStar => (Precedence::Exponent, Right, Binary),
+        _ => return None,
+    };
+
+    Some(InfixRule {
+        precedence,
+        associativity,
+        kind,
+    })
+}
+
+/// What kind of function body the parser is inside, for validating
+/// `return` statements.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FunctionKind {
+    Function,
+    Method,
+    Initializer,
+}
+
+impl fmt::Display for FunctionKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FunctionKind::Function => write!(f, "function"),
+            FunctionKind::Method | FunctionKind::Initializer => write!(f, "method"),
+        }
     }
 }
 
 impl<'a> Parser<'a> {
     pub fn new(tokens: &'a Vec<Token>) -> Self {
-        Self { tokens, current: 0 }
+        Self {
+            tokens,
+            current: 0,
+            functions: Vec::new(),
+            loops: Vec::new(),
+            class_depth: 0,
+            depth: 0,
+            ast: Ast::new(),
+            errors: Vec::new(),
+        }
+    }
+
+    /// Parses a whole program. Parsing recovers at statement boundaries, so
+    /// every syntax error in the source is reported at once.
+    pub fn parse(&mut self) -> Result<Program, Vec<ParseError>> {
+        let mut statements = Vec::new();
+        while !self.is_at_end() {
+            if let Some(stmt) = self.declaration() {
+                statements.push(stmt);
+            }
+        }
+
+        if self.errors.is_empty() {
+            Ok(Program {
+                ast: Rc::new(mem::take(&mut self.ast)),
+                statements,
+            })
+        } else {
+            Err(std::mem::take(&mut self.errors))
+        }
     }
 
-    pub fn parse(&mut self) -> ParseResult {
-        self.expression()
+    /// Parses a single expression that must span all of the input. Its
+    /// nodes are in `ast`.
+    pub fn parse_expression(&mut self) -> ParseResult {
+        let expr = self.expression()?;
+        if !self.is_at_end() {
+            return Err(ParseError::at(self.peek(), "Expect end of expression."));
+        }
+        match self.errors.pop() {
+            Some(error) => Err(error),
+            None => Ok(expr),
+        }
     }
 
-    fn expression(&mut self) -> ParseResult {
-        self.equality()
+    /// The nodes parsed so far.
+    pub fn ast(&self) -> &Ast {
+        &self.ast
     }
 
-    fn equality(&mut self) -> ParseResult {
-        let mut expr = self.comparison()?;
+    pub fn into_ast(self) -> Ast {
+        self.ast
+    }
 
-        while let Some(next) = self.peek() {
-            if !matches!(next.type_, TokenType::BangEqual | TokenType::EqualEqual) {
-                break;
+    fn declaration(&mut self) -> Option<StmtId> {
+        let start = self.peek().span.start;
+        let result = self.nested(|parser| {
+            if parser.match_token(TokenType::Class) {
+                parser.class_declaration()
+            } else if parser.match_token(TokenType::Fun) {
+                parser.function(FunctionKind::Function).map(Stmt::Function)
+            } else if parser.match_token(TokenType::Var) {
+                parser.var_declaration()
+            } else {
+                parser.nested_statement()
+            }
+        });
+
+        match result {
+            Ok(stmt) => Some(self.add_stmt(start, stmt)),
+            Err(error) => {
+                self.errors.push(error);
+                self.synchronize();
+                None
             }
-            let token = self.advance().clone();
-            let right = self.comparison()?;
-            expr = Expr::binary(expr, token, right);
         }
-        Ok(expr)
     }
 
-    fn comparison(&mut self) -> ParseResult {
-        let mut expr = self.term()?;
-        while let Some(next) = self.peek() {
-            if !matches!(
-                next.type_,
-                TokenType::Greater
-                    | TokenType::GreaterEqual
-                    | TokenType::Less
-                    | TokenType::LessEqual
-            ) {
-                break;
+    fn class_declaration(&mut self) -> StmtResult {
+        let name = self.consume_identifier("Expect class name.")?;
+        self.consume(TokenType::LeftBrace, "Expect '{' before class body.")?;
+
+        self.class_depth += 1;
+        let methods = self.class_body();
+        self.class_depth -= 1;
+
+        Ok(Stmt::Class(name, methods?))
+    }
+
+    fn class_body(&mut self) -> Result<Vec<Rc<FunctionDecl>>, ParseError> {
+        let mut methods = Vec::new();
+        while !self.check(&TokenType::RightBrace) && !self.is_at_end() {
+            let kind = if self.peek().lexeme() == "init" {
+                FunctionKind::Initializer
+            } else {
+                FunctionKind::Method
+            };
+            methods.push(self.function(kind)?);
+        }
+        self.consume(TokenType::RightBrace, "Expect '}' after class body.")?;
+        Ok(methods)
+    }
+
+    fn function(&mut self, kind: FunctionKind) -> Result<Rc<FunctionDecl>, ParseError> {
+        let name = self.consume_identifier(&format!("Expect {} name.", kind))?;
+        self.consume(
+            TokenType::LeftParen,
+            &format!("Expect '(' after {} name.", kind),
+        )?;
+
+        let mut params = Vec::new();
+        if !self.check(&TokenType::RightParen) {
+            loop {
+                if params.len() >= MAX_ARGUMENTS {
+                    self.error_at_current("Can't have more than 255 parameters.");
+                }
+                params.push(self.consume_identifier("Expect parameter name.")?);
+                if !self.match_token(TokenType::Comma) {
+                    break;
+                }
             }
-            let token = self.advance().clone();
-            let right = self.comparison()?;
-            expr = Expr::binary(expr, token, right);
         }
-        Ok(expr)
+        self.consume(TokenType::RightParen, "Expect ')' after parameters.")?;
+        self.consume(
+            TokenType::LeftBrace,
+            &format!("Expect '{{' before {} body.", kind),
+        )?;
+
+        self.functions.push(kind);
+        let enclosing_loops = mem::take(&mut self.loops);
+        let body = self.block();
+        self.loops = enclosing_loops;
+        self.functions.pop();
+
+        Ok(Rc::new(FunctionDecl {
+            name,
+            params,
+            body: body?,
+        }))
     }
 
-    fn term(&mut self) -> ParseResult {
-        let mut expr = self.factor()?;
-        while let Some(next) = self.peek() {
-            if !matches!(next.type_, TokenType::Plus | TokenType::Minus) {
-                break;
+    fn var_declaration(&mut self) -> StmtResult {
+        let name = self.consume_identifier("Expect variable name.")?;
+        let initializer = if self.match_token(TokenType::Equal) {
+            Some(self.expression()?)
+        } else {
+            None
+        };
+        self.consume(
+            TokenType::Semicolon,
+            "Expect ';' after variable declaration.",
+        )?;
+        Ok(Stmt::Var(name, initializer))
+    }
+
+    fn statement(&mut self) -> Result<StmtId, ParseError> {
+        let start = self.peek().span.start;
+        let stmt = self.nested(Self::nested_statement)?;
+        Ok(self.add_stmt(start, stmt))
+    }
+
+    fn nested_statement(&mut self) -> StmtResult {
+        if self.check_identifier() && self.check_next(&TokenType::Colon) {
+            let label = self.advance().clone();
+            self.advance();
+            if self.match_token(TokenType::While) {
+                return self.while_statement(Some(label));
+            }
+            if self.match_token(TokenType::For) {
+                return self.for_statement(Some(label));
+            }
+            return Err(ParseError::at(self.peek(), "Expect loop after label."));
+        }
+        if self.match_token(TokenType::If) {
+            return self.if_statement();
+        }
+        if self.match_token(TokenType::While) {
+            return self.while_statement(None);
+        }
+        if self.match_token(TokenType::For) {
+            return self.for_statement(None);
+        }
+        if self.match_token(TokenType::Break) {
+            return self.jump_statement(Stmt::Break);
+        }
+        if self.match_token(TokenType::Continue) {
+            return self.jump_statement(Stmt::Continue);
+        }
+        if self.match_token(TokenType::Print) {
+            return self.print_statement();
+        }
+        if self.match_token(TokenType::Throw) {
+            return self.throw_statement();
+        }
+        if self.match_token(TokenType::Import) {
+            return self.import_statement();
+        }
+        if self.match_token(TokenType::Try) {
+            return self.try_statement();
+        }
+        if self.match_token(TokenType::Return) {
+            return self.return_statement();
+        }
+        if self.match_token(TokenType::LeftBrace) {
+            return Ok(Stmt::Block(self.block()?));
+        }
+        self.expression_statement()
+    }
+
+    fn if_statement(&mut self) -> StmtResult {
+        self.consume(TokenType::LeftParen, "Expect '(' after 'if'.")?;
+        let condition = self.expression()?;
+        self.consume(TokenType::RightParen, "Expect ')' after if condition.")?;
+
+        let then_branch = self.statement()?;
+        let else_branch = if self.match_token(TokenType::Else) {
+            Some(self.statement()?)
+        } else {
+            None
+        };
+
+        Ok(Stmt::If(condition, then_branch, else_branch))
+    }
+
+    fn while_statement(&mut self, label: Option<Token>) -> StmtResult {
+        self.consume(TokenType::LeftParen, "Expect '(' after 'while'.")?;
+        let condition = self.expression()?;
+        self.consume(TokenType::RightParen, "Expect ')' after condition.")?;
+        let body = self.loop_body(&label)?;
+
+        Ok(Stmt::While(label, condition, body, None))
+    }
+
+    fn loop_body(&mut self, label: &Option<Token>) -> Result<StmtId, ParseError> {
+        self.loops
+            .push(label.as_ref().map(|label| label.name().clone()));
+        let body = self.statement();
+        self.loops.pop();
+        body
+    }
+
+    /// Parses the rest of a `break` or `continue`, which must be inside a
+    /// loop of the current function, or of the loop it names.
+    fn jump_statement(&mut self, jump: fn(Token, Option<Token>) -> Stmt) -> StmtResult {
+        let keyword = self.previous().clone();
+        let label = if self.check_identifier() {
+            Some(self.advance().clone())
+        } else {
+            None
+        };
+
+        match &label {
+            _ if self.loops.is_empty() => self.error_at(
+                &keyword,
+                &format!("Can't use '{}' outside of a loop.", keyword.lexeme()),
+            ),
+            Some(label) if !self.loops.contains(&Some(label.name().clone())) => self.error_at(
+                label,
+                &format!("No enclosing loop labeled '{}'.", label.lexeme()),
+            ),
+            _ => {}
+        }
+
+        self.consume(
+            TokenType::Semicolon,
+            &format!("Expect ';' after '{}'.", keyword.lexeme()),
+        )?;
+        Ok(jump(keyword, label))
+    }
+
+    /// Parses both `for (x in iterable)` and the C-style `for`, which is
+    /// desugared into a `while` loop.
+    fn for_statement(&mut self, label: Option<Token>) -> StmtResult {
+        let start = label.as_ref().unwrap_or(self.previous()).span.start;
+        self.consume(TokenType::LeftParen, "Expect '(' after 'for'.")?;
+
+        if self.check_identifier() && self.check_next(&TokenType::In) {
+            let name = self.advance().clone();
+            self.advance();
+            let iterable = self.expression()?;
+            self.consume(TokenType::RightParen, "Expect ')' after for-in iterable.")?;
+            let body = self.loop_body(&label)?;
+            return Ok(Stmt::ForIn(label, name, iterable, body));
+        }
+
+        let initializer_start = self.peek().span.start;
+        let initializer = if self.match_token(TokenType::Semicolon) {
+            None
+        } else if self.match_token(TokenType::Var) {
+            Some(self.var_declaration()?)
+        } else {
+            self.reject_map_literal()?;
+            Some(self.expression_statement()?)
+        };
+        let initializer = initializer.map(|stmt| self.add_stmt(initializer_start, stmt));
+
+        let condition = if self.check(&TokenType::Semicolon) {
+            let start = self.peek().span.start;
+            self.add_expr(start, Expr::Literal(Value::True))
+        } else {
+            self.reject_map_literal()?;
+            self.expression()?
+        };
+        self.consume(TokenType::Semicolon, "Expect ';' after loop condition.")?;
+
+        let increment = if self.check(&TokenType::RightParen) {
+            None
+        } else {
+            self.reject_map_literal()?;
+            Some(self.expression()?)
+        };
+        self.consume(TokenType::RightParen, "Expect ')' after for clauses.")?;
+
+        let body = self.loop_body(&label)?;
+        let stmt = Stmt::While(label, condition, body, increment);
+        match initializer {
+            Some(initializer) => {
+                let stmt = self.add_stmt(start, stmt);
+                Ok(Stmt::Block(vec![initializer, stmt]))
+            }
+            None => Ok(stmt),
+        }
+    }
+
+    /// A clause of a C-style `for` can't start with a map literal. In Lox
+    /// without maps, `{` there is a missing expression, and that is how the
+    /// upstream test suite expects it to be reported. `({})` still works.
+    fn reject_map_literal(&self) -> Result<(), ParseError> {
+        if self.check(&TokenType::LeftBrace) {
+            return Err(ParseError::at(self.peek(), "Expect expression."));
+        }
+        Ok(())
+    }
+
+    /// `import "path" as name;` or `import { a, b } from "path";`. `as` and
+    /// `from` are only keywords here, so they remain usable as names.
+    fn import_statement(&mut self) -> StmtResult {
+        let keyword = self.previous().clone();
+
+        let (path, imported) = if self.match_token(TokenType::LeftBrace) {
+            let mut names = Vec::new();
+            loop {
+                names.push(self.consume_identifier("Expect name to import.")?);
+                if !self.match_token(TokenType::Comma) {
+                    break;
+                }
+            }
+            self.consume(TokenType::RightBrace, "Expect '}' after imported names.")?;
+            self.consume_contextual("from", "Expect 'from' after imported names.")?;
+            (self.module_path()?, Imported::Names(names))
+        } else {
+            let path = self.module_path()?;
+            self.consume_contextual("as", "Expect 'as' after module path.")?;
+            let name = self.consume_identifier("Expect module name after 'as'.")?;
+            (path, Imported::Module(name))
+        };
+
+        self.consume(TokenType::Semicolon, "Expect ';' after import.")?;
+        Ok(Stmt::Import(keyword, path, imported))
+    }
+
+    fn module_path(&mut self) -> Result<String, ParseError> {
+        match &self.peek().type_ {
+            TokenType::String(path) => {
+                let path = path.to_string();
+                self.advance();
+                Ok(path)
+            }
+            _ => Err(ParseError::at(self.peek(), "Expect module path string.")),
+        }
+    }
+
+    fn throw_statement(&mut self) -> StmtResult {
+        let keyword = self.previous().clone();
+        let value = self.expression()?;
+        self.consume(TokenType::Semicolon, "Expect ';' after thrown value.")?;
+        Ok(Stmt::Throw(keyword, value))
+    }
+
+    fn try_statement(&mut self) -> StmtResult {
+        self.consume(TokenType::LeftBrace, "Expect '{' after 'try'.")?;
+        let body = self.block()?;
+
+        let catch = if self.match_token(TokenType::Catch) {
+            self.consume(TokenType::LeftParen, "Expect '(' after 'catch'.")?;
+            let name = self.consume_identifier("Expect error variable name.")?;
+            self.consume(TokenType::RightParen, "Expect ')' after error variable.")?;
+            self.consume(TokenType::LeftBrace, "Expect '{' before catch body.")?;
+            Some((name, self.block()?))
+        } else {
+            None
+        };
+
+        let finally = if self.match_token(TokenType::Finally) {
+            self.consume(TokenType::LeftBrace, "Expect '{' after 'finally'.")?;
+            Some(self.block()?)
+        } else {
+            None
+        };
+
+        if catch.is_none() && finally.is_none() {
+            return Err(ParseError::at(
+                self.peek(),
+                "Expect 'catch' or 'finally' after try block.",
+            ));
+        }
+        Ok(Stmt::Try(body, catch, finally))
+    }
+
+    fn print_statement(&mut self) -> StmtResult {
+        let value = self.expression()?;
+        self.consume(TokenType::Semicolon, "Expect ';' after value.")?;
+        Ok(Stmt::Print(value))
+    }
+
+    fn return_statement(&mut self) -> StmtResult {
+        let keyword = self.previous().clone();
+        let value = if self.check(&TokenType::Semicolon) {
+            None
+        } else {
+            Some(self.expression()?)
+        };
+
+        match self.functions.last() {
+            None => self.error_at(&keyword, "Can't return from top-level code."),
+            Some(FunctionKind::Initializer) if value.is_some() => {
+                self.error_at(&keyword, "Can't return a value from an initializer.")
+            }
+            Some(_) => {}
+        }
+
+        self.consume(TokenType::Semicolon, "Expect ';' after return value.")?;
+        Ok(Stmt::Return(keyword, value))
+    }
+
+    fn expression_statement(&mut self) -> StmtResult {
+        let expr = self.expression()?;
+        self.consume(TokenType::Semicolon, "Expect ';' after expression.")?;
+        Ok(Stmt::Expression(expr))
+    }
+
+    fn block(&mut self) -> Result<Vec<StmtId>, ParseError> {
+        let mut statements = Vec::new();
+        while !self.check(&TokenType::RightBrace) && !self.is_at_end() {
+            if let Some(stmt) = self.declaration() {
+                statements.push(stmt);
             }
+        }
+        self.consume(TokenType::RightBrace, "Expect '}' after block.")?;
+        Ok(statements)
+    }
+
+    fn expression(&mut self) -> ParseResult {
+        self.comma()
+    }
+
+    /// The C comma operator: evaluates each operand and yields the last.
+    /// Call arguments are parsed one level down, at `assignment`, so that
+    /// their commas separate arguments instead.
+    fn comma(&mut self) -> ParseResult {
+        let depth = self.depth;
+        let mut expr = self.assignment()?;
+
+        while self.check(&TokenType::Comma) {
+            self.deepen()?;
             let token = self.advance().clone();
-            let right = self.factor()?;
-            expr = Expr::binary(expr, token, right);
+            let right = self.assignment()?;
+            expr = self.add_infix(expr, Expr::Binary(expr, token, right));
+        }
+
+        self.depth = depth;
+        Ok(expr)
+    }
+
+    fn assignment(&mut self) -> ParseResult {
+        let expr = self.conditional()?;
+
+        if self.match_token(TokenType::Equal) {
+            let equals = self.previous().clone();
+            let value = self.assignment()?;
+
+            // The target becomes the assignment, keeping its children.
+            let assignment = match &self.ast[expr] {
+                Expr::Variable(name) => Expr::Assign(name.clone(), value),
+                Expr::Get(object, name) => Expr::Set(*object, name.clone(), value),
+                Expr::Index(object, bracket, index) => {
+                    Expr::IndexSet(*object, bracket.clone(), *index, value)
+                }
+                _ => {
+                    self.error_at(&equals, "Invalid assignment target.");
+                    return Ok(expr);
+                }
+            };
+            let span = self.span_from(self.ast.expr_span(expr).start);
+            self.ast.replace_expr(expr, assignment, span);
         }
+
         Ok(expr)
     }
 
-    fn factor(&mut self) -> ParseResult {
+    /// `cond ? then : else`, which groups to the right. As in C, the middle
+    /// operand may be any expression.
+    fn conditional(&mut self) -> ParseResult {
+        self.nested(Self::nested_conditional)
+    }
+
+    fn nested_conditional(&mut self) -> ParseResult {
+        let condition = self.parse_precedence(Precedence::Coalesce)?;
+
+        if self.match_token(TokenType::Question) {
+            let then_branch = self.expression()?;
+            self.consume(
+                TokenType::Colon,
+                "Expect ':' after then branch of conditional expression.",
+            )?;
+            let else_branch = self.conditional()?;
+            return Ok(self.add_infix(
+                condition,
+                Expr::Conditional(condition, then_branch, else_branch),
+            ));
+        }
+
+        Ok(condition)
+    }
+
+    /// Parses an expression whose operators all bind at least as tightly as
+    /// `min_precedence`.
+    fn parse_precedence(&mut self, min_precedence: Precedence) -> ParseResult {
+        let depth = self.depth;
+        self.deepen()?;
         let mut expr = self.unary()?;
-        while let Some(next) = self.peek() {
-            if !matches!(next.type_, TokenType::Slash | TokenType::Star) {
+
+        while let Some(rule) = infix_rule(&self.peek().type_) {
+            if rule.precedence < min_precedence {
                 break;
             }
+            self.deepen()?;
             let token = self.advance().clone();
-            let right = self.unary()?;
-            expr = Expr::binary(expr, token, right);
+            let right_precedence = match rule.associativity {
+                Associativity::Left => rule.precedence.next(),
+                Associativity::Right => rule.precedence,
+            };
+            let right = self.parse_precedence(right_precedence)?;
+            let node = match rule.kind {
+                InfixKind::Binary => Expr::Binary(expr, token, right),
+                InfixKind::Logical => Expr::Logical(expr, token, right),
+            };
+            expr = self.add_infix(expr, node);
         }
 
+        self.depth = depth;
         Ok(expr)
     }
 
     fn unary(&mut self) -> ParseResult {
-        let Some(next) = self.peek() else {
-            unimplemented!();
-        };
-
-        if matches!(next.type_, TokenType::Bang | TokenType::Minus) {
+        if matches!(
+            self.peek().type_,
+            TokenType::Bang | TokenType::Minus | TokenType::Tilde
+        ) {
             let token = self.advance().clone();
-            let expr = self.unary()?;
-            return Ok(Expr::unary(token, expr));
+            let start = token.span.start;
+            // 2^63 is only an `i64` once negated, so `-9223372036854775808`
+            // is folded into a single literal. With `**` after it, the minus
+            // applies to the power instead, and the literal is too large.
+            let negated_min = matches!(token.type_, TokenType::Minus)
+                && matches!(self.peek().type_, TokenType::Integer(int) if int == 1 << 63)
+                && !self.check_next(&TokenType::StarStar);
+            if negated_min {
+                self.advance();
+                return Ok(self.add_expr(start, Expr::Literal(Value::Int(i64::MIN))));
+            }
+            let expr = self.parse_precedence(Precedence::Unary)?;
+            return Ok(self.add_expr(start, Expr::Unary(token, expr)));
+        }
+
+        self.call()
+    }
+
+    fn call(&mut self) -> ParseResult {
+        let depth = self.depth;
+        let mut expr = self.primary()?;
+
+        loop {
+            if matches!(
+                self.peek().type_,
+                TokenType::LeftParen
+                    | TokenType::Dot
+                    | TokenType::QuestionDot
+                    | TokenType::LeftBracket
+            ) {
+                self.deepen()?;
+            }
+            if self.match_token(TokenType::LeftParen) {
+                expr = self.finish_call(expr)?;
+            } else if self.match_token(TokenType::Dot) {
+                let name = self.consume_identifier("Expect property name after '.'.")?;
+                expr = self.add_infix(expr, Expr::Get(expr, name));
+            } else if self.match_token(TokenType::QuestionDot) {
+                let name = self.consume_identifier("Expect property name after '?.'.")?;
+                expr = self.add_infix(expr, Expr::OptionalGet(expr, name));
+            } else if self.match_token(TokenType::LeftBracket) {
+                let index = self.expression()?;
+                let bracket = self.consume(TokenType::RightBracket, "Expect ']' after index.")?;
+                expr = self.add_infix(expr, Expr::Index(expr, bracket, index));
+            } else {
+                break;
+            }
         }
 
-        self.primary()
+        self.depth = depth;
+        Ok(expr)
+    }
+
+    fn finish_call(&mut self, callee: ExprId) -> ParseResult {
+        let mut arguments = Vec::new();
+        if !self.check(&TokenType::RightParen) {
+            loop {
+                if arguments.len() >= MAX_ARGUMENTS {
+                    self.error_at_current("Can't have more than 255 arguments.");
+                }
+                arguments.push(self.assignment()?);
+                if !self.match_token(TokenType::Comma) {
+                    break;
+                }
+            }
+        }
+        let paren = self.consume(TokenType::RightParen, "Expect ')' after arguments.")?;
+
+        Ok(self.add_infix(callee, Expr::Call(callee, paren, arguments)))
     }
 
     fn primary(&mut self) -> ParseResult {
-        let token = self.advance();
+        let token = self.advance().clone();
+        let start = token.span.start;
         let expr = match &token.type_ {
-            TokenType::Number(num) => Expr::literal_num(*num),
-            TokenType::String(string) => Expr::literal_str(string.clone()),
+            TokenType::Number(num) => Expr::Literal(Value::Number(*num)),
+            TokenType::Integer(int) => match i64::try_from(*int) {
+                Ok(int) => Expr::Literal(Value::Int(int)),
+                Err(_) => return Err(ParseError::at(&token, "Integer literal is too large.")),
+            },
+            TokenType::String(string) => Expr::Literal(Value::from(&**string)),
             TokenType::True => Expr::Literal(Value::True),
             TokenType::False => Expr::Literal(Value::False),
             TokenType::Nil => Expr::Literal(Value::Nil),
+            TokenType::Identifier(_) => Expr::Variable(token),
+            TokenType::This => {
+                if self.class_depth == 0 {
+                    self.error_at(&token, "Can't use 'this' outside of a class.");
+                }
+                Expr::This(token)
+            }
             TokenType::LeftParen => {
                 let expr = self.expression()?;
-                if !matches!(self.advance().type_, TokenType::RightParen) {
-                    return Err(ParseError { message: "Expected ')' after expression".to_string()});
+                self.consume(TokenType::RightParen, "Expect ')' after expression.")?;
+                Expr::Grouping(expr)
+            }
+            TokenType::LeftBracket => {
+                let mut elements = Vec::new();
+                if !self.check(&TokenType::RightBracket) {
+                    loop {
+                        elements.push(self.assignment()?);
+                        // A trailing comma is allowed.
+                        if !self.match_token(TokenType::Comma)
+                            || self.check(&TokenType::RightBracket)
+                        {
+                            break;
+                        }
+                    }
+                }
+                self.consume(TokenType::RightBracket, "Expect ']' after list elements.")?;
+                Expr::List(elements)
+            }
+            // Statements that start with '{' are blocks, so a '{' here, where
+            // an expression is expected, always starts a map.
+            TokenType::LeftBrace => {
+                let mut entries = Vec::new();
+                if !self.check(&TokenType::RightBrace) {
+                    loop {
+                        let key = self.assignment()?;
+                        let colon = self.consume(TokenType::Colon, "Expect ':' after map key.")?;
+                        let value = self.assignment()?;
+                        entries.push((key, colon, value));
+                        // A trailing comma is allowed.
+                        if !self.match_token(TokenType::Comma) || self.check(&TokenType::RightBrace)
+                        {
+                            break;
+                        }
+                    }
                 }
-                Expr::grouping(expr)
+                self.consume(TokenType::RightBrace, "Expect '}' after map entries.")?;
+                Expr::Map(entries)
+            }
+            _ => {
+                return Err(ParseError::at(&token, "Expect expression."));
             }
-            _ => { return Err(ParseError { message: "Expected expression.".to_string() }); }
         };
 
-        Ok(expr)
+        Ok(self.add_expr(start, expr))
     }
 
-    fn peek(&self) -> Option<&Token> {
-        self.tokens.get(self.current)
+    /// Adds an expression that starts at byte `start` and ends with the
+    /// token just consumed.
+    fn add_expr(&mut self, start: u32, expr: Expr) -> ExprId {
+        let span = self.span_from(start);
+        self.ast.add_expr(expr, span)
     }
 
-    fn is_at_end(&self) -> bool {
-        if let Some(token) = self.peek() {
-            return matches!(token.type_, TokenType::Eof);
+    /// Adds an expression whose first operand is `first`, such as a binary
+    /// operator or a call.
+    fn add_infix(&mut self, first: ExprId, expr: Expr) -> ExprId {
+        let start = self.ast.expr_span(first).start;
+        self.add_expr(start, expr)
+    }
+
+    fn add_stmt(&mut self, start: u32, stmt: Stmt) -> StmtId {
+        let span = self.span_from(start);
+        self.ast.add_stmt(stmt, span)
+    }
+
+    fn span_from(&self, start: u32) -> Span {
+        Span {
+            start,
+            end: self.previous().span.end.max(start),
         }
-        true
     }
 
-    fn advance(&mut self) -> &Token {
-        let token = &self.tokens[self.current];
-        if !self.is_at_end() {
-            self.current += 1;
+    /// Runs `parse` one level deeper, restoring the depth afterwards even if
+    /// it fails partway.
+    fn nested<T>(
+        &mut self,
+        parse: impl FnOnce(&mut Self) -> Result<T, ParseError>,
+    ) -> Result<T, ParseError> {
+        let depth = self.depth;
+        let result = self.deepen().and_then(|()| parse(self));
+        self.depth = depth;
+        result
+    }
+
+    fn deepen(&mut self) -> Result<(), ParseError> {
+        self.depth += 1;
+        if self.depth > MAX_NESTING {
+            return Err(ParseError::at(self.peek(), "Too much nesting."));
         }
-        token
+        Ok(())
     }
 
+    /// Records an error that doesn't leave the parser confused, so parsing
+    /// carries on without synchronizing.
+    fn error_at(&mut self, token: &Token, message: &str) {
+        self.errors.push(ParseError::at(token, message));
+    }
+
+    fn error_at_current(&mut self, message: &str) {
+        let error = ParseError::at(self.peek(), message);
+        self.errors.push(error);
+    }
+
+    /// Skips tokens until the start of the next statement.
     fn synchronize(&mut self) {
         self.advance();
 
         while !self.is_at_end() {
+            if matches!(self.previous().type_, TokenType::Semicolon) {
+                return;
+            }
+            if matches!(
+                self.peek().type_,
+                TokenType::Class
+                    | TokenType::Fun
+                    | TokenType::Var
+                    | TokenType::For
+                    | TokenType::If
+                    | TokenType::While
+                    | TokenType::Print
+                    | TokenType::Return
+                    | TokenType::Throw
+                    | TokenType::Try
+                    | TokenType::Import
+            ) {
+                return;
+            }
+            self.advance();
+        }
+    }
+
+    fn consume(&mut self, type_: TokenType, message: &str) -> Result<Token, ParseError> {
+        if self.check(&type_) {
+            return Ok(self.advance().clone());
+        }
+        Err(ParseError::at(self.peek(), message))
+    }
+
+    fn consume_identifier(&mut self, message: &str) -> Result<Token, ParseError> {
+        if self.check_identifier() {
+            return Ok(self.advance().clone());
+        }
+        Err(ParseError::at(self.peek(), message))
+    }
+
+    /// Consumes an identifier that acts as a keyword in this position.
+    fn consume_contextual(&mut self, keyword: &str, message: &str) -> Result<Token, ParseError> {
+        if self.check_identifier() && self.peek().lexeme() == keyword {
+            return Ok(self.advance().clone());
+        }
+        Err(ParseError::at(self.peek(), message))
+    }
+
+    fn match_token(&mut self, type_: TokenType) -> bool {
+        if self.check(&type_) {
+            self.advance();
+            return true;
+        }
+        false
+    }
+
+    /// Compares token kinds only, ignoring any literal payload.
+    fn check(&self, type_: &TokenType) -> bool {
+        mem::discriminant(&self.peek().type_) == mem::discriminant(type_)
+    }
+
+    fn check_identifier(&self) -> bool {
+        matches!(self.peek().type_, TokenType::Identifier(_))
+    }
+
+    /// Like `check`, but looks at the token after the current one.
+    fn check_next(&self, type_: &TokenType) -> bool {
+        let index = (self.current + 1).min(self.tokens.len() - 1);
+        mem::discriminant(&self.tokens[index].type_) == mem::discriminant(type_)
+    }
+
+    fn peek(&self) -> &Token {
+        let index = self.current.min(self.tokens.len() - 1);
+        &self.tokens[index]
+    }
+
+    fn previous(&self) -> &Token {
+        &self.tokens[self.current.saturating_sub(1)]
+    }
+
+    fn is_at_end(&self) -> bool {
+        matches!(self.peek().type_, TokenType::Eof)
+    }
+
+    fn advance(&mut self) -> &Token {
+        let index = self.current.min(self.tokens.len() - 1);
+        if !self.is_at_end() {
+            self.current += 1;
+        }
+        &self.tokens[index]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::scanner::Scanner;
+
+    fn parse(source: &str) -> Result<String, String> {
+        let mut scanner = Scanner::new(source.to_string());
+        let tokens = scanner.scan_tokens().map_err(|e| e.message)?;
+        let mut parser = Parser::new(tokens);
+        parser
+            .parse_expression()
+            .map(|expr| parser.ast().tree(expr).to_string())
+            .map_err(|e| e.to_string())
+    }
+
+    /// Parses `source` as a program and returns the errors reported.
+    fn program_errors(source: &str) -> Vec<String> {
+        let mut scanner = Scanner::new(source.to_string());
+        let tokens = scanner.scan_tokens().unwrap();
+        match Parser::new(tokens).parse() {
+            Ok(_) => Vec::new(),
+            Err(errors) => errors.iter().map(|e| e.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn test_break_and_continue_must_be_in_a_loop() {
+        assert!(program_errors(
+            "while (true) { if (x) break; else continue; }
+             outer: for (;;) for (x in xs) { break outer; continue outer; }"
+        )
+        .is_empty());
+        assert_eq!(
+            program_errors("break;"),
+            ["[line 1] Error at 'break': Can't use 'break' outside of a loop."]
+        );
+        assert_eq!(
+            program_errors("while (true) { fun f() { continue; } }"),
+            ["[line 1] Error at 'continue': Can't use 'continue' outside of a loop."]
+        );
+        assert_eq!(
+            program_errors("a: while (true) {} while (true) break a;"),
+            ["[line 1] Error at 'a': No enclosing loop labeled 'a'."]
+        );
+        assert_eq!(
+            program_errors("a: print 1;"),
+            ["[line 1] Error at 'print': Expect loop after label."]
+        );
+    }
+
+    #[test]
+    fn test_try_needs_a_clause() {
+        assert!(program_errors("try {} catch (e) {} finally {} try {} finally {}").is_empty());
+        assert_eq!(
+            program_errors("try {} print 1;"),
+            ["[line 1] Error at 'print': Expect 'catch' or 'finally' after try block."]
+        );
+    }
+
+    #[test]
+    fn test_deep_nesting_is_an_error() {
+        let nested = |open: &str, close: &str| {
+            format!("print {}1{};", open.repeat(1000), close.repeat(1000))
+        };
+        let sources = [
+            nested("(", ")"),
+            nested("-", ""),
+            nested("[", "]"),
+            nested("{", "}").replacen("print ", "", 1),
+            format!("print 1{};", " + 1".repeat(1000)),
+        ];
+        // Unoptimized builds need more than the default test thread stack to
+        // reach the nesting limit.
+        let errors = std::thread::Builder::new()
+            .stack_size(64 * 1024 * 1024)
+            .spawn(move || sources.map(|source| program_errors(&source)))
+            .unwrap()
+            .join()
+            .unwrap();
+        for errors in errors {
+            assert!(errors[0].ends_with("Too much nesting."), "{:?}", errors);
+        }
+    }
+
+    #[test]
+    fn test_comparison_is_left_associative() {
+        assert_eq!(parse("1 < 2 < 3"), Ok("(< (< 1 2) 3)".to_string()));
+    }
+
+    #[test]
+    fn test_minimum_integer_literal() {
+        assert_eq!(
+            parse("-9223372036854775808"),
+            Ok("-9223372036854775808".to_string())
+        );
+        assert_eq!(
+            parse("1 - -9223372036854775808"),
+            Ok("(- 1 -9223372036854775808)".to_string())
+        );
+        assert_eq!(
+            parse("-9223372036854775807"),
+            Ok("(- 9223372036854775807)".to_string())
+        );
+        assert!(parse("9223372036854775808").is_err());
+        assert!(parse("-9223372036854775808 ** 2").is_err());
+    }
+
+    #[test]
+    fn test_logical_operators() {
+        assert_eq!(
+            parse("a or b and c == d"),
+            Ok("(or a (and b (== c d)))".to_string())
+        );
+        assert_eq!(parse("a ?? b or c"), Ok("(?? a (or b c))".to_string()));
+    }
+
+    #[test]
+    fn test_exponent_binds_tighter_than_prefix_operators() {
+        assert_eq!(parse("-2 ** 2"), Ok("(- (** 2 2))".to_string()));
+        assert_eq!(parse("2 ** -1"), Ok("(** 2 (- 1))".to_string()));
+        assert_eq!(parse("2 ** 3 ** 2"), Ok("(** 2 (** 3 2))".to_string()));
+        assert_eq!(parse("~1 & 2"), Ok("(& (~ 1) 2)".to_string()));
+    }
+
+    #[test]
+    fn test_conditional_comma_and_coalesce() {
+        assert_eq!(
+            parse("a ? b : c ? d : e"),
+            Ok("(?: a b (?: c d e))".to_string())
+        );
+        assert_eq!(parse("a ? b, c : d"), Ok("(?: a (, b c) d)".to_string()));
+        assert_eq!(parse("a = b ? c : d"), Ok("(= a (?: b c d))".to_string()));
+        assert_eq!(parse("a, b = 1, c"), Ok("(, (, a (= b 1)) c)".to_string()));
+        assert_eq!(parse("a ?? b ?? c"), Ok("(?? a (?? b c))".to_string()));
+        assert_eq!(parse("a ?? b ? c : d"), Ok("(?: (?? a b) c d)".to_string()));
+        assert_eq!(
+            parse("f(a, (b, c))"),
+            Ok("(call f a (group (, b c)))".to_string())
+        );
+        assert_eq!(parse("a?.b.c()"), Ok("(call (. (?. a b) c))".to_string()));
+    }
+
+    #[test]
+    fn test_lists_and_indexing() {
+        assert_eq!(parse("[]"), Ok("(list)".to_string()));
+        assert_eq!(parse("[1, a, [b],]"), Ok("(list 1 a (list b))".to_string()));
+        assert_eq!(
+            parse("a.b[i + 1][-1]"),
+            Ok("(index (index (. a b) (+ i 1)) (- 1))".to_string())
+        );
+        assert_eq!(
+            parse("a[i] = b[j] = 0"),
+            Ok("(= (index a i) (= (index b j) 0))".to_string())
+        );
+        assert_eq!(
+            parse("-a[0] ** 2"),
+            Ok("(- (** (index a 0) 2))".to_string())
+        );
+        assert!(parse("[1, 2").is_err());
+        assert!(parse("a[1").is_err());
+    }
+
+    #[test]
+    fn test_map_literals() {
+        assert_eq!(parse("{}"), Ok("(map)".to_string()));
+        assert_eq!(
+            parse("{\"a\": 1, b: {c: d},}"),
+            Ok("(map (\"a\" 1) (b (map (c d))))".to_string())
+        );
+        assert_eq!(
+            parse("{a ? b : c: d}"),
+            Ok("(map ((?: a b c) d))".to_string())
+        );
+        assert!(parse("{a}").is_err());
+        assert!(parse("{a: 1").is_err());
+    }
+
+    #[test]
+    fn test_error_reports_token() {
+        assert_eq!(
+            parse("(1 + 2"),
+            Err("[line 1] Error at end: Expect ')' after expression.".to_string())
+        );
+        assert_eq!(
+            parse("1 2"),
+            Err("[line 1] Error at '2': Expect end of expression.".to_string())
+        );
+    }
+
+    #[test]
+    fn test_reports_every_error() {
+        let mut scanner = Scanner::new("var = 1; print 2; 3 = 4; print".to_string());
+        let tokens = scanner.scan_tokens().unwrap();
+        let errors = Parser::new(tokens).parse().unwrap_err();
+        let messages: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+        assert_eq!(
+            messages,
+            [
+                "[line 1] Error at '=': Expect variable name.",
+                "[line 1] Error at '=': Invalid assignment target.",
+                "[line 1] Error at end: Expect expression.",
+            ]
+        );
+    }
+
+    const OPERATORS: [&str; 22] = [
+        "??", "or", "and", "==", "!=", "<", "<=", ">", ">=", "|", "^", "&", "<<", ">>", "+", "-",
+        "*", "/", "%", "~/", "**", "**",
+    ];
+
+    /// Independent statement of the grammar's binding powers, one level per
+    /// row of the precedence table.
+    fn reference_precedence(op: &str) -> u8 {
+        match op {
+            "??" => 0,
+            "or" => 1,
+            "and" => 2,
+            "==" | "!=" => 3,
+            "<" | "<=" | ">" | ">=" => 4,
+            "|" => 5,
+            "^" => 6,
+            "&" => 7,
+            "<<" | ">>" => 8,
+            "+" | "-" => 9,
+            "*" | "/" | "%" | "~/" => 10,
+            "**" => 11,
+            _ => unreachable!(),
+        }
+    }
+
+    /// Fully parenthesizes `operands[0] ops[0] operands[1] ...` by splitting
+    /// at the loosest operator. Operators of equal precedence group to the
+    /// left, so the split is at the rightmost one, except for `**` and `??`,
+    /// which group to the right.
+    fn reference_print(operands: &[String], ops: &[&str]) -> String {
+        if ops.is_empty() {
+            return operands[0].clone();
+        }
+        let loosest = ops.iter().map(|op| reference_precedence(op)).min().unwrap();
+        let at_loosest = |op: &&str| reference_precedence(op) == loosest;
+        let split = if ["**", "??"].map(reference_precedence).contains(&loosest) {
+            ops.iter().position(at_loosest).unwrap()
+        } else {
+            ops.iter().rposition(at_loosest).unwrap()
+        };
+        format!(
+            "({} {} {})",
+            ops[split],
+            reference_print(&operands[..=split], &ops[..split]),
+            reference_print(&operands[split + 1..], &ops[split + 1..])
+        )
+    }
+
+    /// xorshift64, so the property test is reproducible without extra crates.
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, n: usize) -> usize {
+            (self.next() % n as u64) as usize
+        }
+    }
+
+    #[test]
+    fn test_precedence_matches_reference_printer() {
+        let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+
+        for _ in 0..2000 {
+            let len = 1 + rng.below(6);
+            let operands: Vec<String> = (0..=len).map(|_| (1 + rng.below(9)).to_string()).collect();
+            let ops: Vec<&str> = (0..len)
+                .map(|_| OPERATORS[rng.below(OPERATORS.len())])
+                .collect();
+
+            let mut source = operands[0].clone();
+            for (op, operand) in ops.iter().zip(&operands[1..]) {
+                source.push_str(&format!(" {} {}", op, operand));
+            }
 
+            assert_eq!(
+                parse(&source),
+                Ok(reference_print(&operands, &ops)),
+                "source: {}",
+                source
+            );
         }
     }
 }
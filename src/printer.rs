@@ -0,0 +1,781 @@
+//! Prints syntax trees back out as Lox source.
+//!
+//! Parentheses are only printed where the tree has a `Grouping`, so the
+//! output of a tree the parser built parses back to the same tree.
+
+use crate::ast::{Ast, ExprId, Program, StmtId};
+use crate::expr::{Expr, Value};
+use crate::stmt::{FunctionDecl, Imported, Stmt};
+use crate::token::Token;
+use crate::token_type::TokenType;
+use std::fmt::Write;
+
+/// Formats a whole program, one top-level statement per line.
+pub fn print(program: &Program) -> String {
+    let mut printer = Printer::new(&program.ast);
+    for &statement in &program.statements {
+        printer.statement(statement);
+        printer.out.push('\n');
+    }
+    printer.out
+}
+
+pub fn print_expr(ast: &Ast, expr: ExprId) -> String {
+    let mut printer = Printer::new(ast);
+    printer.expr(expr);
+    printer.out
+}
+
+struct Printer<'a> {
+    ast: &'a Ast,
+    out: String,
+    depth: usize,
+}
+
+impl<'a> Printer<'a> {
+    fn new(ast: &'a Ast) -> Self {
+        Self {
+            ast,
+            out: String::new(),
+            depth: 0,
+        }
+    }
+
+    fn indent(&mut self) {
+        for _ in 0..self.depth {
+            self.out.push_str("  ");
+        }
+    }
+
+    /// Writes `statement` starting at the current position. Statements that
+    /// span lines indent their later lines by the current depth.
+    fn statement(&mut self, statement: StmtId) {
+        let ast = self.ast;
+        match &ast[statement] {
+            Stmt::Expression(expr) => {
+                self.expr(*expr);
+                self.out.push(';');
+            }
+            Stmt::Print(expr) => {
+                self.out.push_str("print ");
+                self.expr(*expr);
+                self.out.push(';');
+            }
+            Stmt::Var(name, initializer) => {
+                write!(self.out, "var {}", name.lexeme()).unwrap();
+                if let Some(initializer) = initializer {
+                    self.out.push_str(" = ");
+                    self.expr(*initializer);
+                }
+                self.out.push(';');
+            }
+            Stmt::Block(statements) => match self.map_initialized_for(statements) {
+                Some(initializer) => self.for_loop(initializer, statements[1]),
+                None => self.block(statements),
+            },
+            Stmt::Function(declaration) => {
+                self.out.push_str("fun ");
+                self.function(declaration);
+            }
+            Stmt::Return(_, value) => {
+                self.out.push_str("return");
+                if let Some(value) = value {
+                    self.out.push(' ');
+                    self.expr(*value);
+                }
+                self.out.push(';');
+            }
+            Stmt::Class(name, methods) => {
+                write!(self.out, "class {} {{", name.lexeme()).unwrap();
+                self.depth += 1;
+                for method in methods {
+                    self.out.push('\n');
+                    self.indent();
+                    self.function(method);
+                }
+                self.depth -= 1;
+                self.close();
+            }
+            Stmt::If(condition, then_branch, else_branch) => {
+                self.out.push_str("if (");
+                self.expr(*condition);
+                self.out.push(')');
+                self.body(*then_branch);
+                if let Some(else_branch) = else_branch {
+                    if matches!(ast[*then_branch], Stmt::Block(_)) {
+                        self.out.push(' ');
+                    } else {
+                        self.out.push('\n');
+                        self.indent();
+                    }
+                    self.out.push_str("else");
+                    self.body(*else_branch);
+                }
+            }
+            Stmt::While(label, condition, body, increment) => {
+                self.label(label);
+                match increment {
+                    // A `for` loop's initializer is a separate statement
+                    // before it, so only the other clauses are left.
+                    Some(increment) => {
+                        self.out.push_str("for (; ");
+                        self.expr(*condition);
+                        self.out.push_str("; ");
+                        self.expr(*increment);
+                    }
+                    None => {
+                        self.out.push_str("while (");
+                        self.expr(*condition);
+                    }
+                }
+                self.out.push(')');
+                self.body(*body);
+            }
+            Stmt::ForIn(label, name, iterable, body) => {
+                self.label(label);
+                write!(self.out, "for ({} in ", name.lexeme()).unwrap();
+                self.expr(*iterable);
+                self.out.push(')');
+                self.body(*body);
+            }
+            Stmt::Break(_, label) => self.jump("break", label),
+            Stmt::Continue(_, label) => self.jump("continue", label),
+            Stmt::Throw(_, value) => {
+                self.out.push_str("throw ");
+                self.expr(*value);
+                self.out.push(';');
+            }
+            Stmt::Try(body, catch, finally) => {
+                self.out.push_str("try ");
+                self.block(body);
+                if let Some((name, handler)) = catch {
+                    write!(self.out, " catch ({}) ", name.lexeme()).unwrap();
+                    self.block(handler);
+                }
+                if let Some(finally) = finally {
+                    self.out.push_str(" finally ");
+                    self.block(finally);
+                }
+            }
+            Stmt::Import(_, path, Imported::Module(name)) => {
+                write!(self.out, "import \"{}\" as {};", path, name.lexeme()).unwrap();
+            }
+            Stmt::Import(_, path, Imported::Names(names)) => {
+                self.out.push_str("import { ");
+                self.tokens(names);
+                write!(self.out, " }} from \"{}\";", path).unwrap();
+            }
+        }
+    }
+
+    fn block(&mut self, statements: &[StmtId]) {
+        self.out.push('{');
+        self.depth += 1;
+        for &statement in statements {
+            self.out.push('\n');
+            self.indent();
+            self.statement(statement);
+        }
+        self.depth -= 1;
+        self.close();
+    }
+
+    /// Closes a brace opened on an earlier line, or right away if nothing
+    /// was written inside it.
+    fn close(&mut self) {
+        if !self.out.ends_with('{') {
+            self.out.push('\n');
+            self.indent();
+        }
+        self.out.push('}');
+    }
+
+    /// The body of an `if` or a loop: a block on the same line, or any
+    /// other statement indented on the next.
+    /// The initializer of a `for` loop whose initializer is an expression
+    /// starting with a map, as in `for ({}; ...)`. The parser turns it into
+    /// a block holding the initializer and a `while` loop, but written out
+    /// that way, the initializer would start with `{` and be read as a
+    /// block, so the loop is printed as a `for` loop instead.
+    fn map_initialized_for(&self, statements: &[StmtId]) -> Option<ExprId> {
+        let ast = self.ast;
+        match statements {
+            [initializer, while_loop] => match (&ast[*initializer], &ast[*while_loop]) {
+                (Stmt::Expression(initializer), Stmt::While(..))
+                    if print_expr(ast, *initializer).starts_with('{') =>
+                {
+                    Some(*initializer)
+                }
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    fn for_loop(&mut self, initializer: ExprId, while_loop: StmtId) {
+        let ast = self.ast;
+        let Stmt::While(label, condition, body, increment) = &ast[while_loop] else {
+            unreachable!("a for loop's second statement is a while loop");
+        };
+        self.label(label);
+        self.out.push_str("for (");
+        self.expr(initializer);
+        self.out.push_str("; ");
+        self.expr(*condition);
+        self.out.push(';');
+        if let Some(increment) = increment {
+            self.out.push(' ');
+            self.expr(*increment);
+        }
+        self.out.push(')');
+        self.body(*body);
+    }
+
+    fn body(&mut self, body: StmtId) {
+        let ast = self.ast;
+        let block = match &ast[body] {
+            Stmt::Block(statements) if self.map_initialized_for(statements).is_none() => {
+                Some(statements)
+            }
+            _ => None,
+        };
+        if let Some(statements) = block {
+            self.out.push(' ');
+            self.block(statements);
+        } else {
+            self.depth += 1;
+            self.out.push('\n');
+            self.indent();
+            self.statement(body);
+            self.depth -= 1;
+        }
+    }
+
+    fn function(&mut self, declaration: &FunctionDecl) {
+        write!(self.out, "{}(", declaration.name.lexeme()).unwrap();
+        self.tokens(&declaration.params);
+        self.out.push_str(") ");
+        self.block(&declaration.body);
+    }
+
+    fn label(&mut self, label: &Option<Token>) {
+        if let Some(label) = label {
+            write!(self.out, "{}: ", label.lexeme()).unwrap();
+        }
+    }
+
+    fn jump(&mut self, keyword: &str, label: &Option<Token>) {
+        self.out.push_str(keyword);
+        if let Some(label) = label {
+            write!(self.out, " {}", label.lexeme()).unwrap();
+        }
+        self.out.push(';');
+    }
+
+    fn tokens(&mut self, tokens: &[Token]) {
+        for (i, token) in tokens.iter().enumerate() {
+            if i > 0 {
+                self.out.push_str(", ");
+            }
+            self.out.push_str(&token.lexeme());
+        }
+    }
+
+    fn expr(&mut self, expr: ExprId) {
+        let ast = self.ast;
+        match &ast[expr] {
+            // The comma operator hugs its left operand, like a separator.
+            Expr::Binary(left, operator, right) if matches!(operator.type_, TokenType::Comma) => {
+                self.expr(*left);
+                self.out.push_str(", ");
+                self.expr(*right);
+            }
+            Expr::Binary(left, operator, right) | Expr::Logical(left, operator, right) => {
+                self.expr(*left);
+                write!(self.out, " {} ", operator.lexeme()).unwrap();
+                self.expr(*right);
+            }
+            Expr::Unary(operator, operand) => {
+                self.out.push_str(&operator.lexeme());
+                self.expr(*operand);
+            }
+            Expr::Grouping(expr) => {
+                self.out.push('(');
+                self.expr(*expr);
+                self.out.push(')');
+            }
+            Expr::Literal(value) => self.literal(value),
+            Expr::Variable(name) | Expr::This(name) => self.out.push_str(&name.lexeme()),
+            Expr::Assign(name, value) => {
+                write!(self.out, "{} = ", name.lexeme()).unwrap();
+                self.expr(*value);
+            }
+            Expr::Call(callee, _, arguments) => {
+                self.expr(*callee);
+                self.out.push('(');
+                self.exprs(arguments);
+                self.out.push(')');
+            }
+            Expr::Get(object, name) => {
+                self.expr(*object);
+                write!(self.out, ".{}", name.lexeme()).unwrap();
+            }
+            Expr::OptionalGet(object, name) => {
+                self.expr(*object);
+                write!(self.out, "?.{}", name.lexeme()).unwrap();
+            }
+            Expr::Set(object, name, value) => {
+                self.expr(*object);
+                write!(self.out, ".{} = ", name.lexeme()).unwrap();
+                self.expr(*value);
+            }
+            Expr::Conditional(condition, then_branch, else_branch) => {
+                self.expr(*condition);
+                self.out.push_str(" ? ");
+                self.expr(*then_branch);
+                self.out.push_str(" : ");
+                self.expr(*else_branch);
+            }
+            Expr::List(elements) => {
+                self.out.push('[');
+                self.exprs(elements);
+                self.out.push(']');
+            }
+            Expr::Map(entries) => {
+                self.out.push('{');
+                for (i, (key, _, value)) in entries.iter().enumerate() {
+                    if i > 0 {
+                        self.out.push_str(", ");
+                    }
+                    self.expr(*key);
+                    self.out.push_str(": ");
+                    self.expr(*value);
+                }
+                self.out.push('}');
+            }
+            Expr::Index(object, _, index) => {
+                self.expr(*object);
+                self.out.push('[');
+                self.expr(*index);
+                self.out.push(']');
+            }
+            Expr::IndexSet(object, _, index, value) => {
+                self.expr(*object);
+                self.out.push('[');
+                self.expr(*index);
+                self.out.push_str("] = ");
+                self.expr(*value);
+            }
+        }
+    }
+
+    fn exprs(&mut self, exprs: &[ExprId]) {
+        for (i, &expr) in exprs.iter().enumerate() {
+            if i > 0 {
+                self.out.push_str(", ");
+            }
+            self.expr(expr);
+        }
+    }
+
+    fn literal(&mut self, value: &Value) {
+        match value {
+            // Float literals need a fractional part to stay floats.
+            Value::Number(n) if n.fract() == 0.0 => write!(self.out, "{}.0", n).unwrap(),
+            Value::String(s) => write!(self.out, "\"{}\"", s).unwrap(),
+            value => write!(self.out, "{}", value).unwrap(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parser::Parser;
+    use crate::random::Rng;
+    use crate::scanner::Scanner;
+    use std::fs;
+    use std::path::Path;
+
+    fn parse(source: &str) -> Option<Program> {
+        let mut scanner = Scanner::new(source.to_string());
+        let tokens = scanner.scan_tokens().ok()?;
+        Parser::new(tokens).parse().ok()
+    }
+
+    /// Printing the tree of `source` gives a program with the same tree,
+    /// which prints the same way again.
+    fn assert_round_trips(source: &str) {
+        let Some(program) = parse(source) else {
+            return;
+        };
+        let printed = print(&program);
+        let reparsed = parse(&printed)
+            .unwrap_or_else(|| panic!("printed program doesn't parse:\n{}", printed));
+        assert_eq!(program.to_string(), reparsed.to_string(), "\n{}", printed);
+        assert_eq!(printed, print(&reparsed));
+    }
+
+    #[test]
+    fn test_print_program() {
+        let program = parse(
+            "class A { init(x) { this.x = x; } get() { return this.x; } }
+             outer: for (var i = 0; i < 3; i = i + 1) if (i == 1) continue outer; else print -i;
+             try { throw {1: [2.0, \"s\"]}; } catch (e) { print e?.x ?? (1, 2); }",
+        )
+        .unwrap();
+        assert_eq!(
+            print(&program),
+            "class A {
+  init(x) {
+    this.x = x;
+  }
+  get() {
+    return this.x;
+  }
+}
+{
+  var i = 0;
+  outer: for (; i < 3; i = i + 1)
+    if (i == 1)
+      continue outer;
+    else
+      print -i;
+}
+try {
+  throw {1: [2.0, \"s\"]};
+} catch (e) {
+  print e?.x ?? (1, 2);
+}
+"
+        );
+    }
+
+    #[test]
+    fn test_round_trip_test_suite() {
+        let mut dirs = vec![Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/lox")];
+        while let Some(dir) = dirs.pop() {
+            for entry in fs::read_dir(dir).unwrap() {
+                let path = entry.unwrap().path();
+                if path.is_dir() {
+                    dirs.push(path);
+                } else if path.extension().is_some_and(|e| e == "lox") {
+                    assert_round_trips(&fs::read_to_string(path).unwrap());
+                }
+            }
+        }
+    }
+
+    /// Writes random programs that use every kind of statement and
+    /// expression.
+    struct Generator {
+        rng: Rng,
+        source: String,
+        loops: usize,
+        /// Whether the innermost function is an initializer, if there is
+        /// one.
+        initializer: Option<bool>,
+        classes: usize,
+    }
+
+    impl Generator {
+        fn pick<'a>(&mut self, choices: &[&'a str]) -> &'a str {
+            choices[self.rng.below(choices.len() as u64) as usize]
+        }
+
+        fn name(&mut self) -> &'static str {
+            self.pick(&["a", "b", "c", "f", "g", "x", "as", "from"])
+        }
+
+        fn expr(&mut self, depth: usize) {
+            let kind = if depth == 0 { 0 } else { self.rng.below(14) };
+            match kind {
+                0 => match self.rng.below(7) {
+                    0 => write!(self.source, "{}", self.rng.below(1000)).unwrap(),
+                    1 => write!(self.source, "{:?}", self.rng.next_f64() * 100.0).unwrap(),
+                    2 => self.source.push_str("\"str\""),
+                    3 => {
+                        let literal = self.pick(&["true", "false", "nil"]);
+                        self.source.push_str(literal);
+                    }
+                    _ => {
+                        let name = self.name();
+                        self.source.push_str(name);
+                    }
+                },
+                1..=3 => {
+                    self.expr(depth - 1);
+                    let operator = self.pick(&[
+                        "??", "or", "and", "==", "!=", "<", "<=", ">", ">=", "|", "^", "&", "<<",
+                        ">>", "+", "-", "*", "/", "%", "~/", "**",
+                    ]);
+                    write!(self.source, " {} ", operator).unwrap();
+                    self.expr(depth - 1);
+                }
+                4 => {
+                    let operator = self.pick(&["!", "-", "~"]);
+                    self.source.push_str(operator);
+                    self.expr(depth - 1);
+                }
+                5 => {
+                    self.source.push('(');
+                    self.expr(depth - 1);
+                    if self.rng.below(4) == 0 {
+                        self.source.push_str(", ");
+                        self.expr(depth - 1);
+                    }
+                    self.source.push(')');
+                }
+                6 => {
+                    self.expr(depth - 1);
+                    self.source.push_str(" ? ");
+                    self.expr(depth - 1);
+                    self.source.push_str(" : ");
+                    self.expr(depth - 1);
+                }
+                // Assignments are grouped, as most places an expression can
+                // go aren't assignment targets.
+                7 => {
+                    let name = self.name();
+                    write!(self.source, "({} = ", name).unwrap();
+                    self.expr(depth - 1);
+                    self.source.push(')');
+                }
+                8 => {
+                    let name = self.name();
+                    self.source.push_str(name);
+                    self.source.push('(');
+                    for i in 0..self.rng.below(3) {
+                        if i > 0 {
+                            self.source.push_str(", ");
+                        }
+                        self.expr(depth - 1);
+                    }
+                    self.source.push(')');
+                }
+                9 => {
+                    let name = self.name();
+                    let access = self.pick(&[".", "?."]);
+                    let property = self.name();
+                    if access == "." && self.rng.below(2) == 0 {
+                        write!(self.source, "({}.{} = ", name, property).unwrap();
+                        self.expr(depth - 1);
+                        self.source.push(')');
+                    } else {
+                        write!(self.source, "{}{}{}", name, access, property).unwrap();
+                    }
+                }
+                10 => {
+                    let name = self.name();
+                    let set = self.rng.below(2) == 0;
+                    if set {
+                        self.source.push('(');
+                    }
+                    write!(self.source, "{}[", name).unwrap();
+                    self.expr(depth - 1);
+                    self.source.push(']');
+                    if set {
+                        self.source.push_str(" = ");
+                        self.expr(depth - 1);
+                        self.source.push(')');
+                    }
+                }
+                11 => {
+                    self.source.push('[');
+                    for _ in 0..self.rng.below(3) {
+                        self.expr(depth - 1);
+                        self.source.push_str(", ");
+                    }
+                    self.source.push(']');
+                }
+                12 => {
+                    self.source.push('{');
+                    for i in 0..self.rng.below(3) {
+                        if i > 0 {
+                            self.source.push_str(", ");
+                        }
+                        self.expr(depth - 1);
+                        self.source.push_str(": ");
+                        self.expr(depth - 1);
+                    }
+                    self.source.push('}');
+                }
+                _ if self.classes > 0 => self.source.push_str("this"),
+                _ => self.source.push_str("nil"),
+            }
+        }
+
+        fn declaration(&mut self, depth: usize) {
+            let kind = if depth == 0 { 0 } else { self.rng.below(6) };
+            match kind {
+                0 => {
+                    let name = self.name();
+                    write!(self.source, "var {} = ", name).unwrap();
+                    self.expr(3);
+                    self.source.push(';');
+                }
+                1 => {
+                    let name = self.name();
+                    write!(self.source, "fun {}(a, b) ", name).unwrap();
+                    self.function_body(depth - 1, false);
+                }
+                2 => {
+                    self.classes += 1;
+                    self.source.push_str("class C { init(x) ");
+                    self.function_body(depth - 1, true);
+                    self.source.push_str(" method() ");
+                    self.function_body(depth - 1, false);
+                    self.source.push_str(" }");
+                    self.classes -= 1;
+                }
+                _ => self.statement(depth),
+            }
+        }
+
+        fn statement(&mut self, depth: usize) {
+            let kind = if depth == 0 { 0 } else { self.rng.below(13) };
+            match kind {
+                0 => {
+                    self.source.push_str("print ");
+                    self.expr(3);
+                    self.source.push(';');
+                }
+                1 | 2 => self.block(depth - 1),
+                3 => {
+                    self.source.push_str("if (");
+                    self.expr(2);
+                    self.source.push_str(") ");
+                    self.statement(depth - 1);
+                    if self.rng.below(2) == 0 {
+                        self.source.push_str(" else ");
+                        self.statement(depth - 1);
+                    }
+                }
+                4 | 5 => {
+                    if self.rng.below(2) == 0 {
+                        self.source.push_str("outer: ");
+                    }
+                    match self.rng.below(3) {
+                        0 => {
+                            self.source.push_str("while (");
+                            self.expr(2);
+                        }
+                        1 => {
+                            let name = self.name();
+                            write!(self.source, "for ({} in ", name).unwrap();
+                            self.expr(2);
+                        }
+                        _ => {
+                            self.source.push_str("for (");
+                            match self.rng.below(3) {
+                                0 => self.source.push(';'),
+                                1 => self.source.push_str("var i = 0;"),
+                                _ => self.source.push_str("i = 0;"),
+                            }
+                            if self.rng.below(2) == 0 {
+                                self.expr(2);
+                            }
+                            self.source.push(';');
+                            if self.rng.below(2) == 0 {
+                                self.expr(2);
+                            }
+                        }
+                    }
+                    self.source.push_str(") ");
+                    self.loops += 1;
+                    self.statement(depth - 1);
+                    self.loops -= 1;
+                }
+                6 if self.loops > 0 => {
+                    let jump = self.pick(&["break;", "continue;"]);
+                    self.source.push_str(jump);
+                }
+                7 | 8 => match self.initializer {
+                    Some(false) => {
+                        self.source.push_str("return ");
+                        self.expr(2);
+                        self.source.push(';');
+                    }
+                    Some(true) => self.source.push_str("return;"),
+                    None => self.source.push_str("print nil;"),
+                },
+                10 => {
+                    self.source.push_str("try ");
+                    self.block(depth - 1);
+                    if self.rng.below(2) == 0 {
+                        self.source.push_str(" catch (e) ");
+                        self.block(depth - 1);
+                    }
+                    self.source.push_str(" finally ");
+                    self.block(depth - 1);
+                }
+                11 => {
+                    self.source.push_str("throw ");
+                    self.expr(2);
+                    self.source.push(';');
+                }
+                12 => {
+                    let import = self.pick(&[
+                        "import \"lib.lox\" as lib;",
+                        "import { a, b } from \"lib.lox\";",
+                    ]);
+                    self.source.push_str(import);
+                }
+                _ => {
+                    // An expression statement can't start with `{`, which
+                    // would open a block.
+                    let start = self.source.len();
+                    self.expr(3);
+                    if self.source[start..].starts_with('{') {
+                        self.source.insert(start, '(');
+                        self.source.push(')');
+                    }
+                    self.source.push(';');
+                }
+            }
+        }
+
+        fn block(&mut self, depth: usize) {
+            self.source.push('{');
+            for _ in 0..self.rng.below(4) {
+                self.source.push(' ');
+                self.declaration(depth);
+            }
+            self.source.push_str(" }");
+        }
+
+        /// A function body, which no enclosing loop reaches into.
+        fn function_body(&mut self, depth: usize, initializer: bool) {
+            let loops = std::mem::take(&mut self.loops);
+            let enclosing = self.initializer.replace(initializer);
+            self.block(depth);
+            self.initializer = enclosing;
+            self.loops = loops;
+        }
+    }
+
+    #[test]
+    fn test_round_trip_random_programs() {
+        let mut generator = Generator {
+            rng: Rng::new(44),
+            source: String::new(),
+            loops: 0,
+            initializer: None,
+            classes: 0,
+        };
+        let mut parsed = 0;
+        for _ in 0..500 {
+            generator.source.clear();
+            for _ in 0..5 {
+                generator.declaration(4);
+                generator.source.push('\n');
+            }
+            if parse(&generator.source).is_some() {
+                parsed += 1;
+            }
+            assert_round_trips(&generator.source);
+        }
+        // Most programs should be valid, or the test checks little.
+        assert!(parsed > 450, "only {} of 500 programs parsed", parsed);
+    }
+}
@@ -0,0 +1,229 @@
+//! The `random` native module, backed by a seedable generator so that a
+//! run can be reproduced with `--seed` or `random.seed(n)`.
+
+use crate::expr::{exact_integer, Value};
+use crate::function::{NativeFn, NativeFunction};
+use crate::interpreter::Interpreter;
+use crate::list::List;
+use crate::lox::RuntimeError;
+use crate::token::Token;
+use std::ops::RangeInclusive;
+use std::rc::Rc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A xorshift64* generator. It is fast and small, not cryptographically
+/// secure.
+#[derive(Debug, Clone)]
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        // Scramble the seed with splitmix64 so that similar seeds give
+        // unrelated sequences, and so that the state is never zero.
+        let mut z = seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^= z >> 31;
+        Rng(if z == 0 { 1 } else { z })
+    }
+
+    /// A generator seeded from the system clock.
+    pub fn from_time() -> Self {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_nanos());
+        Rng::new(nanos as u64)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    /// A float in `[0, 1)`.
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// An integer in `[0, n)`, without modulo bias. `n` must not be zero.
+    pub fn below(&mut self, n: u64) -> u64 {
+        let zone = u64::MAX - u64::MAX % n;
+        loop {
+            let x = self.next_u64();
+            if x < zone {
+                return x % n;
+            }
+        }
+    }
+}
+
+pub fn define(interpreter: &mut Interpreter) {
+    let functions: [(&'static str, RangeInclusive<usize>, NativeFn); 5] = [
+        ("random", 0..=0, random),
+        ("randint", 2..=2, randint),
+        ("choice", 1..=1, choice),
+        ("shuffle", 1..=1, shuffle),
+        ("seed", 1..=1, seed),
+    ];
+    let members = functions
+        .into_iter()
+        .map(|(name, arity, function)| {
+            let native = NativeFunction::new(name, arity, function);
+            (name, Value::Native(Rc::new(native)))
+        })
+        .collect();
+    interpreter.define_native_module("random", members);
+}
+
+fn invalid_argument(interpreter: &Interpreter, paren: &Token, message: String) -> RuntimeError {
+    RuntimeError::InvalidArgument {
+        paren: paren.clone(),
+        message,
+        trace: interpreter.trace(paren),
+    }
+}
+
+fn integer_arg(
+    interpreter: &Interpreter,
+    paren: &Token,
+    function: &str,
+    value: &Value,
+) -> Result<i64, RuntimeError> {
+    let n = match value {
+        Value::Int(n) => Some(*n),
+        Value::Number(n) => exact_integer(*n),
+        _ => None,
+    };
+    n.ok_or_else(|| {
+        invalid_argument(
+            interpreter,
+            paren,
+            format!(
+                "Arguments to {}() must be integers, got {}",
+                function,
+                value.type_name()
+            ),
+        )
+    })
+}
+
+fn list_arg(
+    interpreter: &Interpreter,
+    paren: &Token,
+    function: &str,
+    value: &Value,
+) -> Result<List, RuntimeError> {
+    match value {
+        Value::List(list) => Ok(list.clone()),
+        _ => Err(invalid_argument(
+            interpreter,
+            paren,
+            format!(
+                "Argument to {}() must be a list, got {}",
+                function,
+                value.type_name()
+            ),
+        )),
+    }
+}
+
+/// `random()` returns a float in `[0, 1)`.
+fn random(interpreter: &mut Interpreter, _: &Token, _: &[Value]) -> Result<Value, RuntimeError> {
+    Ok(Value::Number(interpreter.rng().next_f64()))
+}
+
+/// `randint(a, b)` returns an integer from `a` to `b`, inclusive.
+fn randint(
+    interpreter: &mut Interpreter,
+    paren: &Token,
+    args: &[Value],
+) -> Result<Value, RuntimeError> {
+    let low = integer_arg(interpreter, paren, "randint", &args[0])?;
+    let high = integer_arg(interpreter, paren, "randint", &args[1])?;
+    if low > high {
+        return Err(invalid_argument(
+            interpreter,
+            paren,
+            format!("randint() bounds are reversed: {} > {}", low, high),
+        ));
+    }
+    // The span wraps to zero only when it covers every i64.
+    let span = (high.wrapping_sub(low) as u64).wrapping_add(1);
+    let offset = match span {
+        0 => interpreter.rng().next_u64(),
+        span => interpreter.rng().below(span),
+    };
+    Ok(Value::Int(low.wrapping_add(offset as i64)))
+}
+
+/// `choice(list)` returns a random element of a non-empty list.
+fn choice(
+    interpreter: &mut Interpreter,
+    paren: &Token,
+    args: &[Value],
+) -> Result<Value, RuntimeError> {
+    let list = list_arg(interpreter, paren, "choice", &args[0])?;
+    let len = list.borrow().len();
+    if len == 0 {
+        return Err(invalid_argument(
+            interpreter,
+            paren,
+            "Can't choose from an empty list".to_string(),
+        ));
+    }
+    let index = interpreter.rng().below(len as u64) as usize;
+    let element = list.borrow()[index].clone();
+    Ok(element)
+}
+
+/// `shuffle(list)` shuffles a list in place.
+fn shuffle(
+    interpreter: &mut Interpreter,
+    paren: &Token,
+    args: &[Value],
+) -> Result<Value, RuntimeError> {
+    let list = list_arg(interpreter, paren, "shuffle", &args[0])?;
+    let mut elements = list.borrow_mut();
+    for i in (1..elements.len()).rev() {
+        let j = interpreter.rng().below(i as u64 + 1) as usize;
+        elements.swap(i, j);
+    }
+    Ok(Value::Nil)
+}
+
+/// `seed(n)` restarts the generator from `n`.
+fn seed(
+    interpreter: &mut Interpreter,
+    paren: &Token,
+    args: &[Value],
+) -> Result<Value, RuntimeError> {
+    let seed = integer_arg(interpreter, paren, "seed", &args[0])?;
+    interpreter.seed_random(seed as u64);
+    Ok(Value::Nil)
+}
+
+#[cfg(test)]
+mod test {
+    use super::Rng;
+
+    #[test]
+    fn test_rng_is_deterministic_and_in_range() {
+        let mut a = Rng::new(42);
+        let mut b = Rng::new(42);
+        for _ in 0..100 {
+            assert_eq!(a.next_u64(), b.next_u64());
+        }
+        assert_ne!(Rng::new(1).next_u64(), Rng::new(2).next_u64());
+
+        let mut rng = Rng::new(0);
+        let mut seen = [false; 6];
+        for _ in 0..1000 {
+            let f = rng.next_f64();
+            assert!((0.0..1.0).contains(&f));
+            seen[rng.below(6) as usize] = true;
+        }
+        assert!(seen.iter().all(|&seen| seen));
+    }
+}
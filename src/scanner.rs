@@ -1,33 +1,38 @@
-use std::collections::HashMap;
-use std::sync::OnceLock;
-
 use crate::error::Error;
-use crate::token::Token;
+use crate::interner::Symbol;
+use crate::token::{Span, Token};
 use crate::token_type::TokenType;
 
-pub static KEYWORDS: OnceLock<HashMap<&str, TokenType>> = OnceLock::new();
-
-pub fn init() {
-    KEYWORDS.get_or_init(|| {
-        HashMap::from([
-            ("and", TokenType::And),
-            ("class", TokenType::Class),
-            ("else", TokenType::Else),
-            ("false", TokenType::False),
-            ("for", TokenType::For),
-            ("fun", TokenType::Fun),
-            ("if", TokenType::If),
-            ("nil", TokenType::Nil),
-            ("or", TokenType::Or),
-            ("print", TokenType::Print),
-            ("return", TokenType::Return),
-            ("super", TokenType::Super),
-            ("this", TokenType::This),
-            ("true", TokenType::True),
-            ("var", TokenType::Var),
-            ("while", TokenType::While),
-        ])
-    });
+/// The keyword spelled by `text`, if it is one.
+pub fn keyword(text: &str) -> Option<TokenType> {
+    let type_ = match text {
+        "and" => TokenType::And,
+        "break" => TokenType::Break,
+        "catch" => TokenType::Catch,
+        "class" => TokenType::Class,
+        "continue" => TokenType::Continue,
+        "else" => TokenType::Else,
+        "false" => TokenType::False,
+        "finally" => TokenType::Finally,
+        "for" => TokenType::For,
+        "fun" => TokenType::Fun,
+        "if" => TokenType::If,
+        "import" => TokenType::Import,
+        "in" => TokenType::In,
+        "nil" => TokenType::Nil,
+        "or" => TokenType::Or,
+        "print" => TokenType::Print,
+        "return" => TokenType::Return,
+        "super" => TokenType::Super,
+        "this" => TokenType::This,
+        "throw" => TokenType::Throw,
+        "true" => TokenType::True,
+        "try" => TokenType::Try,
+        "var" => TokenType::Var,
+        "while" => TokenType::While,
+        _ => return None,
+    };
+    Some(type_)
 }
 
 #[derive(Debug)]
@@ -35,22 +40,21 @@ pub struct Scanner {
     pub source: String,
     pub tokens: Vec<Token>,
 
+    /// Byte offsets into `source` of the token being scanned and of the
+    /// next character.
     start: usize,
     current: usize,
     line: u32,
-    source_chars: Vec<char>,
 }
 
 impl Scanner {
     pub fn new(source: String) -> Self {
-        let source_chars = String::from(&source).chars().collect();
         Self {
             source,
             tokens: Vec::<Token>::new(),
             start: 0,
             current: 0,
             line: 1,
-            source_chars: source_chars,
         }
     }
 
@@ -60,6 +64,12 @@ impl Scanner {
             self.scan_token()?;
         }
 
+        self.tokens.push(Token {
+            type_: TokenType::Eof,
+            span: self.span(self.current, self.current),
+            line: self.line,
+        });
+
         Ok(&self.tokens)
     }
 
@@ -68,8 +78,8 @@ impl Scanner {
     }
 
     fn advance(&mut self) -> char {
-        let next = self.source_chars[self.current];
-        self.current += 1;
+        let next = self.peek();
+        self.current += next.len_utf8();
         next
     }
 
@@ -78,18 +88,17 @@ impl Scanner {
     }
 
     fn peek_next(&self, lookahead: usize) -> char {
-        if self.current + lookahead >= self.source.len() {
-            '\0'
-        } else {
-            self.source_chars[self.current + lookahead]
-        }
+        self.source[self.current..]
+            .chars()
+            .nth(lookahead)
+            .unwrap_or('\0')
     }
 
     fn match_next(&mut self, expected: char) -> bool {
-        let is_a_match = !self.is_at_end() && self.source_chars[self.current] == expected;
+        let is_a_match = !self.is_at_end() && self.peek() == expected;
 
         if is_a_match {
-            self.current += 1;
+            self.current += expected.len_utf8();
         }
 
         is_a_match
@@ -103,12 +112,45 @@ impl Scanner {
             ')' => self.add_token(TokenType::RightParen),
             '{' => self.add_token(TokenType::LeftBrace),
             '}' => self.add_token(TokenType::RightBrace),
+            '[' => self.add_token(TokenType::LeftBracket),
+            ']' => self.add_token(TokenType::RightBracket),
             ',' => self.add_token(TokenType::Comma),
             '.' => self.add_token(TokenType::Dot),
             '-' => self.add_token(TokenType::Minus),
             '+' => self.add_token(TokenType::Plus),
             ';' => self.add_token(TokenType::Semicolon),
-            '*' => self.add_token(TokenType::Star),
+            '*' => {
+                let type_ = if self.match_next('*') {
+                    TokenType::StarStar
+                } else {
+                    TokenType::Star
+                };
+                self.add_token(type_)
+            }
+            '%' => self.add_token(TokenType::Percent),
+            '&' => self.add_token(TokenType::Ampersand),
+            '|' => self.add_token(TokenType::Pipe),
+            '^' => self.add_token(TokenType::Caret),
+            ':' => self.add_token(TokenType::Colon),
+            '?' => {
+                let type_ = if self.match_next('?') {
+                    TokenType::QuestionQuestion
+                } else if self.match_next('.') {
+                    TokenType::QuestionDot
+                } else {
+                    TokenType::Question
+                };
+                self.add_token(type_)
+            }
+            // `//` starts a comment, so integer division is spelled `~/`.
+            '~' => {
+                let type_ = if self.match_next('/') {
+                    TokenType::TildeSlash
+                } else {
+                    TokenType::Tilde
+                };
+                self.add_token(type_)
+            }
             '!' => {
                 let type_ = if self.match_next('=') {
                     TokenType::BangEqual
@@ -117,9 +159,19 @@ impl Scanner {
                 };
                 self.add_token(type_)
             }
+            '=' => {
+                let type_ = if self.match_next('=') {
+                    TokenType::EqualEqual
+                } else {
+                    TokenType::Equal
+                };
+                self.add_token(type_)
+            }
             '>' => {
                 let type_ = if self.match_next('=') {
                     TokenType::GreaterEqual
+                } else if self.match_next('>') {
+                    TokenType::GreaterGreater
                 } else {
                     TokenType::Greater
                 };
@@ -128,6 +180,8 @@ impl Scanner {
             '<' => {
                 let type_ = if self.match_next('=') {
                     TokenType::LessEqual
+                } else if self.match_next('<') {
+                    TokenType::LessLess
                 } else {
                     TokenType::Less
                 };
@@ -142,8 +196,8 @@ impl Scanner {
             ' ' | '\r' | '\t' => {}
             '\n' => self.line += 1,
             '"' => self.scan_string()?,
-            c if c.is_digit(10) => self.scan_number()?,
-            c if c.is_alphanumeric() => self.scan_kw_or_identifier()?,
+            c if c.is_ascii_digit() => self.scan_number()?,
+            c if c.is_alphanumeric() || c == '_' => self.scan_kw_or_identifier()?,
             _ => return Err(Error::new("Unexpected character.", self.line)),
         }
 
@@ -164,58 +218,88 @@ impl Scanner {
 
         self.advance();
 
-        let value = String::from_iter(self.source_chars[self.start + 1..self.current - 1].iter());
+        let value = Symbol::intern(&self.source[self.start + 1..self.current - 1]);
         self.add_token(TokenType::String(value));
 
         Ok(())
     }
 
+    /// Scans digits with an optional fraction and an optional exponent, as
+    /// in `1.5e-3` or `1E18`. A literal with a fraction or an exponent is a
+    /// float, even if its value is integral. Any other literal is an integer,
+    /// whose magnitude may be at most 2^63.
     fn scan_number(&mut self) -> Result<(), Error> {
-        while self.peek().is_digit(10) {
+        while self.peek().is_ascii_digit() {
             self.advance();
         }
 
-        if self.peek() == '.' && self.peek_next(1).is_digit(10) {
+        let mut is_float = false;
+        if self.peek() == '.' && self.peek_next(1).is_ascii_digit() {
+            is_float = true;
             self.advance();
 
-            while self.peek().is_digit(10) {
+            while self.peek().is_ascii_digit() {
+                self.advance();
+            }
+        }
+
+        // `1e` and `1e+` are a number followed by an identifier, just as
+        // `1.` is a number followed by a dot.
+        let sign = usize::from(matches!(self.peek_next(1), '+' | '-'));
+        if matches!(self.peek(), 'e' | 'E') && self.peek_next(1 + sign).is_ascii_digit() {
+            is_float = true;
+            for _ in 0..=sign {
+                self.advance();
+            }
+
+            while self.peek().is_ascii_digit() {
                 self.advance();
             }
         }
 
-        let value = String::from_iter(self.source_chars[self.start..self.current].iter());
-        let Ok(number) = value.parse::<f64>() else {
-            return Err(Error::new("Invalid number", self.line));
+        let value = &self.source[self.start..self.current];
+        let type_ = if is_float {
+            let Ok(number) = value.parse::<f64>() else {
+                return Err(Error::new("Invalid number", self.line));
+            };
+            TokenType::Number(number)
+        } else {
+            let int = match value.parse::<u64>() {
+                Ok(int) if int <= i64::MIN.unsigned_abs() => int,
+                _ => return Err(Error::new("Integer literal is too large.", self.line)),
+            };
+            TokenType::Integer(int)
         };
 
-        self.add_token(TokenType::Number(number));
+        self.add_token(type_);
 
         Ok(())
     }
 
     fn scan_kw_or_identifier(&mut self) -> Result<(), Error> {
-        while self.peek().is_alphanumeric() {
+        while self.peek().is_alphanumeric() || self.peek() == '_' {
             self.advance();
         }
 
-        let text = String::from_iter(self.source_chars[self.start..self.current].iter());
+        let text = &self.source[self.start..self.current];
 
-        match KEYWORDS
-            .get()
-            .expect("scanner::init must be called before using Scanner")
-            .get(&String::as_str(&text))
-        {
-            Some(token_type) => self.add_token(token_type.clone()),
-            None => self.add_token(TokenType::Identifier),
-        }
+        let type_ = keyword(text).unwrap_or_else(|| TokenType::Identifier(Symbol::intern(text)));
+        self.add_token(type_);
 
         Ok(())
     }
 
+    fn span(&self, start: usize, end: usize) -> Span {
+        Span {
+            start: start as u32,
+            end: end as u32,
+        }
+    }
+
     fn add_token(&mut self, type_: TokenType) {
         let token = Token {
             type_,
-            lexeme: String::from_iter(self.source_chars[self.start..self.current].iter()),
+            span: self.span(self.start, self.current),
             line: self.line,
         };
         self.tokens.push(token);
@@ -228,21 +312,91 @@ mod test {
     use std::matches;
 
     #[test]
-    #[should_panic]
-    #[ignore = "Not isolated with other tests so would fail sometimes"]
-    fn test_use_before_init() {
-        let _ = Scanner::new(String::from("while")).scan_kw_or_identifier();
+    fn test_keyword_lookup() {
+        assert!(matches!(keyword("while"), Some(TokenType::While)));
+        assert!(matches!(keyword("var"), Some(TokenType::Var)));
+        assert!(keyword("While").is_none());
+        assert!(keyword("whiles").is_none());
+        assert!(keyword("").is_none());
     }
 
     #[test]
-    fn test_init_keywords_lookup() {
-        init();
-        assert!(KEYWORDS.get().is_some());
+    fn test_tokens_share_interned_text() -> Result<(), Error> {
+        let mut scanner = Scanner::new(String::from("var é = \"x\"; é = x; x != 1;"));
+        let tokens = scanner.scan_tokens()?;
+        assert_eq!(tokens[1].name().as_ptr(), tokens[5].name().as_ptr());
+        let TokenType::String(literal) = &tokens[3].type_ else {
+            panic!("expected a string literal");
+        };
+        assert_eq!(literal, tokens[7].name());
+        // Names are shared with other scanners on the thread.
+        let mut other = Scanner::new(String::from("x;"));
+        assert_eq!(other.scan_tokens()?[0].name(), literal);
+
+        let source = &scanner.source;
+        for token in &scanner.tokens {
+            let span = token.span;
+            assert_eq!(
+                &source[span.start as usize..span.end as usize],
+                token.lexeme()
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_scanners_in_parallel_threads() {
+        let threads: Vec<_> = (0..8)
+            .map(|i| {
+                std::thread::spawn(move || {
+                    let mut scanner = Scanner::new(format!("var x{} = nil;", i));
+                    let tokens = scanner.scan_tokens().unwrap();
+                    let is_var = matches!(tokens[0].type_, TokenType::Var);
+                    (is_var, tokens[1].name().to_string())
+                })
+            })
+            .collect();
+        for (i, thread) in threads.into_iter().enumerate() {
+            let (is_var, name) = thread.join().unwrap();
+            assert!(is_var);
+            assert_eq!(name, format!("x{}", i));
+        }
+    }
+
+    #[test]
+    fn test_number_literals() -> Result<(), Error> {
+        let mut scanner = Scanner::new(String::from("9007199254740993 3.0 3"));
+        scanner.scan_tokens()?;
+        assert!(matches!(
+            scanner.tokens[0].type_,
+            TokenType::Integer(9007199254740993)
+        ));
+        assert!(matches!(scanner.tokens[1].type_, TokenType::Number(n) if n == 3.0));
+        assert!(matches!(scanner.tokens[2].type_, TokenType::Integer(3)));
+
+        let mut scanner = Scanner::new(String::from("1e18 2.5E-1 1e+3 1e x"));
+        scanner.scan_tokens()?;
+        let types: Vec<_> = scanner.tokens.iter().map(|token| &token.type_).collect();
+        assert!(matches!(types[0], TokenType::Number(n) if *n == 1e18));
+        assert!(matches!(types[1], TokenType::Number(n) if *n == 0.25));
+        assert!(matches!(types[2], TokenType::Number(n) if *n == 1000.0));
+        assert!(matches!(types[3], TokenType::Integer(1)));
+        assert!(matches!(types[4], TokenType::Identifier(_)));
+
+        let mut scanner = Scanner::new(String::from("9223372036854775808"));
+        scanner.scan_tokens()?;
+        assert!(matches!(
+            scanner.tokens[0].type_,
+            TokenType::Integer(9223372036854775808)
+        ));
+        let mut scanner = Scanner::new(String::from("9223372036854775809"));
+        assert!(scanner.scan_tokens().is_err());
+
+        Ok(())
     }
 
     #[test]
     fn test_keyword() -> Result<(), Error> {
-        init();
         let mut scanner = Scanner::new(String::from("while"));
         scanner.scan_kw_or_identifier()?;
         assert_eq!(scanner.tokens.len(), 1);
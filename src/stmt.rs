@@ -0,0 +1,195 @@
+use crate::ast::{Ast, ExprId, StmtId, Tree};
+use crate::token::Token;
+use std::fmt;
+use std::rc::Rc;
+
+/// A statement node. Children are nodes in the same `Ast`.
+#[derive(Debug)]
+pub enum Stmt {
+    Expression(ExprId),
+    Print(ExprId),
+    Var(Token, Option<ExprId>),
+    Block(Vec<StmtId>),
+    Function(Rc<FunctionDecl>),
+    Return(Token, Option<ExprId>),
+    Class(Token, Vec<Rc<FunctionDecl>>),
+    If(ExprId, StmtId, Option<StmtId>),
+    /// A loop's label, condition, body and, for a desugared C-style `for`,
+    /// the increment, which also runs after a `continue`.
+    While(Option<Token>, ExprId, StmtId, Option<ExprId>),
+    /// `for (name in iterable) body`, with an optional label. Each iteration
+    /// binds `name` in a new scope, so closures in the body capture that
+    /// iteration's element.
+    ForIn(Option<Token>, Token, ExprId, StmtId),
+    /// `break` or `break label`.
+    Break(Token, Option<Token>),
+    /// `continue` or `continue label`.
+    Continue(Token, Option<Token>),
+    Throw(Token, ExprId),
+    /// `try` block, `catch` clause with the name it binds, and `finally`
+    /// block. At least one of the clauses is present.
+    Try(
+        Vec<StmtId>,
+        Option<(Token, Vec<StmtId>)>,
+        Option<Vec<StmtId>>,
+    ),
+    /// `import` keyword, module path and what to bind.
+    Import(Token, String, Imported),
+}
+
+#[derive(Debug)]
+pub enum Imported {
+    /// `import "path" as name;`
+    Module(Token),
+    /// `import { a, b } from "path";`
+    Names(Vec<Token>),
+}
+
+/// A function or method declaration. Shared between the AST and every
+/// closure created from it.
+#[derive(Debug)]
+pub struct FunctionDecl {
+    pub name: Token,
+    pub params: Vec<Token>,
+    pub body: Vec<StmtId>,
+}
+
+/// Prints the tree in the same prefix form as `Expr`, one statement per
+/// line, with nested statements indented under the one that holds them.
+impl fmt::Display for Tree<'_, StmtId> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_stmt(f, self.ast, self.id, 0)
+    }
+}
+
+fn write_stmt(f: &mut fmt::Formatter, ast: &Ast, id: StmtId, depth: usize) -> fmt::Result {
+    write!(f, "{:indent$}", "", indent = depth * 2)?;
+    let tree = |id| ast.tree(id);
+    match &ast[id] {
+        Stmt::Expression(expr) => write!(f, "(expr {})", tree(*expr)),
+        Stmt::Print(expr) => write!(f, "(print {})", tree(*expr)),
+        Stmt::Var(name, Some(initializer)) => {
+            write!(f, "(var {} {})", name.lexeme(), tree(*initializer))
+        }
+        Stmt::Var(name, None) => write!(f, "(var {})", name.lexeme()),
+        Stmt::Block(statements) => {
+            write!(f, "(block")?;
+            write_body(f, ast, statements, depth)?;
+            write!(f, ")")
+        }
+        Stmt::Function(declaration) => declaration.write(f, ast, depth),
+        Stmt::Return(_, Some(value)) => write!(f, "(return {})", tree(*value)),
+        Stmt::Return(_, None) => write!(f, "(return)"),
+        Stmt::Class(name, methods) => {
+            write!(f, "(class {}", name.lexeme())?;
+            for method in methods {
+                writeln!(f)?;
+                method.write(f, ast, depth + 1)?;
+            }
+            write!(f, ")")
+        }
+        Stmt::If(condition, then_branch, else_branch) => {
+            write!(f, "(if {}", tree(*condition))?;
+            writeln!(f)?;
+            write_stmt(f, ast, *then_branch, depth + 1)?;
+            if let Some(else_branch) = else_branch {
+                writeln!(f)?;
+                write_stmt(f, ast, *else_branch, depth + 1)?;
+            }
+            write!(f, ")")
+        }
+        Stmt::While(label, condition, body, increment) => {
+            write!(f, "(while")?;
+            write_label(f, label)?;
+            write!(f, " {}", tree(*condition))?;
+            if let Some(increment) = increment {
+                write!(f, " (increment {})", tree(*increment))?;
+            }
+            writeln!(f)?;
+            write_stmt(f, ast, *body, depth + 1)?;
+            write!(f, ")")
+        }
+        Stmt::ForIn(label, name, iterable, body) => {
+            write!(f, "(for-in")?;
+            write_label(f, label)?;
+            write!(f, " {} {}", name.lexeme(), tree(*iterable))?;
+            writeln!(f)?;
+            write_stmt(f, ast, *body, depth + 1)?;
+            write!(f, ")")
+        }
+        Stmt::Break(_, label) => {
+            write!(f, "(break")?;
+            write_label(f, label)?;
+            write!(f, ")")
+        }
+        Stmt::Continue(_, label) => {
+            write!(f, "(continue")?;
+            write_label(f, label)?;
+            write!(f, ")")
+        }
+        Stmt::Throw(_, value) => write!(f, "(throw {})", tree(*value)),
+        Stmt::Try(body, catch, finally) => {
+            write!(f, "(try")?;
+            write_body(f, ast, body, depth)?;
+            if let Some((name, handler)) = catch {
+                let indent = (depth + 1) * 2;
+                write!(f, "\n{:indent$}(catch {}", "", name.lexeme())?;
+                write_body(f, ast, handler, depth + 1)?;
+                write!(f, ")")?;
+            }
+            if let Some(finally) = finally {
+                let indent = (depth + 1) * 2;
+                write!(f, "\n{:indent$}(finally", "")?;
+                write_body(f, ast, finally, depth + 1)?;
+                write!(f, ")")?;
+            }
+            write!(f, ")")
+        }
+        Stmt::Import(_, path, Imported::Module(name)) => {
+            write!(f, "(import {:?} as {})", path, name.lexeme())
+        }
+        Stmt::Import(_, path, Imported::Names(names)) => {
+            write!(f, "(import {:?}", path)?;
+            for name in names {
+                write!(f, " {}", name.lexeme())?;
+            }
+            write!(f, ")")
+        }
+    }
+}
+
+impl FunctionDecl {
+    fn write(&self, f: &mut fmt::Formatter, ast: &Ast, depth: usize) -> fmt::Result {
+        write!(f, "(fun {} (", self.name.lexeme())?;
+        for (i, param) in self.params.iter().enumerate() {
+            if i > 0 {
+                write!(f, " ")?;
+            }
+            write!(f, "{}", param.lexeme())?;
+        }
+        write!(f, ")")?;
+        write_body(f, ast, &self.body, depth)?;
+        write!(f, ")")
+    }
+}
+
+/// Writes `statements` on the lines after their parent's header.
+fn write_body(
+    f: &mut fmt::Formatter,
+    ast: &Ast,
+    statements: &[StmtId],
+    depth: usize,
+) -> fmt::Result {
+    for &statement in statements {
+        writeln!(f)?;
+        write_stmt(f, ast, statement, depth + 1)?;
+    }
+    Ok(())
+}
+
+fn write_label(f: &mut fmt::Formatter, label: &Option<Token>) -> fmt::Result {
+    match label {
+        Some(label) => write!(f, " {}:", label.lexeme()),
+        None => Ok(()),
+    }
+}
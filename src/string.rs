@@ -0,0 +1,281 @@
+//! Built-in methods of `Value::String`. Indices and lengths count Unicode
+//! scalar values, not bytes.
+
+use crate::expr::Value;
+use crate::function::{NativeFn, NativeFunction};
+use crate::interpreter::Interpreter;
+use crate::list::{integer_arg, out_of_bounds, resolve_position};
+use crate::lox::RuntimeError;
+use crate::token::Token;
+use std::ops::RangeInclusive;
+use std::rc::Rc;
+
+/// Looks up the built-in method `name`, bound to `string`.
+pub fn method(string: &Rc<str>, name: &str) -> Option<NativeFunction> {
+    let (name, arity, function): (&'static str, RangeInclusive<usize>, NativeFn) = match name {
+        "len" => ("len", 0..=0, len),
+        "upper" => ("upper", 0..=0, upper),
+        "lower" => ("lower", 0..=0, lower),
+        "trim" => ("trim", 0..=0, trim),
+        "split" => ("split", 1..=1, split),
+        "replace" => ("replace", 2..=2, replace),
+        "contains" => ("contains", 1..=1, contains),
+        "starts_with" => ("starts_with", 1..=1, starts_with),
+        "ends_with" => ("ends_with", 1..=1, ends_with),
+        "index_of" => ("index_of", 1..=1, index_of),
+        "substring" => ("substring", 1..=2, substring),
+        "repeat" => ("repeat", 1..=1, repeat),
+        "chars" => ("chars", 0..=0, chars),
+        "format" => ("format", 0..=255, format),
+        _ => return None,
+    };
+    Some(NativeFunction::method(
+        name,
+        arity,
+        function,
+        Value::String(string.clone()),
+    ))
+}
+
+fn this(args: &[Value]) -> &str {
+    match &args[0] {
+        Value::String(string) => string,
+        _ => unreachable!("string method bound to a non-string"),
+    }
+}
+
+fn string_arg<'a>(
+    interpreter: &Interpreter,
+    paren: &Token,
+    method: &str,
+    value: &'a Value,
+) -> Result<&'a str, RuntimeError> {
+    match value {
+        Value::String(string) => Ok(string),
+        _ => Err(invalid_argument(
+            interpreter,
+            paren,
+            format!(
+                "Argument to {}() must be a string, got {}",
+                method,
+                value.type_name()
+            ),
+        )),
+    }
+}
+
+fn invalid_argument(interpreter: &Interpreter, paren: &Token, message: String) -> RuntimeError {
+    RuntimeError::InvalidArgument {
+        paren: paren.clone(),
+        message,
+        trace: interpreter.trace(paren),
+    }
+}
+
+fn len(_: &mut Interpreter, _: &Token, args: &[Value]) -> Result<Value, RuntimeError> {
+    Ok(Value::Int(this(args).chars().count() as i64))
+}
+
+fn upper(_: &mut Interpreter, _: &Token, args: &[Value]) -> Result<Value, RuntimeError> {
+    Ok(Value::from(this(args).to_uppercase()))
+}
+
+fn lower(_: &mut Interpreter, _: &Token, args: &[Value]) -> Result<Value, RuntimeError> {
+    Ok(Value::from(this(args).to_lowercase()))
+}
+
+fn trim(_: &mut Interpreter, _: &Token, args: &[Value]) -> Result<Value, RuntimeError> {
+    Ok(Value::from(this(args).trim().to_string()))
+}
+
+fn split(
+    interpreter: &mut Interpreter,
+    paren: &Token,
+    args: &[Value],
+) -> Result<Value, RuntimeError> {
+    let separator = string_arg(interpreter, paren, "split", &args[1])?;
+    if separator.is_empty() {
+        return Err(invalid_argument(
+            interpreter,
+            paren,
+            "Separator passed to split() must not be empty; use chars() instead".to_string(),
+        ));
+    }
+    let parts = this(args)
+        .split(separator)
+        .map(|part| Value::from(part.to_string()))
+        .collect();
+    Ok(interpreter.heap().list(parts))
+}
+
+/// `replace(from, to)` replaces every occurrence of `from`.
+fn replace(
+    interpreter: &mut Interpreter,
+    paren: &Token,
+    args: &[Value],
+) -> Result<Value, RuntimeError> {
+    let from = string_arg(interpreter, paren, "replace", &args[1])?;
+    let to = string_arg(interpreter, paren, "replace", &args[2])?;
+    Ok(Value::from(this(args).replace(from, to)))
+}
+
+fn contains(
+    interpreter: &mut Interpreter,
+    paren: &Token,
+    args: &[Value],
+) -> Result<Value, RuntimeError> {
+    let needle = string_arg(interpreter, paren, "contains", &args[1])?;
+    Ok(Value::from(this(args).contains(needle)))
+}
+
+fn starts_with(
+    interpreter: &mut Interpreter,
+    paren: &Token,
+    args: &[Value],
+) -> Result<Value, RuntimeError> {
+    let prefix = string_arg(interpreter, paren, "starts_with", &args[1])?;
+    Ok(Value::from(this(args).starts_with(prefix)))
+}
+
+fn ends_with(
+    interpreter: &mut Interpreter,
+    paren: &Token,
+    args: &[Value],
+) -> Result<Value, RuntimeError> {
+    let suffix = string_arg(interpreter, paren, "ends_with", &args[1])?;
+    Ok(Value::from(this(args).ends_with(suffix)))
+}
+
+/// `index_of(needle)` returns the index of the first occurrence of
+/// `needle`, or -1 if there is none.
+fn index_of(
+    interpreter: &mut Interpreter,
+    paren: &Token,
+    args: &[Value],
+) -> Result<Value, RuntimeError> {
+    let string = this(args);
+    let needle = string_arg(interpreter, paren, "index_of", &args[1])?;
+    let index = match string.find(needle) {
+        Some(byte_index) => string[..byte_index].chars().count() as i64,
+        None => -1,
+    };
+    Ok(Value::Int(index))
+}
+
+/// `substring(start, end?)` works like `slice` on lists.
+fn substring(
+    interpreter: &mut Interpreter,
+    paren: &Token,
+    args: &[Value],
+) -> Result<Value, RuntimeError> {
+    let chars: Vec<char> = this(args).chars().collect();
+    let start = integer_arg(interpreter, paren, "substring", &args[1])?;
+    let end = match args.get(2) {
+        Some(end) => integer_arg(interpreter, paren, "substring", end)?,
+        None => chars.len() as i64,
+    };
+
+    let Some(from) = resolve_position(start, chars.len()) else {
+        return Err(out_of_bounds(interpreter, paren, start, chars.len()));
+    };
+    let Some(to) = resolve_position(end, chars.len()).filter(|&to| to >= from) else {
+        return Err(out_of_bounds(interpreter, paren, end, chars.len()));
+    };
+    Ok(Value::from(chars[from..to].iter().collect::<String>()))
+}
+
+fn repeat(
+    interpreter: &mut Interpreter,
+    paren: &Token,
+    args: &[Value],
+) -> Result<Value, RuntimeError> {
+    let string = this(args);
+    let count = match args[1] {
+        Value::Int(count) => usize::try_from(count).ok(),
+        _ => None,
+    };
+    let Some(count) = count else {
+        return Err(invalid_argument(
+            interpreter,
+            paren,
+            format!(
+                "Count passed to repeat() must be a non-negative integer, got {}",
+                args[1]
+            ),
+        ));
+    };
+    if string.len().checked_mul(count).is_none() {
+        return Err(invalid_argument(
+            interpreter,
+            paren,
+            "Result of repeat() is too long".to_string(),
+        ));
+    }
+    Ok(Value::from(string.repeat(count)))
+}
+
+fn chars(interpreter: &mut Interpreter, _: &Token, args: &[Value]) -> Result<Value, RuntimeError> {
+    let chars = this(args)
+        .chars()
+        .map(|c| Value::from(c.to_string()))
+        .collect();
+    Ok(interpreter.heap().list(chars))
+}
+
+/// `format(args...)` replaces each `{}` with the next argument, printed as
+/// `print` would. `{{` and `}}` stand for literal braces.
+fn format(
+    interpreter: &mut Interpreter,
+    paren: &Token,
+    args: &[Value],
+) -> Result<Value, RuntimeError> {
+    let mut arguments = args[1..].iter();
+    let mut formatted = String::new();
+    let mut chars = this(args).chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match (c, chars.peek()) {
+            ('{', Some('{')) | ('}', Some('}')) => {
+                chars.next();
+                formatted.push(c);
+            }
+            ('{', Some('}')) => {
+                chars.next();
+                let Some(argument) = arguments.next() else {
+                    return Err(invalid_argument(
+                        interpreter,
+                        paren,
+                        format!(
+                            "format() has more placeholders than the {} arguments given",
+                            args.len() - 1
+                        ),
+                    ));
+                };
+                formatted.push_str(&argument.to_string());
+            }
+            ('{', _) | ('}', _) => {
+                return Err(invalid_argument(
+                    interpreter,
+                    paren,
+                    format!(
+                        "Unmatched '{}' in format string; use '{}{}' for a literal brace",
+                        c, c, c
+                    ),
+                ));
+            }
+            _ => formatted.push(c),
+        }
+    }
+
+    if arguments.next().is_some() {
+        return Err(invalid_argument(
+            interpreter,
+            paren,
+            format!(
+                "format() was given {} arguments but has fewer placeholders",
+                args.len() - 1
+            ),
+        ));
+    }
+    Ok(Value::from(formatted))
+}
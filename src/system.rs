@@ -0,0 +1,397 @@
+//! Natives for files, standard input and the process.
+//!
+//! Touching the file system or the environment, and importing modules, needs
+//! a capability, which an interpreter starts without: the host grants them
+//! with `Interpreter::set_capabilities`, and the command line with
+//! `--allow-read`, `--allow-write`, `--allow-env` and `--allow-import`.
+//! Standard input, the script's arguments and `exit` are always available,
+//! since the host supplies the first two and receives `exit` as
+//! `RuntimeError::Exit`.
+
+use crate::expr::{exact_integer, Value};
+use crate::interpreter::Interpreter;
+use crate::lox::RuntimeError;
+use crate::token::Token;
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Capability {
+    Read,
+    Write,
+    Env,
+    Import,
+}
+
+impl fmt::Display for Capability {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Capability::Read => write!(f, "read"),
+            Capability::Write => write!(f, "write"),
+            Capability::Env => write!(f, "env"),
+            Capability::Import => write!(f, "import"),
+        }
+    }
+}
+
+/// The capabilities granted to a script. None are granted by default.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Capabilities {
+    /// Reading files and listing directories.
+    pub read: bool,
+    /// Creating and writing files.
+    pub write: bool,
+    /// Reading environment variables.
+    pub env: bool,
+    /// Loading modules with `import`.
+    pub import: bool,
+}
+
+impl Capabilities {
+    /// Every capability, for trusted scripts.
+    pub fn all() -> Self {
+        Capabilities {
+            read: true,
+            write: true,
+            env: true,
+            import: true,
+        }
+    }
+
+    pub fn allows(&self, capability: Capability) -> bool {
+        match capability {
+            Capability::Read => self.read,
+            Capability::Write => self.write,
+            Capability::Env => self.env,
+            Capability::Import => self.import,
+        }
+    }
+}
+
+pub fn define(interpreter: &mut Interpreter) {
+    interpreter.define_native("read_file", 1..=1, read_file);
+    interpreter.define_native("write_file", 2..=2, write_file);
+    interpreter.define_native("append_file", 2..=2, append_file);
+    interpreter.define_native("read_line", 0..=0, read_line);
+    interpreter.define_native("exists", 1..=1, exists);
+    interpreter.define_native("list_dir", 1..=1, list_dir);
+    interpreter.define_native("args", 0..=0, args);
+    interpreter.define_native("env", 1..=1, env);
+    interpreter.define_native("exit", 0..=1, exit);
+}
+
+/// Fails unless the interpreter was granted `capability`, which
+/// `operation`, attempted at `token`, needs.
+pub(crate) fn require(
+    interpreter: &Interpreter,
+    token: &Token,
+    operation: &'static str,
+    capability: Capability,
+) -> Result<(), RuntimeError> {
+    if interpreter.capabilities().allows(capability) {
+        return Ok(());
+    }
+    Err(RuntimeError::PermissionDenied {
+        token: token.clone(),
+        operation,
+        capability,
+        trace: interpreter.trace(token),
+    })
+}
+
+fn string_arg<'a>(
+    interpreter: &Interpreter,
+    paren: &Token,
+    function: &str,
+    value: &'a Value,
+) -> Result<&'a str, RuntimeError> {
+    match value {
+        Value::String(string) => Ok(string),
+        _ => Err(RuntimeError::InvalidArgument {
+            paren: paren.clone(),
+            message: format!(
+                "Argument to {}() must be a string, got {}",
+                function,
+                value.type_name()
+            ),
+            trace: interpreter.trace(paren),
+        }),
+    }
+}
+
+fn io_error(
+    interpreter: &Interpreter,
+    paren: &Token,
+    action: &str,
+    path: &str,
+    error: std::io::Error,
+) -> RuntimeError {
+    RuntimeError::Io {
+        paren: paren.clone(),
+        message: format!("Can't {} {:?}: {}", action, path, error),
+        trace: interpreter.trace(paren),
+    }
+}
+
+/// `read_file(path)` returns the contents of a UTF-8 text file.
+fn read_file(
+    interpreter: &mut Interpreter,
+    paren: &Token,
+    args: &[Value],
+) -> Result<Value, RuntimeError> {
+    require(interpreter, paren, "read_file()", Capability::Read)?;
+    let path = string_arg(interpreter, paren, "read_file", &args[0])?;
+    fs::read_to_string(path)
+        .map(Value::from)
+        .map_err(|error| io_error(interpreter, paren, "read", path, error))
+}
+
+/// `write_file(path, contents)` creates or replaces a file.
+fn write_file(
+    interpreter: &mut Interpreter,
+    paren: &Token,
+    args: &[Value],
+) -> Result<Value, RuntimeError> {
+    require(interpreter, paren, "write_file()", Capability::Write)?;
+    let path = string_arg(interpreter, paren, "write_file", &args[0])?;
+    let contents = string_arg(interpreter, paren, "write_file", &args[1])?;
+    fs::write(path, contents)
+        .map(|()| Value::Nil)
+        .map_err(|error| io_error(interpreter, paren, "write", path, error))
+}
+
+/// `append_file(path, contents)` adds to the end of a file, creating it if
+/// it doesn't exist.
+fn append_file(
+    interpreter: &mut Interpreter,
+    paren: &Token,
+    args: &[Value],
+) -> Result<Value, RuntimeError> {
+    require(interpreter, paren, "append_file()", Capability::Write)?;
+    let path = string_arg(interpreter, paren, "append_file", &args[0])?;
+    let contents = string_arg(interpreter, paren, "append_file", &args[1])?;
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .and_then(|mut file| file.write_all(contents.as_bytes()))
+        .map(|()| Value::Nil)
+        .map_err(|error| io_error(interpreter, paren, "append to", path, error))
+}
+
+/// `read_line()` returns the next line of input without its line ending,
+/// or nil at the end of the input.
+fn read_line(
+    interpreter: &mut Interpreter,
+    paren: &Token,
+    _: &[Value],
+) -> Result<Value, RuntimeError> {
+    let mut line = String::new();
+    match interpreter.input().read_line(&mut line) {
+        Ok(0) => Ok(Value::Nil),
+        Ok(_) => {
+            if line.ends_with('\n') {
+                line.pop();
+                if line.ends_with('\r') {
+                    line.pop();
+                }
+            }
+            Ok(Value::from(line))
+        }
+        Err(error) => Err(io_error(interpreter, paren, "read", "<stdin>", error)),
+    }
+}
+
+/// `exists(path)` tells whether a file or directory exists.
+fn exists(
+    interpreter: &mut Interpreter,
+    paren: &Token,
+    args: &[Value],
+) -> Result<Value, RuntimeError> {
+    require(interpreter, paren, "exists()", Capability::Read)?;
+    let path = string_arg(interpreter, paren, "exists", &args[0])?;
+    Ok(Value::from(Path::new(path).exists()))
+}
+
+/// `list_dir(path)` returns the names of a directory's entries, sorted.
+fn list_dir(
+    interpreter: &mut Interpreter,
+    paren: &Token,
+    args: &[Value],
+) -> Result<Value, RuntimeError> {
+    require(interpreter, paren, "list_dir()", Capability::Read)?;
+    let path = string_arg(interpreter, paren, "list_dir", &args[0])?;
+    let entries = fs::read_dir(path)
+        .and_then(|entries| entries.collect::<Result<Vec<_>, _>>())
+        .map_err(|error| io_error(interpreter, paren, "list", path, error))?;
+    let mut names: Vec<_> = entries
+        .iter()
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        .collect();
+    names.sort();
+    let names = names.into_iter().map(Value::from).collect();
+    Ok(interpreter.heap().list(names))
+}
+
+/// `args()` returns the arguments passed to the script.
+fn args(interpreter: &mut Interpreter, _: &Token, _: &[Value]) -> Result<Value, RuntimeError> {
+    let args = interpreter
+        .args()
+        .iter()
+        .cloned()
+        .map(Value::from)
+        .collect();
+    Ok(interpreter.heap().list(args))
+}
+
+/// `env(name)` returns an environment variable, or nil if it isn't set.
+fn env(
+    interpreter: &mut Interpreter,
+    paren: &Token,
+    args: &[Value],
+) -> Result<Value, RuntimeError> {
+    require(interpreter, paren, "env()", Capability::Env)?;
+    let name = string_arg(interpreter, paren, "env", &args[0])?;
+    Ok(std::env::var(name).map_or(Value::Nil, Value::from))
+}
+
+/// `exit(code?)` stops the script with `code`, 0 by default.
+fn exit(
+    interpreter: &mut Interpreter,
+    paren: &Token,
+    args: &[Value],
+) -> Result<Value, RuntimeError> {
+    let code = match args.first() {
+        None => Some(0),
+        Some(Value::Int(code)) => i32::try_from(*code).ok(),
+        Some(Value::Number(code)) => exact_integer(*code).and_then(|code| code.try_into().ok()),
+        Some(_) => None,
+    };
+    let Some(code) = code else {
+        return Err(RuntimeError::InvalidArgument {
+            paren: paren.clone(),
+            message: format!("Exit code must be an integer, got {}", args[0]),
+            trace: interpreter.trace(paren),
+        });
+    };
+    Err(RuntimeError::Exit {
+        paren: paren.clone(),
+        code,
+        trace: interpreter.trace(paren),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::Capabilities;
+    use crate::lox::RuntimeError;
+    use crate::test_util;
+    use std::fs;
+    use std::io::Cursor;
+
+    fn run(source: &str, capabilities: Capabilities) -> (String, Result<(), RuntimeError>) {
+        test_util::run_with(source, |interpreter| {
+            interpreter.set_capabilities(capabilities);
+            interpreter.set_input(Box::new(Cursor::new("first\r\nsecond")));
+            interpreter.set_args(vec!["-v".to_string(), "data.txt".to_string()]);
+        })
+    }
+
+    #[test]
+    fn test_files() {
+        let dir = std::env::temp_dir().join(format!("rslox-files-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("sub")).unwrap();
+
+        let (output, result) = run(
+            &format!(
+                "var dir = {:?};
+                 write_file(dir + \"/a.txt\", \"one\\n\");
+                 append_file(dir + \"/a.txt\", \"two\\n\");
+                 append_file(dir + \"/b.txt\", \"new\");
+                 print read_file(dir + \"/a.txt\").split(\"\\n\");
+                 print exists(dir + \"/b.txt\"); print exists(dir + \"/c.txt\");
+                 print list_dir(dir);
+                 try {{ read_file(dir + \"/c.txt\"); }} catch (e) {{ print \"missing\"; }}",
+                dir.to_str().unwrap()
+            ),
+            Capabilities::all(),
+        );
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(result.is_ok(), "{:?}", result);
+        assert_eq!(
+            output,
+            "[\"one\", \"two\", \"\"]\ntrue\nfalse\n[\"a.txt\", \"b.txt\", \"sub\"]\nmissing\n"
+        );
+    }
+
+    #[test]
+    fn test_capabilities_are_required() {
+        let denied = |source: &str, capabilities| match run(source, capabilities).1 {
+            Err(error @ RuntimeError::PermissionDenied { .. }) => error.message(),
+            result => panic!("expected a permission error, got {:?}", result),
+        };
+        let read_only = Capabilities {
+            read: true,
+            ..Capabilities::default()
+        };
+        assert_eq!(
+            denied("read_file(\"x\");", Capabilities::default()),
+            "read_file() requires read access (--allow-read)"
+        );
+        assert_eq!(
+            denied("list_dir(\".\");", Capabilities::default()),
+            "list_dir() requires read access (--allow-read)"
+        );
+        assert_eq!(
+            denied("write_file(\"x\", \"\");", read_only),
+            "write_file() requires write access (--allow-write)"
+        );
+        assert_eq!(
+            denied("append_file(\"x\", \"\");", read_only),
+            "append_file() requires write access (--allow-write)"
+        );
+        assert_eq!(
+            denied("env(\"HOME\");", read_only),
+            "env() requires env access (--allow-env)"
+        );
+        let (output, result) = run("print exists(\".\");", read_only);
+        assert!(result.is_ok(), "{:?}", result);
+        assert_eq!(output, "true\n");
+    }
+
+    #[test]
+    fn test_input_args_and_env() {
+        let (output, result) = run(
+            "print read_line(); print read_line(); print read_line(); print args();
+             print env(\"RSLOX_SURELY_UNSET\");",
+            Capabilities {
+                env: true,
+                ..Capabilities::default()
+            },
+        );
+        assert!(result.is_ok(), "{:?}", result);
+        assert_eq!(output, "first\nsecond\nnil\n[\"-v\", \"data.txt\"]\nnil\n");
+    }
+
+    #[test]
+    fn test_exit_is_not_caught() {
+        let (output, result) = run(
+            "try { exit(2); } catch (e) { print \"caught\"; } finally { print \"finally\"; }
+             print \"after\";",
+            Capabilities::default(),
+        );
+        assert_eq!(output, "finally\n");
+        assert!(matches!(result, Err(RuntimeError::Exit { code: 2, .. })));
+        assert!(matches!(
+            run("exit();", Capabilities::default()).1,
+            Err(RuntimeError::Exit { code: 0, .. })
+        ));
+        assert!(matches!(
+            run("exit(\"no\");", Capabilities::default()).1,
+            Err(RuntimeError::InvalidArgument { .. })
+        ));
+    }
+}
@@ -0,0 +1,53 @@
+//! Helpers shared by the unit tests.
+
+use crate::interpreter::Interpreter;
+use crate::lox::RuntimeError;
+use crate::parser::Parser;
+use crate::scanner::Scanner;
+use std::cell::RefCell;
+use std::io::{self, Write};
+use std::rc::Rc;
+
+/// A `print` destination the test can read back after the interpreter
+/// has taken ownership of it.
+#[derive(Clone, Default)]
+pub struct SharedOutput(Rc<RefCell<Vec<u8>>>);
+
+impl SharedOutput {
+    /// Everything written so far, which is then cleared.
+    pub fn take(&self) -> String {
+        String::from_utf8(self.0.take()).unwrap()
+    }
+}
+
+impl Write for SharedOutput {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Runs `source`, returning what it printed and how it ended.
+pub fn run(source: &str) -> (String, Result<(), RuntimeError>) {
+    run_with(source, |_| {})
+}
+
+/// Runs `source` with an interpreter that `configure` has set up first.
+pub fn run_with(
+    source: &str,
+    configure: impl FnOnce(&mut Interpreter),
+) -> (String, Result<(), RuntimeError>) {
+    let mut scanner = Scanner::new(source.to_string());
+    let tokens = scanner.scan_tokens().expect("scan error");
+    let program = Parser::new(tokens).parse().expect("parse error");
+
+    let output = SharedOutput::default();
+    let mut interpreter = Interpreter::with_output(Box::new(output.clone()));
+    configure(&mut interpreter);
+    let result = interpreter.interpret(&program);
+    (output.take(), result)
+}
@@ -0,0 +1,407 @@
+//! Natives for clocks and UTC dates.
+//!
+//! Times are seconds since the Unix epoch, as floats. Dates are converted
+//! with the proleptic Gregorian calendar in UTC, so no time zone database
+//! is needed.
+
+use crate::expr::{exact_integer, Value};
+use crate::interpreter::Interpreter;
+use crate::lox::RuntimeError;
+use crate::math::number_arg;
+use crate::token::Token;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// The pattern `format_time` uses by default, ISO-8601 in UTC.
+const ISO_8601: &str = "%Y-%m-%dT%H:%M:%SZ";
+
+const WEEKDAYS: [&str; 7] = ["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"];
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+pub fn define(interpreter: &mut Interpreter) {
+    interpreter.define_native("clock", 0..=0, clock);
+    interpreter.define_native("now", 0..=0, now);
+    interpreter.define_native("sleep", 1..=1, sleep);
+    interpreter.define_native("format_time", 1..=2, format_time);
+    interpreter.define_native("parse_time", 1..=1, parse_time);
+}
+
+fn invalid_argument(interpreter: &Interpreter, paren: &Token, message: String) -> RuntimeError {
+    RuntimeError::InvalidArgument {
+        paren: paren.clone(),
+        message,
+        trace: interpreter.trace(paren),
+    }
+}
+
+/// `clock()` returns seconds from a monotonic clock, for measuring how long
+/// something takes.
+fn clock(interpreter: &mut Interpreter, _: &Token, _: &[Value]) -> Result<Value, RuntimeError> {
+    Ok(Value::Number(interpreter.started().elapsed().as_secs_f64()))
+}
+
+/// `now()` returns the current time in seconds since the Unix epoch.
+fn now(_: &mut Interpreter, _: &Token, _: &[Value]) -> Result<Value, RuntimeError> {
+    let seconds = match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(elapsed) => elapsed.as_secs_f64(),
+        Err(error) => -error.duration().as_secs_f64(),
+    };
+    Ok(Value::Number(seconds))
+}
+
+/// `sleep(ms)` pauses for `ms` milliseconds.
+fn sleep(
+    interpreter: &mut Interpreter,
+    paren: &Token,
+    args: &[Value],
+) -> Result<Value, RuntimeError> {
+    let ms = number_arg(interpreter, paren, "sleep", &args[0])?;
+    let Ok(duration) = Duration::try_from_secs_f64(ms / 1000.0) else {
+        return Err(invalid_argument(
+            interpreter,
+            paren,
+            format!(
+                "Duration passed to sleep() must be a non-negative number, got {}",
+                args[0]
+            ),
+        ));
+    };
+    thread::sleep(duration);
+    Ok(Value::Nil)
+}
+
+/// A moment in UTC, broken down into its calendar fields.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct DateTime {
+    year: i64,
+    month: u32,
+    day: u32,
+    hour: u32,
+    minute: u32,
+    second: u32,
+    millisecond: u32,
+}
+
+impl DateTime {
+    fn from_epoch(seconds: i64, millisecond: u32) -> Self {
+        let days = seconds.div_euclid(86_400);
+        let time = seconds.rem_euclid(86_400) as u32;
+        let (year, month, day) = civil_from_days(days);
+        DateTime {
+            year,
+            month,
+            day,
+            hour: time / 3600,
+            minute: time / 60 % 60,
+            second: time % 60,
+            millisecond,
+        }
+    }
+
+    /// Whole seconds since the epoch; milliseconds are left out.
+    fn epoch_seconds(&self) -> i64 {
+        let days = days_from_civil(self.year, self.month, self.day);
+        days * 86_400 + i64::from(self.hour * 3600 + self.minute * 60 + self.second)
+    }
+
+    /// Monday is 0.
+    fn weekday(&self) -> usize {
+        // 1970-01-01 was a Thursday.
+        (days_from_civil(self.year, self.month, self.day) + 3).rem_euclid(7) as usize
+    }
+}
+
+/// Converts days since 1970-01-01 to a (year, month, day) date, after
+/// Howard Hinnant's `civil_from_days`.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * shifted_month + 2) / 5 + 1) as u32;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    } as u32;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// The inverse of `civil_from_days`.
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let shifted_month = i64::from((month + 9) % 12);
+    let day_of_year = (153 * shifted_month + 2) / 5 + i64::from(day) - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// `format_time(epoch, pattern?)` formats a time in UTC. The pattern may
+/// use `%Y`, `%m`, `%d`, `%H`, `%M`, `%S`, `%L` (milliseconds), `%a` and
+/// `%b` (weekday and month names) and `%%`, and defaults to ISO-8601.
+fn format_time(
+    interpreter: &mut Interpreter,
+    paren: &Token,
+    args: &[Value],
+) -> Result<Value, RuntimeError> {
+    let epoch = number_arg(interpreter, paren, "format_time", &args[0])?;
+    let pattern = match args.get(1) {
+        None => ISO_8601,
+        Some(Value::String(pattern)) => pattern,
+        Some(pattern) => {
+            return Err(invalid_argument(
+                interpreter,
+                paren,
+                format!(
+                    "Pattern passed to format_time() must be a string, got {}",
+                    pattern.type_name()
+                ),
+            ))
+        }
+    };
+
+    // Limiting years to four digits keeps the arithmetic in range.
+    const LIMIT: f64 = 253_402_300_800.0; // 10000-01-01T00:00:00Z
+    let seconds = epoch.floor();
+    let (Some(whole), true) = (exact_integer(seconds), (-LIMIT..LIMIT).contains(&seconds)) else {
+        return Err(invalid_argument(
+            interpreter,
+            paren,
+            format!("Time {} is out of range for format_time()", args[0]),
+        ));
+    };
+    let millisecond = (((epoch - seconds) * 1000.0) as u32).min(999);
+    let time = DateTime::from_epoch(whole, millisecond);
+
+    let mut formatted = String::new();
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            formatted.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('Y') if time.year < 0 => formatted.push_str(&format!("-{:04}", -time.year)),
+            Some('Y') => formatted.push_str(&format!("{:04}", time.year)),
+            Some('m') => formatted.push_str(&format!("{:02}", time.month)),
+            Some('d') => formatted.push_str(&format!("{:02}", time.day)),
+            Some('H') => formatted.push_str(&format!("{:02}", time.hour)),
+            Some('M') => formatted.push_str(&format!("{:02}", time.minute)),
+            Some('S') => formatted.push_str(&format!("{:02}", time.second)),
+            Some('L') => formatted.push_str(&format!("{:03}", time.millisecond)),
+            Some('a') => formatted.push_str(WEEKDAYS[time.weekday()]),
+            Some('b') => formatted.push_str(MONTHS[time.month as usize - 1]),
+            Some('%') => formatted.push('%'),
+            specifier => {
+                let specifier = specifier.map_or(String::new(), String::from);
+                return Err(invalid_argument(
+                    interpreter,
+                    paren,
+                    format!("Unknown format specifier '%{}' in format_time()", specifier),
+                ));
+            }
+        }
+    }
+    Ok(Value::from(formatted))
+}
+
+/// `parse_time(text)` parses an ISO-8601 date or date and time and returns
+/// it in seconds since the epoch. A time without an offset is taken to be
+/// in UTC.
+fn parse_time(
+    interpreter: &mut Interpreter,
+    paren: &Token,
+    args: &[Value],
+) -> Result<Value, RuntimeError> {
+    let Value::String(text) = &args[0] else {
+        return Err(invalid_argument(
+            interpreter,
+            paren,
+            format!(
+                "Argument to parse_time() must be a string, got {}",
+                args[0].type_name()
+            ),
+        ));
+    };
+    let Some((seconds, millisecond)) = parse_iso_8601(text) else {
+        return Err(invalid_argument(
+            interpreter,
+            paren,
+            format!("Invalid ISO-8601 time {:?}", text),
+        ));
+    };
+    if millisecond == 0 {
+        Ok(Value::Int(seconds))
+    } else {
+        Ok(Value::Number(
+            seconds as f64 + f64::from(millisecond) / 1000.0,
+        ))
+    }
+}
+
+/// Parses `YYYY-MM-DD`, optionally followed by `T` or a space and
+/// `HH:MM`, `HH:MM:SS` or `HH:MM:SS.fff`, and then by `Z` or an offset
+/// such as `+02:00`. Returns whole seconds since the epoch and
+/// milliseconds.
+fn parse_iso_8601(text: &str) -> Option<(i64, u32)> {
+    let mut rest = text;
+    let year = digits(&mut rest, 4)?;
+    let month = prefixed(&mut rest, '-', 2)?;
+    let day = prefixed(&mut rest, '-', 2)?;
+    let mut time = DateTime {
+        year: i64::from(year),
+        month,
+        day,
+        hour: 0,
+        minute: 0,
+        second: 0,
+        millisecond: 0,
+    };
+    if !(1..=12).contains(&month) || !(1..=days_in_month(time.year, month)).contains(&day) {
+        return None;
+    }
+
+    let mut offset = 0;
+    if let Some(after) = rest.strip_prefix(['T', ' ']) {
+        rest = after;
+        time.hour = digits(&mut rest, 2)?;
+        time.minute = prefixed(&mut rest, ':', 2)?;
+        if rest.starts_with(':') {
+            time.second = prefixed(&mut rest, ':', 2)?;
+            if let Some(after) = rest.strip_prefix('.') {
+                let fraction_len = after
+                    .find(|c: char| !c.is_ascii_digit())
+                    .unwrap_or(after.len());
+                let (fraction, after) = after.split_at(fraction_len);
+                rest = after;
+                if fraction.is_empty() {
+                    return None;
+                }
+                // Digits past milliseconds are dropped.
+                let millis = format!("{:0<3}", &fraction[..fraction.len().min(3)]);
+                time.millisecond = millis.parse().ok()?;
+            }
+        }
+        if time.hour > 23 || time.minute > 59 || time.second > 59 {
+            return None;
+        }
+
+        if let Some(after) = rest.strip_prefix('Z') {
+            rest = after;
+        } else if let Some(sign) = rest.chars().next().filter(|c| matches!(c, '+' | '-')) {
+            rest = &rest[1..];
+            let hours = digits(&mut rest, 2)?;
+            let minutes = prefixed(&mut rest, ':', 2)?;
+            if hours > 23 || minutes > 59 {
+                return None;
+            }
+            offset = i64::from(hours * 3600 + minutes * 60);
+            if sign == '-' {
+                offset = -offset;
+            }
+        }
+    }
+
+    if !rest.is_empty() {
+        return None;
+    }
+    Some((time.epoch_seconds() - offset, time.millisecond))
+}
+
+/// Consumes exactly `count` ASCII digits from the front of `text`.
+fn digits(text: &mut &str, count: usize) -> Option<u32> {
+    let digits = text.get(..count)?;
+    if !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    *text = &text[count..];
+    digits.parse().ok()
+}
+
+/// Consumes `separator` followed by `count` digits.
+fn prefixed(text: &mut &str, separator: char, count: usize) -> Option<u32> {
+    *text = text.strip_prefix(separator)?;
+    digits(text, count)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_calendar_conversions() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(-1), (1969, 12, 31));
+        assert_eq!(civil_from_days(11_016), (2000, 2, 29));
+        for days in (-800_000..800_000).step_by(997) {
+            let (year, month, day) = civil_from_days(days);
+            assert_eq!(days_from_civil(year, month, day), days);
+        }
+
+        let time = DateTime::from_epoch(1_700_000_000, 0);
+        assert_eq!(
+            (
+                time.year,
+                time.month,
+                time.day,
+                time.hour,
+                time.minute,
+                time.second
+            ),
+            (2023, 11, 14, 22, 13, 20)
+        );
+        assert_eq!(WEEKDAYS[time.weekday()], "Tue");
+        assert_eq!(time.epoch_seconds(), 1_700_000_000);
+    }
+
+    #[test]
+    fn test_parse_iso_8601() {
+        assert_eq!(parse_iso_8601("1970-01-01"), Some((0, 0)));
+        assert_eq!(
+            parse_iso_8601("2023-11-14T22:13:20Z"),
+            Some((1_700_000_000, 0))
+        );
+        assert_eq!(
+            parse_iso_8601("2023-11-14 22:13:20.25"),
+            Some((1_700_000_000, 250))
+        );
+        assert_eq!(
+            parse_iso_8601("2023-11-15T00:13:20+02:00"),
+            Some((1_700_000_000, 0))
+        );
+        assert_eq!(
+            parse_iso_8601("2023-11-14T22:13-00:30"),
+            Some((1_700_001_780, 0))
+        );
+        for invalid in [
+            "",
+            "2023",
+            "2023-13-01",
+            "2023-02-29",
+            "2023-11-14T24:00",
+            "2023-11-14T22:13:20.",
+            "2023-11-14T22:13:20Zjunk",
+            "2023-11-14T22",
+        ] {
+            assert_eq!(parse_iso_8601(invalid), None, "{:?}", invalid);
+        }
+        assert_eq!(parse_iso_8601("2024-02-29"), Some((1_709_164_800, 0)));
+    }
+}
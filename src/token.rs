@@ -1,15 +1,66 @@
+use crate::interner::Symbol;
 use crate::token_type::TokenType;
+use std::borrow::Cow;
 use std::fmt;
 
+/// Where a token's text lies in its source, as byte offsets. Offsets are
+/// 32 bits to keep tokens, and the errors that carry them, small.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Span {
+    pub start: u32,
+    pub end: u32,
+}
+
+/// A token, which refers to its text in the source by `span` rather than
+/// holding a copy. Identifiers and string literals carry their interned
+/// text in `type_`; the text of other tokens follows from their type.
 #[derive(Debug, Clone)]
 pub struct Token {
     pub type_: TokenType,
-    pub lexeme: String,
+    pub span: Span,
     pub line: u32,
 }
 
+impl Token {
+    /// An identifier token spelling `name`, for looking up a name that
+    /// doesn't appear in the source.
+    pub fn identifier(name: &str, line: u32) -> Self {
+        Self {
+            type_: TokenType::Identifier(Symbol::intern(name)),
+            span: Span::default(),
+            line,
+        }
+    }
+
+    /// The name an identifier spells.
+    ///
+    /// # Panics
+    ///
+    /// If the token isn't an identifier, which the parser rules out
+    /// everywhere a name is expected.
+    pub fn name(&self) -> &Symbol {
+        match &self.type_ {
+            TokenType::Identifier(name) => name,
+            type_ => panic!("expected an identifier, got {}", type_),
+        }
+    }
+
+    /// The token's text as it was written, for messages and printing.
+    /// Number literals are written in their shortest form, so `1.50`
+    /// reads back as `1.5`.
+    pub fn lexeme(&self) -> Cow<'_, str> {
+        match &self.type_ {
+            TokenType::Identifier(name) => Cow::Borrowed(name),
+            TokenType::String(string) => Cow::Owned(format!("\"{}\"", string)),
+            TokenType::Number(n) => Cow::Owned(format!("{:?}", n)),
+            TokenType::Integer(n) => Cow::Owned(n.to_string()),
+            type_ => Cow::Borrowed(type_.spelling()),
+        }
+    }
+}
+
 impl fmt::Display for Token {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{} {}", self.type_, self.lexeme)
+        write!(f, "{} {}", self.type_, self.lexeme())
     }
 }
@@ -1,3 +1,4 @@
+use crate::interner::Symbol;
 use std::fmt;
 
 #[derive(Debug, Clone)]
@@ -7,6 +8,8 @@ pub enum TokenType {
     RightParen,
     LeftBrace,
     RightBrace,
+    LeftBracket,
+    RightBracket,
     Comma,
     Dot,
     Minus,
@@ -14,6 +17,11 @@ pub enum TokenType {
     Semicolon,
     Slash,
     Star,
+    Percent,
+    Ampersand,
+    Pipe,
+    Caret,
+    Colon,
     // one or two character tokens.
     Bang,
     BangEqual,
@@ -23,33 +31,129 @@ pub enum TokenType {
     GreaterEqual,
     Less,
     LessEqual,
+    LessLess,
+    GreaterGreater,
+    StarStar,
+    Tilde,
+    TildeSlash,
+    Question,
+    QuestionQuestion,
+    QuestionDot,
     // literals.
-    Identifier,
-    String(String),
+    Identifier(Symbol),
+    String(Symbol),
     Number(f64),
+    /// The magnitude of an integer literal. A leading `-` is a separate
+    /// token, so this goes up to 2^63 to allow `-9223372036854775808`.
+    Integer(u64),
     // keywords.
     And,
+    Break,
+    Catch,
     Class,
+    Continue,
     Else,
     False,
+    Finally,
     Fun,
     For,
     If,
+    Import,
+    In,
     Nil,
     Or,
     Print,
     Return,
     Super,
     This,
+    Throw,
     True,
+    Try,
     Var,
     While,
 
     Eof,
 }
 
+impl TokenType {
+    /// How a token of a type with fixed text is written: punctuation,
+    /// keywords, and the empty end of file. Empty for literals and
+    /// identifiers, whose text varies.
+    pub fn spelling(&self) -> &'static str {
+        match self {
+            TokenType::LeftParen => "(",
+            TokenType::RightParen => ")",
+            TokenType::LeftBrace => "{",
+            TokenType::RightBrace => "}",
+            TokenType::LeftBracket => "[",
+            TokenType::RightBracket => "]",
+            TokenType::Comma => ",",
+            TokenType::Dot => ".",
+            TokenType::Minus => "-",
+            TokenType::Plus => "+",
+            TokenType::Semicolon => ";",
+            TokenType::Slash => "/",
+            TokenType::Star => "*",
+            TokenType::Percent => "%",
+            TokenType::Ampersand => "&",
+            TokenType::Pipe => "|",
+            TokenType::Caret => "^",
+            TokenType::Colon => ":",
+            TokenType::Bang => "!",
+            TokenType::BangEqual => "!=",
+            TokenType::Equal => "=",
+            TokenType::EqualEqual => "==",
+            TokenType::Greater => ">",
+            TokenType::GreaterEqual => ">=",
+            TokenType::Less => "<",
+            TokenType::LessEqual => "<=",
+            TokenType::LessLess => "<<",
+            TokenType::GreaterGreater => ">>",
+            TokenType::StarStar => "**",
+            TokenType::Tilde => "~",
+            TokenType::TildeSlash => "~/",
+            TokenType::Question => "?",
+            TokenType::QuestionQuestion => "??",
+            TokenType::QuestionDot => "?.",
+            TokenType::And => "and",
+            TokenType::Break => "break",
+            TokenType::Catch => "catch",
+            TokenType::Class => "class",
+            TokenType::Continue => "continue",
+            TokenType::Else => "else",
+            TokenType::False => "false",
+            TokenType::Finally => "finally",
+            TokenType::Fun => "fun",
+            TokenType::For => "for",
+            TokenType::If => "if",
+            TokenType::Import => "import",
+            TokenType::In => "in",
+            TokenType::Nil => "nil",
+            TokenType::Or => "or",
+            TokenType::Print => "print",
+            TokenType::Return => "return",
+            TokenType::Super => "super",
+            TokenType::This => "this",
+            TokenType::Throw => "throw",
+            TokenType::True => "true",
+            TokenType::Try => "try",
+            TokenType::Var => "var",
+            TokenType::While => "while",
+            TokenType::Identifier(_)
+            | TokenType::String(_)
+            | TokenType::Number(_)
+            | TokenType::Integer(_)
+            | TokenType::Eof => "",
+        }
+    }
+}
+
+/// Names the type, without an identifier's name, which the lexeme shows.
 impl fmt::Display for TokenType {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{:?}", self)
+        match self {
+            TokenType::Identifier(_) => write!(f, "Identifier"),
+            type_ => write!(f, "{:?}", type_),
+        }
     }
 }
@@ -0,0 +1,309 @@
+//! Runs the Lox programs under `tests/lox/` through the `rslox` binary and
+//! checks what they print against the annotations in their comments.
+//!
+//! The programs outside `extensions/` are the Crafting Interpreters test
+//! suite, from the `test/` directory of the upstream repository, and are
+//! checked the way its runner checks jlox:
+//!
+//! - `// expect: text` is a line the program prints to stdout.
+//! - `// expect runtime error: message` is a runtime error raised on that
+//!   line. stderr starts with the message, followed by a line containing
+//!   `[line N]`, and the program exits with code 70.
+//! - `// [line N] Error at 'x': message` is a compile error reported on
+//!   line N, and `// Error at 'x': message` one on the annotated line. The
+//!   program then exits with code 65 without running. `[java line N]`
+//!   errors are expected too, and `[c line N]` ones, which only clox
+//!   reports, are ignored.
+//!
+//! Programs listed in `tests/lox/known_failures.txt` are expected to fail,
+//! so that the list records exactly which features don't conform. One that
+//! starts passing fails the run until it is taken off the list.
+//!
+//! The suite runs a second time with `--gc-stress`, which collects garbage
+//! before every statement, so that the collector is tested by every program.
+
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const EXIT_COMPILE_ERROR: i32 = 65;
+const EXIT_RUNTIME_ERROR: i32 = 70;
+
+/// How long a program may run before it is killed, so that one that loops
+/// forever fails instead of hanging the run.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The chapter of the book that introduces each directory's feature, for
+/// reporting results chapter by chapter. Programs at the top level belong
+/// to chapter 8, where `print` statements arrive.
+const CHAPTERS: &[(&str, &str)] = &[
+    ("assignment", "08_statements_and_state"),
+    ("block", "08_statements_and_state"),
+    ("bool", "08_statements_and_state"),
+    ("comments", "08_statements_and_state"),
+    ("nil", "08_statements_and_state"),
+    ("number", "08_statements_and_state"),
+    ("operator", "08_statements_and_state"),
+    ("print", "08_statements_and_state"),
+    ("string", "08_statements_and_state"),
+    ("variable", "08_statements_and_state"),
+    ("for", "09_control_flow"),
+    ("if", "09_control_flow"),
+    ("logical_operator", "09_control_flow"),
+    ("while", "09_control_flow"),
+    ("call", "10_functions"),
+    ("closure", "10_functions"),
+    ("function", "10_functions"),
+    ("return", "10_functions"),
+    ("class", "12_classes"),
+    ("constructor", "12_classes"),
+    ("field", "12_classes"),
+    ("method", "12_classes"),
+    ("this", "12_classes"),
+    ("inheritance", "13_inheritance"),
+    ("super", "13_inheritance"),
+    ("regression", "regression"),
+    ("extensions", "extensions"),
+];
+
+fn chapter(name: &str) -> &'static str {
+    let Some((directory, _)) = name.split_once('/') else {
+        return "08_statements_and_state";
+    };
+    CHAPTERS
+        .iter()
+        .find(|(known, _)| *known == directory)
+        .map_or_else(
+            || panic!("{}: no chapter for its directory", name),
+            |(_, chapter)| chapter,
+        )
+}
+
+#[derive(Debug, Default)]
+struct Expectations {
+    stdout: Vec<String>,
+    compile_errors: Vec<String>,
+    /// The message of the expected runtime error and its line.
+    runtime_error: Option<(String, usize)>,
+}
+
+impl Expectations {
+    fn parse(source: &str) -> Self {
+        let mut expectations = Expectations::default();
+        for (index, line) in source.lines().enumerate() {
+            let line_number = index + 1;
+            if let Some(text) = annotation(line, "// expect: ") {
+                expectations.stdout.push(text.to_string());
+            } else if let Some(error) = annotation(line, "// Error") {
+                expectations
+                    .compile_errors
+                    .push(format!("[line {}] Error{}", line_number, error));
+            } else if let Some(error) = annotation(line, "// [").and_then(compile_error) {
+                expectations.compile_errors.push(error);
+            } else if let Some(message) = annotation(line, "// expect runtime error: ") {
+                expectations.runtime_error = Some((message.to_string(), line_number));
+            }
+        }
+        expectations
+    }
+
+    fn exit_code(&self) -> i32 {
+        if self.runtime_error.is_some() {
+            EXIT_RUNTIME_ERROR
+        } else if !self.compile_errors.is_empty() {
+            EXIT_COMPILE_ERROR
+        } else {
+            0
+        }
+    }
+}
+
+/// The text following `marker` anywhere in `line`, which is how the
+/// upstream runner finds annotations.
+fn annotation<'a>(line: &'a str, marker: &str) -> Option<&'a str> {
+    line.find(marker).map(|start| &line[start + marker.len()..])
+}
+
+/// Parses the rest of `[line N] Error...` or `[java line N] Error...`.
+fn compile_error(rest: &str) -> Option<String> {
+    let rest = rest.strip_prefix("java ").unwrap_or(rest);
+    let (line, error) = rest.strip_prefix("line ")?.split_once("] ")?;
+    let line: usize = line.parse().ok()?;
+    error
+        .starts_with("Error")
+        .then(|| format!("[line {}] {}", line, error))
+}
+
+/// Runs the program at `path`, describing every way its behavior differs
+/// from its annotations.
+fn check(path: &Path, options: &[&str]) -> Result<(), Vec<String>> {
+    let source = fs::read_to_string(path).unwrap();
+    let expected = Expectations::parse(&source);
+    let Some((status, stdout, stderr)) = run(path, options) else {
+        return Err(vec![format!("timed out after {:?}", TIMEOUT)]);
+    };
+
+    let mut failures = Vec::new();
+    let stdout: Vec<_> = stdout.lines().collect();
+    if stdout != expected.stdout {
+        failures.push(format!(
+            "expected stdout {:?}, got {:?}",
+            expected.stdout, stdout
+        ));
+    }
+    let stderr: Vec<_> = stderr.lines().collect();
+    match &expected.runtime_error {
+        // Only the line of the first stack frame is checked.
+        Some((message, line)) => {
+            let frame = format!("[line {}]", line);
+            if stderr.first() != Some(&message.as_str())
+                || !stderr.get(1).is_some_and(|second| second.contains(&frame))
+            {
+                failures.push(format!(
+                    "expected runtime error {:?} and {:?}, got {:?}",
+                    message, frame, stderr
+                ));
+            }
+        }
+        None => {
+            if stderr != expected.compile_errors {
+                failures.push(format!(
+                    "expected stderr {:?}, got {:?}",
+                    expected.compile_errors, stderr
+                ));
+            }
+        }
+    }
+    let code = status.code();
+    if code != Some(expected.exit_code()) {
+        failures.push(format!(
+            "expected exit code {}, got {:?}",
+            expected.exit_code(),
+            code
+        ));
+    }
+
+    if failures.is_empty() {
+        Ok(())
+    } else {
+        Err(failures)
+    }
+}
+
+/// Runs `rslox` on the program at `path`, returning its exit status and
+/// output, or `None` if it is still running after `TIMEOUT`.
+fn run(path: &Path, options: &[&str]) -> Option<(ExitStatus, String, String)> {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_rslox"))
+        .args(options)
+        .arg(path)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("failed to run rslox");
+    // The pipes are read on their own threads so that a program printing
+    // more than they buffer doesn't block.
+    let read = |mut pipe: Box<dyn Read + Send>| {
+        thread::spawn(move || {
+            let mut output = Vec::new();
+            pipe.read_to_end(&mut output).unwrap();
+            String::from_utf8_lossy(&output).into_owned()
+        })
+    };
+    let stdout = read(Box::new(child.stdout.take().unwrap()));
+    let stderr = read(Box::new(child.stderr.take().unwrap()));
+
+    let start = Instant::now();
+    let status = loop {
+        if let Some(status) = child.try_wait().unwrap() {
+            break status;
+        }
+        if start.elapsed() > TIMEOUT {
+            child.kill().unwrap();
+            child.wait().unwrap();
+            return None;
+        }
+        thread::sleep(Duration::from_millis(5));
+    };
+    Some((status, stdout.join().unwrap(), stderr.join().unwrap()))
+}
+
+fn lox_files(dir: &Path, files: &mut Vec<PathBuf>) {
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            lox_files(&path, files);
+        } else if path.extension().is_some_and(|extension| extension == "lox") {
+            files.push(path);
+        }
+    }
+}
+
+/// Runs every program with the interpreter `options` and checks it.
+fn run_suite(options: &[&str]) {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/lox");
+    let known_failures: HashSet<_> = fs::read_to_string(root.join("known_failures.txt"))
+        .unwrap()
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(String::from)
+        .collect();
+
+    let mut files = Vec::new();
+    lox_files(&root, &mut files);
+    files.sort();
+
+    // Passed and total, per chapter directory.
+    let mut chapters: BTreeMap<&str, (usize, usize)> = BTreeMap::new();
+    let mut problems = Vec::new();
+    for path in &files {
+        let name = path
+            .strip_prefix(&root)
+            .unwrap()
+            .to_string_lossy()
+            .replace('\\', "/");
+        let result = check(path, options);
+        let counts = chapters.entry(chapter(&name)).or_default();
+        counts.1 += 1;
+        match (result, known_failures.contains(&name)) {
+            (Ok(()), false) => counts.0 += 1,
+            (Ok(()), true) => problems.push(format!(
+                "{}: passes but is listed in known_failures.txt",
+                name
+            )),
+            (Err(_), true) => {}
+            (Err(failures), false) => {
+                for failure in failures {
+                    problems.push(format!("{}: {}", name, failure));
+                }
+            }
+        }
+    }
+    for name in &known_failures {
+        if !files.iter().any(|path| path.ends_with(name)) {
+            problems.push(format!(
+                "{}: listed in known_failures.txt but missing",
+                name
+            ));
+        }
+    }
+
+    for (chapter, (passed, total)) in &chapters {
+        println!("{:<24} {:>3}/{:<3} passed", chapter, passed, total);
+    }
+    assert!(problems.is_empty(), "\n{}\n", problems.join("\n"));
+}
+
+#[test]
+fn conformance() {
+    run_suite(&[]);
+}
+
+#[test]
+fn conformance_under_gc_stress() {
+    run_suite(&["--gc-stress"]);
+}
@@ -0,0 +1,137 @@
+//! Golden snapshots of what the scanner and parser make of every program
+//! under `tests/lox/`.
+//!
+//! Each program has a `.snap` file under `tests/snapshots/` at the same
+//! relative path, holding its token dump and its syntax tree as printed by
+//! `Stmt`'s `Display`. Any difference fails the test, so changes to the
+//! scanner or parser show up as diffs of these files. To accept the new
+//! output, rerun with `UPDATE_SNAPSHOTS=1`:
+//!
+//! ```text
+//! UPDATE_SNAPSHOTS=1 cargo test --test snapshots
+//! ```
+
+use rslox::parser::Parser;
+use rslox::scanner::Scanner;
+use std::env;
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The token dump and syntax tree of `source`.
+fn snapshot(source: &str) -> String {
+    let mut snapshot = String::from("--- tokens ---\n");
+    let mut scanner = Scanner::new(source.to_string());
+    let tokens = match scanner.scan_tokens() {
+        Ok(tokens) => tokens,
+        Err(error) => {
+            writeln!(snapshot, "[line {}] Error: {}", error.line, error.message).unwrap();
+            return snapshot;
+        }
+    };
+    for token in tokens {
+        writeln!(snapshot, "{:>4} {}", token.line, token).unwrap();
+    }
+
+    snapshot.push_str("--- ast ---\n");
+    match Parser::new(tokens).parse() {
+        Ok(program) => write!(snapshot, "{}", program).unwrap(),
+        Err(errors) => {
+            for error in errors {
+                writeln!(snapshot, "{}", error).unwrap();
+            }
+        }
+    }
+    snapshot
+}
+
+fn files_with_extension(dir: &Path, extension: &str, files: &mut Vec<PathBuf>) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            files_with_extension(&path, extension, files);
+        } else if path.extension().is_some_and(|e| e == extension) {
+            files.push(path);
+        }
+    }
+}
+
+#[test]
+fn snapshots() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests");
+    let fixtures = root.join("lox");
+    let snapshots = root.join("snapshots");
+    let update = env::var_os("UPDATE_SNAPSHOTS").is_some();
+
+    let mut sources = Vec::new();
+    files_with_extension(&fixtures, "lox", &mut sources);
+    sources.sort();
+
+    let mut problems = Vec::new();
+    let mut expected_snaps = Vec::new();
+    for source_path in &sources {
+        let relative = source_path.strip_prefix(&fixtures).unwrap();
+        let snap_path = snapshots.join(relative).with_extension("snap");
+        let actual = snapshot(&fs::read_to_string(source_path).unwrap());
+        let recorded = fs::read_to_string(&snap_path).ok();
+
+        if recorded.as_deref() != Some(actual.as_str()) {
+            if update {
+                fs::create_dir_all(snap_path.parent().unwrap()).unwrap();
+                fs::write(&snap_path, &actual).unwrap();
+            } else if recorded.is_none() {
+                problems.push(format!("{}: no snapshot", relative.display()));
+            } else {
+                problems.push(format!(
+                    "{}: snapshot differs\n{}",
+                    relative.display(),
+                    diff(recorded.as_deref().unwrap(), &actual)
+                ));
+            }
+        }
+        expected_snaps.push(snap_path);
+    }
+
+    // Snapshots whose program is gone.
+    let mut snaps = Vec::new();
+    files_with_extension(&snapshots, "snap", &mut snaps);
+    for snap in snaps {
+        if !expected_snaps.contains(&snap) {
+            if update {
+                fs::remove_file(&snap).unwrap();
+            } else {
+                let relative = snap.strip_prefix(&snapshots).unwrap();
+                problems.push(format!("{}: no program for snapshot", relative.display()));
+            }
+        }
+    }
+
+    assert!(
+        problems.is_empty(),
+        "\n{}\n\nRerun with UPDATE_SNAPSHOTS=1 to accept the new output.\n",
+        problems.join("\n")
+    );
+}
+
+/// A line-by-line comparison of the two snapshots, showing only the lines
+/// that differ.
+fn diff(recorded: &str, actual: &str) -> String {
+    let recorded: Vec<_> = recorded.lines().collect();
+    let actual: Vec<_> = actual.lines().collect();
+    let mut diff = String::new();
+    for i in 0..recorded.len().max(actual.len()) {
+        let (old, new) = (recorded.get(i), actual.get(i));
+        if old != new {
+            if let Some(old) = old {
+                writeln!(diff, "  line {}: - {}", i + 1, old).unwrap();
+            }
+            if let Some(new) = new {
+                writeln!(diff, "  line {}: + {}", i + 1, new).unwrap();
+            }
+        }
+    }
+    diff
+}